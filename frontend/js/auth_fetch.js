@@ -0,0 +1,59 @@
+// Access tokens only last 15 minutes, so requests that come back 401 get a
+// new token pair with the refresh token and are tried once more
+
+// Refresh tokens are single use, so requests that fail at the same time wait
+// on the same refresh
+let refreshing = null;
+
+function refreshTokens() {
+  if (refreshing === null) {
+    refreshing = fetch("http://localhost:7878/sessions/refresh", {
+      method: "POST",
+      headers: {
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify({
+        refresh_token: sessionStorage.getItem("refresh_token"),
+      }),
+    })
+      .then((response) => {
+        if (!response.ok) {
+          throw new Error("status: " + response.status);
+        }
+        return response.json();
+      })
+      .then((data) => {
+        sessionStorage.setItem("token", data.access_token);
+        sessionStorage.setItem("refresh_token", data.refresh_token);
+      })
+      .finally(() => {
+        refreshing = null;
+      });
+  }
+  return refreshing;
+}
+
+async function authFetch(url, options) {
+  const withToken = () => ({
+    ...options,
+    headers: {
+      ...options.headers,
+      Authorization: "Bearer " + sessionStorage.getItem("token"),
+    },
+  });
+
+  const response = await fetch(url, withToken());
+  if (response.status !== 401) {
+    return response;
+  }
+
+  try {
+    await refreshTokens();
+  } catch (error) {
+    // The session is over, so log in again
+    console.error("Refresh failed:", error);
+    window.location.replace("/index.html");
+    return response;
+  }
+  return fetch(url, withToken());
+}
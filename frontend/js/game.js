@@ -1,5 +1,20 @@
 const tileSize = 16;
 
+// Drawn for tiles without an image. Keep in step with `tiles::TILES` on the
+// server.
+const tileColours = {
+  B: "#8b5a2b", // bridge
+  D: "#1a3d7c", // deep water
+  F: "#1f6b2a", // forest
+  G: "#5fa83a", // grass
+  L: "#d4421c", // lava
+  R: "#7d7d7d", // rock
+  S: "#e0cc8a", // sand
+  W: "#3a7bd5", // water
+};
+// For anything the server sends that isn't known yet
+const unknownTileColour = "#ff00ff";
+
 // Setup
 const username = sessionStorage.getItem("username");
 const create_game_rsp = JSON.parse(localStorage.getItem("initialGamestate"));
@@ -67,13 +82,18 @@ function drawTerrain(board, gameImages) {
     for (let col = 0; col < board[row].length; col++) {
       const tileType = board[row][col];
       const tileImage = gameImages["tile_" + tileType];
-      context.drawImage(
-        tileImage,
-        col * tileSize,
-        row * tileSize,
-        tileSize,
-        tileSize
-      );
+      if (tileImage) {
+        context.drawImage(
+          tileImage,
+          col * tileSize,
+          row * tileSize,
+          tileSize,
+          tileSize
+        );
+      } else {
+        context.fillStyle = tileColours[tileType] || unknownTileColour;
+        context.fillRect(col * tileSize, row * tileSize, tileSize, tileSize);
+      }
     }
   }
 }
@@ -93,8 +113,6 @@ function drawUser(userCoord, topLeftCoord, gameImages) {
 }
 
 async function getGamestate() {
-  const token = sessionStorage.getItem("token");
-
   // This feels wrong, as getting user coord from the old gamestate!
   // This will break when go off edge of map.
   // Maybe want to store a local usercoord?
@@ -103,7 +121,7 @@ async function getGamestate() {
 
   console.log("Found user coord is :" + user_coord.x + ", " + user_coord.y);
 
-  const response = await fetch(
+  const response = await authFetch(
     "http://localhost:7878/rrr-game/" +
       game_id +
       "?x=" +
@@ -114,7 +132,6 @@ async function getGamestate() {
       method: "GET",
       headers: {
         "Content-Type": "application/json",
-        Authorization: "Bearer " + token,
       },
     }
   );
@@ -4,13 +4,10 @@ function joinGame(event) {
   event.preventDefault();
   const game_id = document.getElementById("joinGameId").value;
 
-  const token = sessionStorage.getItem("token");
-
-  fetch("http://localhost:7878/rrr-game/" + game_id + "/players", {
+  authFetch("http://localhost:7878/rrr-game/" + game_id + "/players", {
     method: "POST",
     headers: {
       "Content-Type": "application/json",
-      Authorization: "Bearer " + token,
     },
     body: "",
   })
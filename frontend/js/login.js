@@ -32,6 +32,7 @@ function doLogin(event) {
     .then((data) => {
       console.log(data);
       sessionStorage.setItem("token", data.access_token);
+      sessionStorage.setItem("refresh_token", data.refresh_token);
       sessionStorage.setItem("username", username);
       window.location.replace("/menu.html");
     })
@@ -0,0 +1,52 @@
+use crate::{
+    http::{HttpError, HttpErrorCode},
+    jwt::Claims,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Role {
+    Player,
+    Admin,
+}
+
+impl Claims {
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+}
+
+// Only the user themselves or an admin can access things owned by a user.
+pub fn check_owner_or_admin(claims: &Claims, owner: &str) -> Result<(), HttpError> {
+    if claims.sub == owner || claims.has_role(Role::Admin) {
+        Ok(())
+    } else {
+        Err(HttpError {
+            code: HttpErrorCode::Error403Forbidden,
+            message: "You are not authorized to access this resource.".to_string(),
+        })
+    }
+}
+
+// For things even an admin shouldn't do on someone's behalf
+pub fn check_owner(claims: &Claims, owner: &str) -> Result<(), HttpError> {
+    if claims.sub == owner {
+        Ok(())
+    } else {
+        Err(HttpError {
+            code: HttpErrorCode::Error403Forbidden,
+            message: "Only the user themselves can do this.".to_string(),
+        })
+    }
+}
+
+pub fn check_role(claims: &Claims, role: Role) -> Result<(), HttpError> {
+    if claims.has_role(role) {
+        Ok(())
+    } else {
+        Err(HttpError {
+            code: HttpErrorCode::Error403Forbidden,
+            message: format!("You need the {:?} role to do this.", role),
+        })
+    }
+}
@@ -0,0 +1,151 @@
+use crate::{rate_limit::DEFAULT_ROUTE, RateLimit};
+use jsonwebtoken::Algorithm;
+use std::{collections::HashMap, env, path::PathBuf, str::FromStr, time::Duration};
+
+const DEFAULT_JWT_KEYS_FILE: &str = "jwt_keys.json";
+const DEFAULT_OUTBOX_DIR: &str = "outbox";
+// The same as the client polls at
+const DEFAULT_TICK_MS: u64 = 250;
+
+// Server settings, read from the environment on start up:
+// - RRR_JWT_KEYS_FILE: where the signing keys are stored, generated on first start
+// - RRR_JWT_ALG: algorithm for newly generated keys, HS512 (default) or EdDSA
+// - RRR_ADMINS: comma separated usernames that are promoted to admin at
+//   startup. They need to have signed up already.
+// - RRR_OUTBOX_DIR: where mail to users is written, there's no mail server yet
+// - RRR_REQUIRE_VERIFIED_EMAIL: if "true", users must verify their email before
+//   they can create a game
+// - RRR_RATE_LIMITS: comma separated `route=capacity:refill_per_s` overriding
+//   the default limits, e.g. `POST /rrr-game/{id}/actions=20:5`. The route
+//   `*` is used for routes without their own limit.
+// - RRR_TICK_MS: how often games are moved on a tick, in milliseconds
+#[derive(Clone)]
+pub struct Config {
+    pub jwt_keys_file: PathBuf,
+    pub jwt_alg: Algorithm,
+    pub admins: Vec<String>,
+    pub outbox_dir: PathBuf,
+    pub require_verified_email: bool,
+    pub rate_limits: HashMap<String, RateLimit>,
+    pub tick_interval: Duration,
+}
+impl Config {
+    pub fn from_env() -> Config {
+        let default = Config::default();
+
+        let jwt_keys_file = env::var("RRR_JWT_KEYS_FILE")
+            .map(PathBuf::from)
+            .unwrap_or(default.jwt_keys_file);
+        let jwt_alg = env::var("RRR_JWT_ALG")
+            .map(|alg| Algorithm::from_str(&alg).expect("Invalid RRR_JWT_ALG"))
+            .unwrap_or(default.jwt_alg);
+        let admins = env::var("RRR_ADMINS")
+            .map(|admins| {
+                admins
+                    .split(',')
+                    .map(|admin| admin.trim().to_string())
+                    .filter(|admin| !admin.is_empty())
+                    .collect()
+            })
+            .unwrap_or(default.admins);
+        let outbox_dir = env::var("RRR_OUTBOX_DIR")
+            .map(PathBuf::from)
+            .unwrap_or(default.outbox_dir);
+        let require_verified_email = env::var("RRR_REQUIRE_VERIFIED_EMAIL")
+            .map(|require| {
+                require
+                    .parse()
+                    .expect("Invalid RRR_REQUIRE_VERIFIED_EMAIL, must be true or false")
+            })
+            .unwrap_or(default.require_verified_email);
+        let tick_interval = env::var("RRR_TICK_MS")
+            .map(|tick_ms| {
+                let tick_ms = tick_ms.parse().expect("Invalid RRR_TICK_MS");
+                assert!(tick_ms > 0, "Invalid RRR_TICK_MS, must be more than 0");
+                Duration::from_millis(tick_ms)
+            })
+            .unwrap_or(default.tick_interval);
+        let mut rate_limits = default.rate_limits;
+        if let Ok(raw) = env::var("RRR_RATE_LIMITS") {
+            rate_limits.extend(parse_rate_limits(&raw).expect("Invalid RRR_RATE_LIMITS"));
+        }
+
+        Config {
+            jwt_keys_file,
+            jwt_alg,
+            admins,
+            outbox_dir,
+            require_verified_email,
+            rate_limits,
+            tick_interval,
+        }
+    }
+}
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            jwt_keys_file: PathBuf::from(DEFAULT_JWT_KEYS_FILE),
+            jwt_alg: Algorithm::HS512,
+            admins: vec![],
+            outbox_dir: PathBuf::from(DEFAULT_OUTBOX_DIR),
+            require_verified_email: false,
+            rate_limits: default_rate_limits(),
+            tick_interval: Duration::from_millis(DEFAULT_TICK_MS),
+        }
+    }
+}
+
+fn default_rate_limits() -> HashMap<String, RateLimit> {
+    let limit = |capacity, refill_per_s| RateLimit {
+        capacity,
+        refill_per_s,
+    };
+    HashMap::from([
+        (DEFAULT_ROUTE.to_string(), limit(120.0, 20.0)),
+        // Sign ups and logins hash passwords, which is slow on purpose
+        ("POST /users".to_string(), limit(5.0, 1.0 / 60.0)),
+        ("POST /sessions".to_string(), limit(10.0, 1.0 / 6.0)),
+        ("POST /password-resets".to_string(), limit(3.0, 1.0 / 60.0)),
+        ("POST /rrr-game/{id}/actions".to_string(), limit(20.0, 5.0)),
+    ])
+}
+
+fn parse_rate_limits(raw: &str) -> Result<HashMap<String, RateLimit>, String> {
+    raw.split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let invalid = || format!("Invalid rate limit {:?}", entry);
+            let (route, limit) = entry.trim().rsplit_once('=').ok_or_else(invalid)?;
+            let (capacity, refill_per_s) = limit.split_once(':').ok_or_else(invalid)?;
+            let capacity: f64 = capacity.parse().map_err(|_| invalid())?;
+            let refill_per_s: f64 = refill_per_s.parse().map_err(|_| invalid())?;
+            if capacity < 1.0 || refill_per_s <= 0.0 {
+                return Err(invalid());
+            }
+            Ok((
+                route.to_string(),
+                RateLimit {
+                    capacity,
+                    refill_per_s,
+                },
+            ))
+        })
+        .collect()
+}
+
+#[test]
+fn test_parse_rate_limits() {
+    let rate_limits = parse_rate_limits("POST /users=5:0.5, *=100:10").unwrap();
+    assert_eq!(
+        rate_limits.get("POST /users"),
+        Some(&RateLimit {
+            capacity: 5.0,
+            refill_per_s: 0.5
+        })
+    );
+    assert_eq!(rate_limits.get("*").unwrap().capacity, 100.0);
+
+    assert!(parse_rate_limits("POST /users=5").is_err());
+    assert!(parse_rate_limits("POST /users=five:1").is_err());
+    assert!(parse_rate_limits("POST /users=5:0").is_err());
+}
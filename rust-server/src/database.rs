@@ -5,6 +5,12 @@ pub trait Database {
     fn get(&self, key: &str) -> Option<String>;
     fn set(&self, key: String, value: String);
     fn del(&self, key: &str);
+    // Reads and writes the value in one go, so nothing else can change it in
+    // between. Gets None if the key isn't set, and returning None leaves it
+    // unset.
+    fn update(&self, key: &str, f: &mut dyn FnMut(Option<String>) -> Option<String>);
+    // All the keys starting with the prefix
+    fn keys(&self, prefix: &str) -> Vec<String>;
 }
 
 pub struct LocalDatabase {
@@ -34,6 +40,21 @@ impl Database for LocalDatabase {
         let mut map = self.map.lock().unwrap();
         map.remove(key);
     }
+
+    fn update(&self, key: &str, f: &mut dyn FnMut(Option<String>) -> Option<String>) {
+        let mut map = self.map.lock().unwrap();
+        if let Some(value) = f(map.remove(key)) {
+            map.insert(key.to_string(), value);
+        }
+    }
+
+    fn keys(&self, prefix: &str) -> Vec<String> {
+        let map = self.map.lock().unwrap();
+        map.keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
 }
 
 impl Default for LocalDatabase {
@@ -0,0 +1,130 @@
+use crate::{
+    http::{HttpError, HttpErrorCode},
+    tokens, users, Database, Mail, Mailer,
+};
+use jsonwebtoken::get_current_timestamp;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const EMAIL_VERIFICATION_NAME: &str = "email-verification";
+
+const VERIFICATION_TOKEN_TIMEOUT_S: u64 = 86400;
+
+// Stored under the hash of the token that was mailed to the user. The email is
+// kept so a token for an old address can't verify a new one.
+#[derive(Serialize, Deserialize)]
+struct EmailVerificationEntry {
+    username: String,
+    email: String,
+    exp: u64,
+}
+
+fn email_verification_key(verification_token: &str) -> String {
+    EMAIL_VERIFICATION_NAME.to_string() + ":" + &tokens::hash_token(verification_token)
+}
+
+// E.g. when the user is deleted, so the tokens don't verify whoever signs up
+// with the name next
+pub fn remove_user_verifications(username: &str, db: &Arc<impl Database>) {
+    for key in db.keys(&(EMAIL_VERIFICATION_NAME.to_string() + ":")) {
+        let for_user = db
+            .get(&key)
+            .and_then(|entry| serde_json::from_str::<EmailVerificationEntry>(&entry).ok())
+            .is_some_and(|entry| entry.username == username);
+        if for_user {
+            db.del(&key);
+        }
+    }
+}
+
+// Mail the user a token to prove the address is theirs. Failing to send isn't
+// an error for the caller, the user can ask for another one.
+pub fn send_verification(username: &str, email: &str, db: Arc<impl Database>, mailer: &dyn Mailer) {
+    let verification_token = tokens::generate_token(48);
+    let entry = EmailVerificationEntry {
+        username: username.to_owned(),
+        email: email.to_owned(),
+        exp: get_current_timestamp() + VERIFICATION_TOKEN_TIMEOUT_S,
+    };
+    db.set(
+        email_verification_key(&verification_token),
+        serde_json::to_string(&entry).unwrap(),
+    );
+
+    let mail = Mail {
+        to: email.to_owned(),
+        subject: "Verify your email".to_string(),
+        body: format!(
+            "Welcome {}! Use this code to verify your email address, it expires in {} hours:\
+             \n\n{}\n\nIf you didn't sign up, you can ignore this.",
+            username,
+            VERIFICATION_TOKEN_TIMEOUT_S / 3600,
+            verification_token
+        ),
+    };
+    if let Err(e) = mailer.send(mail) {
+        warn!(
+            "Failed to send email verification for {:?}: {}",
+            username, e
+        );
+    }
+}
+
+// Send a new token, e.g. if the first one expired
+pub fn resend_verification(
+    username: String,
+    db: Arc<impl Database>,
+    mailer: &dyn Mailer,
+) -> Result<String, HttpError> {
+    let (email, verified) = users::get_user_email(&username, Arc::clone(&db))?;
+    if verified {
+        return Err(HttpError {
+            code: HttpErrorCode::Error409Conflict,
+            message: "Email is already verified.".to_string(),
+        });
+    }
+
+    send_verification(&username, &email, db, mailer);
+    Ok("".to_string())
+}
+
+#[derive(Serialize, Deserialize)]
+struct ConfirmVerificationRq {
+    token: String,
+}
+
+pub fn confirm_verification(body: String, db: Arc<impl Database>) -> Result<String, HttpError> {
+    let body: ConfirmVerificationRq = if let Ok(valid_body) = serde_json::from_str(&body) {
+        valid_body
+    } else {
+        return Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: "Confirm email verification request body has invalid format.".to_string(),
+        });
+    };
+
+    let invalid_token_error = HttpError {
+        code: HttpErrorCode::Error401Unauthorized,
+        message: "Verification token is invalid or expired.".to_string(),
+    };
+
+    // Tokens are single use
+    let key = email_verification_key(&body.token);
+    let entry: EmailVerificationEntry = match db.get(&key) {
+        Some(entry) => serde_json::from_str(&entry).unwrap(),
+        None => return Err(invalid_token_error),
+    };
+    db.del(&key);
+    if entry.exp <= get_current_timestamp() {
+        return Err(invalid_token_error);
+    }
+
+    if !users::set_user_verified(&entry.username, &entry.email, db)? {
+        // The user has changed their email since the token was sent
+        return Err(invalid_token_error);
+    }
+
+    info!("Verified email for {:?}", entry.username);
+    Ok("".to_string())
+}
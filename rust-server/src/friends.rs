@@ -0,0 +1,180 @@
+use crate::{
+    http::{HttpError, HttpErrorCode},
+    users, Database,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, sync::Arc};
+
+const FRIENDS_NAME: &str = "friends";
+
+// Each user's side of their friendships. Both users' entries are always
+// updated together, so they agree with each other. Each side is changed in one
+// go, so two users answering each other at once can't undo one another.
+#[derive(Serialize, Deserialize, Default)]
+struct FriendsEntry {
+    friends: BTreeSet<String>,
+    // Requests other users have sent this user
+    incoming: BTreeSet<String>,
+    // Requests this user has sent, waiting to be accepted
+    outgoing: BTreeSet<String>,
+}
+
+fn friends_key(username: &str) -> String {
+    FRIENDS_NAME.to_string() + ":" + username
+}
+
+fn get_friends_raw(username: &str, db: &Arc<impl Database>) -> FriendsEntry {
+    match db.get(&friends_key(username)) {
+        Some(friends) => serde_json::from_str(&friends).unwrap(),
+        None => FriendsEntry::default(),
+    }
+}
+
+// Returns what `f` returns
+fn update_friends_raw<T>(
+    username: &str,
+    db: &Arc<impl Database>,
+    mut f: impl FnMut(&mut FriendsEntry) -> T,
+) -> T {
+    let mut result = None;
+    db.update(&friends_key(username), &mut |friends| {
+        let mut friends: FriendsEntry = match friends {
+            Some(friends) => serde_json::from_str(&friends).unwrap(),
+            None => FriendsEntry::default(),
+        };
+        result = Some(f(&mut friends));
+        Some(serde_json::to_string(&friends).unwrap())
+    });
+    result.unwrap()
+}
+
+// Returns whether there was anything to remove
+fn unfriend(friends: &mut FriendsEntry, other: &str) -> bool {
+    friends.friends.remove(other) | friends.outgoing.remove(other) | friends.incoming.remove(other)
+}
+
+// Makes them friends on the user's side
+fn befriend(friends: &mut FriendsEntry, other: &str) {
+    friends.incoming.remove(other);
+    friends.outgoing.remove(other);
+    friends.friends.insert(other.to_string());
+}
+
+pub fn get_friends(username: String, db: Arc<impl Database>) -> Result<String, HttpError> {
+    let friends = get_friends_raw(&username, &db);
+    Ok(serde_json::to_string(&friends).unwrap())
+}
+
+pub fn get_friend_list(username: &str, db: &Arc<impl Database>) -> BTreeSet<String> {
+    get_friends_raw(username, db).friends
+}
+
+pub fn are_friends(username: &str, other: &str, db: &Arc<impl Database>) -> bool {
+    get_friends_raw(username, db).friends.contains(other)
+}
+
+#[derive(Serialize, Deserialize)]
+struct FriendRq {
+    username: String,
+}
+
+fn parse_friend_rq(body: &str) -> Result<String, HttpError> {
+    let body: FriendRq = if let Ok(valid_body) = serde_json::from_str(body) {
+        valid_body
+    } else {
+        return Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: "Friend request body has invalid format.".to_string(),
+        });
+    };
+    Ok(users::canonical_username(&body.username))
+}
+
+// Sends a friend request, or accepts one if the other user already sent one
+pub fn add_friend(
+    username: String,
+    body: String,
+    db: Arc<impl Database>,
+) -> Result<String, HttpError> {
+    let other = parse_friend_rq(&body)?;
+    if other == username {
+        return Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: "You can't be friends with yourself.".to_string(),
+        });
+    }
+    // Checks the other user exists
+    users::get_user_roles(&other, Arc::clone(&db))?;
+
+    // Returns whether it accepted their request
+    let accepting = update_friends_raw(&username, &db, |friends| {
+        if friends.friends.contains(&other) || friends.outgoing.contains(&other) {
+            return Err(HttpError {
+                code: HttpErrorCode::Error409Conflict,
+                message: "Already friends, or a request has already been sent.".to_string(),
+            });
+        }
+        if friends.incoming.contains(&other) {
+            befriend(friends, &other);
+            Ok(true)
+        } else {
+            friends.outgoing.insert(other.clone());
+            Ok(false)
+        }
+    })?;
+
+    // If they sent a request at the same time, that's them accepting too
+    let crossed = update_friends_raw(&other, &db, |other_friends| {
+        let crossed =
+            other_friends.outgoing.contains(&username) || other_friends.friends.contains(&username);
+        if accepting || crossed {
+            befriend(other_friends, &username);
+        } else {
+            other_friends.incoming.insert(username.clone());
+        }
+        crossed
+    });
+    if crossed {
+        update_friends_raw(&username, &db, |friends| befriend(friends, &other));
+    }
+
+    get_friends(username, db)
+}
+
+// Unfriends, cancels a sent request or declines a received one
+pub fn remove_friend(
+    username: String,
+    body: String,
+    db: Arc<impl Database>,
+) -> Result<String, HttpError> {
+    let other = parse_friend_rq(&body)?;
+
+    let removed = update_friends_raw(&username, &db, |friends| unfriend(friends, &other));
+    if !removed {
+        return Err(HttpError {
+            code: HttpErrorCode::Error404NotFround,
+            message: "No friend or friend request with that user.".to_string(),
+        });
+    }
+    update_friends_raw(&other, &db, |other_friends| {
+        unfriend(other_friends, &username)
+    });
+
+    get_friends(username, db)
+}
+
+// Called when a user is deleted, so they don't linger in anyone's list
+pub fn remove_user(username: &str, db: Arc<impl Database>) {
+    let friends = get_friends_raw(username, &db);
+    for other in friends
+        .friends
+        .iter()
+        .chain(&friends.incoming)
+        .chain(&friends.outgoing)
+    {
+        update_friends_raw(other, &db, |other_friends| {
+            unfriend(other_friends, username)
+        });
+    }
+    db.del(&friends_key(username));
+}
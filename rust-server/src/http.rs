@@ -4,18 +4,21 @@ use std::collections::HashMap;
 
 use log::{debug, info};
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq)]
 pub enum HttpMethod {
     GET,
     POST,
+    PATCH,
     DELETE,
     OPTIONS,
 }
 impl HttpMethod {
-    fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &'static str {
         match self {
             HttpMethod::GET => "GET",
             HttpMethod::POST => "POST",
+            HttpMethod::PATCH => "PATCH",
             HttpMethod::OPTIONS => "OPTIONS",
             HttpMethod::DELETE => "DELETE",
         }
@@ -24,6 +27,7 @@ impl HttpMethod {
         match raw {
             "GET" => HttpMethod::GET,
             "POST" => HttpMethod::POST,
+            "PATCH" => HttpMethod::PATCH,
             "OPTIONS" => HttpMethod::OPTIONS,
             "DELETE" => HttpMethod::DELETE,
             _ => panic!(
@@ -34,44 +38,57 @@ impl HttpMethod {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum HttpErrorCode {
     Error400BadRequest,
+    // Bad request where the body failed validation, lists what was wrong
+    Error400InvalidFields(Vec<FieldError>),
     Error401Unauthorized,
     Error403Forbidden,
     Error404NotFround,
     Error409Conflict,
+    // Seconds until the client can try again, sent as the Retry-After header
+    Error429TooManyRequests(u64),
     Error500InternalServerError,
     Error501NotImplemented,
     Error503ServiceUnavailable,
 }
 
 pub fn get_response(response_body: Result<Response, HttpError>) -> Response {
-    let response = match response_body {
+    match response_body {
         Ok(response) => response,
         Err(error) => {
-            let error_body = error_body(error.message);
             let error_status = match error.code {
-                HttpErrorCode::Error400BadRequest => "400 Bad request".to_string(),
+                HttpErrorCode::Error400BadRequest | HttpErrorCode::Error400InvalidFields(_) => {
+                    "400 Bad request".to_string()
+                }
                 HttpErrorCode::Error401Unauthorized => "401 Unauthorized".to_string(),
                 HttpErrorCode::Error403Forbidden => "403 Forbidden".to_string(),
                 HttpErrorCode::Error404NotFround => "404 Not found".to_string(),
                 HttpErrorCode::Error409Conflict => "409 Conflict".to_string(),
+                HttpErrorCode::Error429TooManyRequests(_) => "429 Too many requests".to_string(),
                 HttpErrorCode::Error501NotImplemented => "501 Not implemented".to_string(),
                 HttpErrorCode::Error503ServiceUnavailable => "503 Service unavailable".to_string(),
                 HttpErrorCode::Error500InternalServerError => {
                     "500 Internal Server Error".to_string()
                 }
             };
+            let mut headers = HashMap::new();
+            if let HttpErrorCode::Error429TooManyRequests(retry_after_s) = error.code {
+                headers.insert("Retry-After".to_string(), retry_after_s.to_string());
+            }
+            let field_errors = match error.code {
+                HttpErrorCode::Error400InvalidFields(field_errors) => field_errors,
+                _ => vec![],
+            };
+            let error_body = error_body(error.message, field_errors);
             Response {
                 body: error_body,
                 status: error_status,
-                headers: HashMap::new(),
+                headers,
             }
         }
-    };
-
-    response
+    }
 }
 
 pub fn build_options_response_headers(allowed_methods: Vec<HttpMethod>) -> HashMap<String, String> {
@@ -89,17 +106,28 @@ pub fn build_options_response_headers(allowed_methods: Vec<HttpMethod>) -> HashM
     headers
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
 #[derive(Serialize, Deserialize)]
 struct ErrorMsg {
     error_message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    field_errors: Vec<FieldError>,
 }
 
-fn error_body(error_message: String) -> String {
-    let error_body = ErrorMsg { error_message };
+fn error_body(error_message: String, field_errors: Vec<FieldError>) -> String {
+    let error_body = ErrorMsg {
+        error_message,
+        field_errors,
+    };
     serde_json::to_string(&error_body).unwrap()
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct HttpError {
     pub code: HttpErrorCode,
     pub message: String,
@@ -153,7 +181,7 @@ impl Request {
         }
 
         // Parse request line
-        let re = Regex::new(r"(?<method>GET|POST|DELETE|OPTIONS) (?<resource>/[a-z-]*)(?<id>/[a-zA-Z0-9]+)?(?<sub_resource>/[a-z0-9]+)?(?<parameters>\?[a-z-0-9=&]+)? HTTP/1.1").unwrap();
+        let re = Regex::new(r"(?<method>GET|POST|PATCH|DELETE|OPTIONS) (?<resource>/[a-z-]*)(?<id>/[a-zA-Z0-9]+)?(?<sub_resource>/[a-z0-9]+)?(?<parameters>\?[a-zA-Z0-9=&-]+)? HTTP/1.1").unwrap();
         let cap = re.captures_iter(&request_line).last();
 
         if let Some(valid_request) = cap {
@@ -1,48 +1,353 @@
 use serde::{Deserialize, Serialize};
+use std::{fs, io::Write, path::Path};
 
+use base64::{engine::general_purpose::STANDARD, Engine};
 use jsonwebtoken::{
-    decode, encode, get_current_timestamp, Algorithm, DecodingKey, EncodingKey, Validation,
+    decode, decode_header, encode, get_current_timestamp, Algorithm, DecodingKey, EncodingKey,
+    Header, Validation,
+};
+use log::{trace, warn};
+use rand::{distributions::Alphanumeric, Rng};
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
 };
-use log::trace;
 
-use crate::http::{HttpError, HttpErrorCode};
+use crate::{
+    auth::Role,
+    http::{HttpError, HttpErrorCode},
+    sessions, Database,
+};
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 #[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    exp: u64,
+pub struct Claims {
+    pub sub: String,
+    pub exp: u64,
+    // Unique token ID, used to revoke a token on logout
+    pub jti: String,
+    #[serde(default)]
+    pub roles: Vec<Role>,
+    // When it was made, in ms since the epoch, so all of a user's tokens from
+    // before a point can be denied. 0 for tokens from before this was added.
+    #[serde(default)]
+    pub iat_ms: u128,
+}
+
+// Access tokens are short lived, clients use a refresh token to get a new one
+pub const ACCESS_TOKEN_TIMEOUT_S: u64 = 15 * 60;
+
+const HMAC_SECRET_LENGTH: usize = 64;
+
+// A single key used to sign tokens. The key material is base64 encoded:
+// - HS512: `private_key` is the shared secret, there is no `public_key`.
+// - EdDSA: `private_key` is a PKCS#8 document, `public_key` the raw public key.
+// - RS256: `private_key` and `public_key` are PKCS#1 DER. These can't be
+//   generated by the server, so have to be added to the keys file by hand.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SigningKey {
+    kid: String,
+    alg: Algorithm,
+    private_key: String,
+    public_key: Option<String>,
+    // Set once the key has been rotated out. Retired keys are only kept
+    // around to validate tokens that were signed before the rotation.
+    #[serde(default)]
+    retired_at: Option<u64>,
+}
+impl SigningKey {
+    fn generate(alg: Algorithm) -> Result<SigningKey, String> {
+        let (private_key, public_key) = match alg {
+            Algorithm::HS512 => {
+                let secret: Vec<u8> = (0..HMAC_SECRET_LENGTH)
+                    .map(|_| rand::thread_rng().gen())
+                    .collect();
+                (secret, None)
+            }
+            Algorithm::EdDSA => {
+                let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+                    .map_err(|_| "Failed to generate EdDSA key".to_string())?;
+                let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+                    .map_err(|_| "Failed to parse generated EdDSA key".to_string())?;
+                (
+                    pkcs8.as_ref().to_vec(),
+                    Some(key_pair.public_key().as_ref().to_vec()),
+                )
+            }
+            _ => return Err(format!("Can't generate keys for algorithm {:?}", alg)),
+        };
+
+        Ok(SigningKey {
+            kid: generate_id(8),
+            alg,
+            private_key: STANDARD.encode(private_key),
+            public_key: public_key.map(|key| STANDARD.encode(key)),
+            retired_at: None,
+        })
+    }
+
+    fn encoding_key(&self) -> EncodingKey {
+        // Key material is checked when the key ring is loaded
+        let private_key = STANDARD.decode(&self.private_key).unwrap();
+        match self.alg {
+            Algorithm::EdDSA => EncodingKey::from_ed_der(&private_key),
+            Algorithm::RS256 => EncodingKey::from_rsa_der(&private_key),
+            _ => EncodingKey::from_secret(&private_key),
+        }
+    }
+
+    fn decoding_key(&self) -> DecodingKey {
+        match self.alg {
+            Algorithm::EdDSA => DecodingKey::from_ed_der(
+                &STANDARD.decode(self.public_key.as_ref().unwrap()).unwrap(),
+            ),
+            Algorithm::RS256 => DecodingKey::from_rsa_der(
+                &STANDARD.decode(self.public_key.as_ref().unwrap()).unwrap(),
+            ),
+            _ => DecodingKey::from_secret(&STANDARD.decode(&self.private_key).unwrap()),
+        }
+    }
+
+    fn check(&self) -> Result<(), String> {
+        match self.alg {
+            Algorithm::HS512 | Algorithm::EdDSA | Algorithm::RS256 => (),
+            _ => return Err(format!("Key {} has unsupported algorithm", self.kid)),
+        }
+        if STANDARD.decode(&self.private_key).is_err() {
+            return Err(format!("Key {} has an invalid private key", self.kid));
+        }
+        if self.alg != Algorithm::HS512 {
+            match &self.public_key {
+                Some(public_key) if STANDARD.decode(public_key).is_ok() => (),
+                _ => return Err(format!("Key {} has an invalid public key", self.kid)),
+            }
+        }
+        Ok(())
+    }
 }
 
-const ONE_DAY_TIMEOUT_S: u64 = 86400;
+fn generate_id(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+#[derive(Serialize)]
+pub struct PublicKey {
+    kid: String,
+    alg: Algorithm,
+    public_key: String,
+}
+
+// All the keys the server knows about. New tokens are always signed with the
+// active key, but any key in the ring can be used to validate a token, which
+// is what allows keys to be rotated without logging everyone out.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct KeyRing {
+    active_kid: String,
+    keys: Vec<SigningKey>,
+}
+impl KeyRing {
+    pub fn generate(alg: Algorithm) -> Result<KeyRing, String> {
+        let key = SigningKey::generate(alg)?;
+        Ok(KeyRing {
+            active_kid: key.kid.clone(),
+            keys: vec![key],
+        })
+    }
+
+    // Keys are only generated on the first start, after that they are loaded
+    // from the keys file so tokens survive a restart.
+    pub fn load_or_generate(path: &Path, alg: Algorithm) -> Result<KeyRing, String> {
+        if path.exists() {
+            let raw = fs::read_to_string(path).map_err(|e| e.to_string())?;
+            let key_ring: KeyRing = serde_json::from_str(&raw).map_err(|e| e.to_string())?;
+            key_ring.check()?;
+            Ok(key_ring)
+        } else {
+            warn!("No JWT keys found at {:?}, generating new keys", path);
+            let key_ring = KeyRing::generate(alg)?;
+            key_ring.save(path)?;
+            Ok(key_ring)
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(path).map_err(|e| e.to_string())?;
+        file.write_all(serde_json::to_string_pretty(self).unwrap().as_bytes())
+            .map_err(|e| e.to_string())
+    }
+
+    // Make a new key the active one. The old keys are kept until every token
+    // they signed has expired.
+    pub fn rotate(&mut self, alg: Algorithm) -> Result<(), String> {
+        let now = get_current_timestamp();
+        let new_key = SigningKey::generate(alg)?;
+
+        for key in self.keys.iter_mut() {
+            if key.retired_at.is_none() {
+                key.retired_at = Some(now);
+            }
+        }
+        self.keys
+            .retain(|key| key.retired_at.unwrap() + ACCESS_TOKEN_TIMEOUT_S > now);
+
+        self.active_kid = new_key.kid.clone();
+        self.keys.push(new_key);
+        Ok(())
+    }
+
+    pub fn public_keys(&self) -> Vec<PublicKey> {
+        self.keys
+            .iter()
+            .filter_map(|key| {
+                key.public_key.as_ref().map(|public_key| PublicKey {
+                    kid: key.kid.clone(),
+                    alg: key.alg,
+                    public_key: public_key.clone(),
+                })
+            })
+            .collect()
+    }
 
-pub fn create_jwt(username: &str, secret: &String) -> String {
+    fn check(&self) -> Result<(), String> {
+        for key in &self.keys {
+            key.check()?;
+        }
+        if self.active_key().is_none() {
+            return Err(format!(
+                "Active key {} is not in the key ring",
+                self.active_kid
+            ));
+        }
+        Ok(())
+    }
+
+    fn active_key(&self) -> Option<&SigningKey> {
+        self.get_key(&self.active_kid)
+    }
+
+    fn get_key(&self, kid: &str) -> Option<&SigningKey> {
+        self.keys.iter().find(|key| key.kid == kid)
+    }
+}
+
+#[derive(Serialize)]
+struct PublicKeysRsp {
+    keys: Vec<PublicKey>,
+}
+
+pub fn get_public_keys(keys: &KeyRing) -> Result<String, HttpError> {
+    let rsp = PublicKeysRsp {
+        keys: keys.public_keys(),
+    };
+    Ok(serde_json::to_string(&rsp).unwrap())
+}
+
+pub fn create_jwt(username: &str, roles: &[Role], keys: &KeyRing) -> String {
     let my_claims = Claims {
         sub: username.to_owned(),
-        exp: get_current_timestamp() + ONE_DAY_TIMEOUT_S,
+        exp: get_current_timestamp() + ACCESS_TOKEN_TIMEOUT_S,
+        jti: generate_id(16),
+        roles: roles.to_vec(),
+        iat_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis(),
     };
 
-    encode(
-        &jsonwebtoken::Header::new(Algorithm::HS512),
-        &my_claims,
-        &EncodingKey::from_secret(secret.as_bytes()),
-    )
-    .unwrap()
+    let key = keys.active_key().unwrap();
+    let mut header = Header::new(key.alg);
+    header.kid = Some(key.kid.clone());
+
+    encode(&header, &my_claims, &key.encoding_key()).unwrap()
 }
 
-pub fn validate_jwt(token: &str, secret: &String) -> Result<String, HttpError> {
-    trace!("Secret {:?}", secret); //todo logging
+pub fn validate_jwt(
+    token: &str,
+    keys: &KeyRing,
+    db: Arc<impl Database>,
+) -> Result<Claims, HttpError> {
     trace!("token {:?}", token);
 
+    let decode_error = HttpError {
+        code: HttpErrorCode::Error401Unauthorized,
+        message: "Error decoding jwt token".to_string(),
+    };
+
+    // Find the key the token was signed with
+    let header = decode_header(token).map_err(|_| decode_error.clone())?;
+    let key = match header.kid.as_deref().and_then(|kid| keys.get_key(kid)) {
+        Some(key) => key,
+        None => return Err(decode_error),
+    };
+
     // Validate token
-    match decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::new(Algorithm::HS512),
-    ) {
-        Ok(c) => Ok(c.claims.sub),
-        Err(_) => Err(HttpError {
+    let claims = match decode::<Claims>(token, &key.decoding_key(), &Validation::new(key.alg)) {
+        Ok(c) => c.claims,
+        Err(_) => return Err(decode_error),
+    };
+
+    // Check the user hasn't logged out, or had every token taken away
+    if sessions::is_revoked(&claims.jti, Arc::clone(&db))
+        || sessions::issued_before_cutoff(&claims, &db)
+    {
+        return Err(HttpError {
             code: HttpErrorCode::Error401Unauthorized,
-            message: "Error decoding jwt token".to_string(),
-        }),
+            message: "Token has been revoked".to_string(),
+        });
     }
+
+    Ok(claims)
+}
+
+#[cfg(test)]
+fn validate_username(token: &str, keys: &KeyRing) -> Result<String, HttpError> {
+    let db = Arc::new(crate::LocalDatabase::new());
+    validate_jwt(token, keys, db).map(|claims| claims.sub)
+}
+
+#[test]
+fn test_jwt_round_trip() {
+    for alg in [Algorithm::HS512, Algorithm::EdDSA] {
+        let keys = KeyRing::generate(alg).unwrap();
+        let token = create_jwt("james", &[Role::Player], &keys);
+        assert_eq!(validate_username(&token, &keys).unwrap(), "james");
+
+        // Token from a different key ring is rejected
+        let other_keys = KeyRing::generate(alg).unwrap();
+        assert!(validate_username(&token, &other_keys).is_err());
+    }
+}
+
+#[test]
+fn test_jwt_key_rotation() {
+    let mut keys = KeyRing::generate(Algorithm::HS512).unwrap();
+    let old_token = create_jwt("james", &[Role::Player], &keys);
+
+    keys.rotate(Algorithm::EdDSA).unwrap();
+    let new_token = create_jwt("james", &[Role::Player], &keys);
+
+    // Both keys are still valid during the rotation
+    assert_eq!(validate_username(&old_token, &keys).unwrap(), "james");
+    assert_eq!(validate_username(&new_token, &keys).unwrap(), "james");
+    assert_eq!(keys.public_keys().len(), 1);
+
+    // Old key is dropped once it has been retired for long enough
+    keys.keys[0].retired_at = Some(get_current_timestamp() - ACCESS_TOKEN_TIMEOUT_S);
+    keys.rotate(Algorithm::EdDSA).unwrap();
+    assert!(validate_username(&old_token, &keys).is_err());
+    assert_eq!(validate_username(&new_token, &keys).unwrap(), "james");
 }
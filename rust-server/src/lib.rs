@@ -1,13 +1,40 @@
 mod threadpool;
 pub use threadpool::ThreadPool;
 
+mod scheduler;
+pub use scheduler::TickScheduler;
+
 mod routes;
-pub use routes::process_request;
+pub use routes::{process_request, process_request_from};
 
 mod database;
 pub use database::{Database, LocalDatabase};
 
-mod http;
+mod state;
+pub use state::ServerState;
+
+mod config;
+pub use config::Config;
+
 mod jwt;
-mod rrr_game;
+pub use jwt::KeyRing;
+
+mod rate_limit;
+pub use rate_limit::RateLimit;
+
+mod mail;
+pub use mail::{read_outbox, Mail, Mailer, OutboxMailer};
+
 mod users;
+pub use users::{migrate_user_keys, promote_admins};
+
+mod auth;
+mod email_verifications;
+mod friends;
+mod http;
+mod login_attempts;
+mod password_resets;
+mod rrr_game;
+mod sessions;
+mod tokens;
+mod validation;
@@ -0,0 +1,218 @@
+use crate::{
+    http::{HttpError, HttpErrorCode},
+    Database,
+};
+use serde::{Deserialize, Serialize};
+use std::{net::IpAddr, sync::Arc};
+
+const LOGIN_FAILURES_NAME: &str = "login-failures";
+
+// Failures allowed before the lockout kicks in. IPs get more, as a lot of
+// players can share one, e.g. behind a school's NAT.
+const USERNAME_FREE_ATTEMPTS: u32 = 5;
+const IP_FREE_ATTEMPTS: u32 = 20;
+
+// The lockout doubles with each failure past the free ones, up to the max
+const BASE_LOCKOUT_S: u64 = 30;
+const MAX_LOCKOUT_S: u64 = 60 * 60;
+
+// Failures are forgotten once there have been none for this long
+const FAILURE_WINDOW_S: u64 = 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Default)]
+struct FailedAttempts {
+    count: u32,
+    last_failure: u64,
+    locked_until: u64,
+}
+
+fn username_key(username: &str) -> String {
+    LOGIN_FAILURES_NAME.to_string() + ":user:" + username
+}
+
+fn ip_key(ip: &IpAddr) -> String {
+    LOGIN_FAILURES_NAME.to_string() + ":ip:" + &ip.to_string()
+}
+
+fn parse_attempts(raw: Option<String>, now: u64) -> FailedAttempts {
+    match raw {
+        Some(raw) => {
+            let attempts: FailedAttempts = serde_json::from_str(&raw).unwrap();
+            if attempts.last_failure + FAILURE_WINDOW_S <= now {
+                FailedAttempts::default()
+            } else {
+                attempts
+            }
+        }
+        None => FailedAttempts::default(),
+    }
+}
+
+fn get_attempts(key: &str, db: &Arc<impl Database>, now: u64) -> FailedAttempts {
+    parse_attempts(db.get(key), now)
+}
+
+// Counted in one update, so failed logins at the same time all count
+fn add_failure(key: String, free_attempts: u32, db: &Arc<impl Database>, now: u64) {
+    db.update(&key, &mut |raw| {
+        let mut attempts = parse_attempts(raw, now);
+        attempts.count += 1;
+        attempts.last_failure = now;
+        if attempts.count >= free_attempts {
+            let doublings = (attempts.count - free_attempts).min(32);
+            let lockout = BASE_LOCKOUT_S
+                .saturating_mul(1u64 << doublings)
+                .min(MAX_LOCKOUT_S);
+            attempts.locked_until = now + lockout;
+        }
+        Some(serde_json::to_string(&attempts).unwrap())
+    });
+}
+
+// Seconds until the username and the IP can both try again, if either is
+// locked out. Unknown usernames are counted too, so this doesn't give away
+// who has an account.
+fn lockout_remaining(username: &str, ip: &IpAddr, db: &Arc<impl Database>, now: u64) -> u64 {
+    let username_locked_until = get_attempts(&username_key(username), db, now).locked_until;
+    let ip_locked_until = get_attempts(&ip_key(ip), db, now).locked_until;
+    username_locked_until
+        .max(ip_locked_until)
+        .saturating_sub(now)
+}
+
+pub fn check_not_locked(
+    username: &str,
+    ip: &IpAddr,
+    db: &Arc<impl Database>,
+    now: u64,
+) -> Result<(), HttpError> {
+    match lockout_remaining(username, ip, db, now) {
+        0 => Ok(()),
+        remaining => Err(HttpError {
+            code: HttpErrorCode::Error429TooManyRequests(remaining),
+            message: format!(
+                "Too many failed login attempts, try again in {} seconds.",
+                remaining
+            ),
+        }),
+    }
+}
+
+pub fn record_failure(username: &str, ip: &IpAddr, db: &Arc<impl Database>, now: u64) {
+    add_failure(username_key(username), USERNAME_FREE_ATTEMPTS, db, now);
+    add_failure(ip_key(ip), IP_FREE_ATTEMPTS, db, now);
+}
+
+// Only the username is cleared. Otherwise an attacker could reset their IP's
+// count by logging in to their own account now and then.
+pub fn record_success(username: &str, db: &Arc<impl Database>) {
+    db.del(&username_key(username));
+}
+
+#[cfg(test)]
+const TEST_IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 1));
+
+#[test]
+fn test_username_lockout_backoff() {
+    let db = Arc::new(crate::LocalDatabase::new());
+    let now = 1_000_000;
+
+    for _ in 0..USERNAME_FREE_ATTEMPTS - 1 {
+        record_failure("james", &TEST_IP, &db, now);
+        assert!(check_not_locked("james", &TEST_IP, &db, now).is_ok());
+    }
+
+    // Locked out once the free attempts are used up
+    record_failure("james", &TEST_IP, &db, now);
+    assert_eq!(
+        lockout_remaining("james", &TEST_IP, &db, now),
+        BASE_LOCKOUT_S
+    );
+    assert!(check_not_locked("james", &TEST_IP, &db, now + BASE_LOCKOUT_S - 1).is_err());
+    assert!(check_not_locked("james", &TEST_IP, &db, now + BASE_LOCKOUT_S).is_ok());
+
+    // Other users aren't affected
+    assert!(check_not_locked("alex", &TEST_IP, &db, now).is_ok());
+
+    // Each failure after that doubles the lockout
+    let now = now + BASE_LOCKOUT_S;
+    record_failure("james", &TEST_IP, &db, now);
+    assert_eq!(
+        lockout_remaining("james", &TEST_IP, &db, now),
+        BASE_LOCKOUT_S * 2
+    );
+    record_failure("james", &TEST_IP, &db, now);
+    assert_eq!(
+        lockout_remaining("james", &TEST_IP, &db, now),
+        BASE_LOCKOUT_S * 4
+    );
+
+    // Up to a max
+    for _ in 0..40 {
+        record_failure("james", &TEST_IP, &db, now);
+    }
+    assert_eq!(
+        lockout_remaining("james", &TEST_IP, &db, now),
+        MAX_LOCKOUT_S
+    );
+
+    // Logging in clears it, the IP is locked out by now though
+    record_success("james", &db);
+    let other_ip: IpAddr = "10.0.0.2".parse().unwrap();
+    assert!(check_not_locked("james", &other_ip, &db, now).is_ok());
+}
+
+#[test]
+fn test_failures_are_forgotten() {
+    let db = Arc::new(crate::LocalDatabase::new());
+    let now = 1_000_000;
+
+    for _ in 0..USERNAME_FREE_ATTEMPTS - 1 {
+        record_failure("james", &TEST_IP, &db, now);
+    }
+
+    // A day later the count starts again
+    let now = now + FAILURE_WINDOW_S;
+    record_failure("james", &TEST_IP, &db, now);
+    assert!(check_not_locked("james", &TEST_IP, &db, now).is_ok());
+}
+
+#[test]
+fn test_ip_lockout() {
+    let db = Arc::new(crate::LocalDatabase::new());
+    let other_ip: IpAddr = "2001:db8::1".parse().unwrap();
+    let now = 1_000_000;
+
+    // Guessing across lots of usernames still locks out the IP
+    for attempt in 0..IP_FREE_ATTEMPTS {
+        let username = format!("user{}", attempt);
+        assert!(check_not_locked(&username, &TEST_IP, &db, now).is_ok());
+        record_failure(&username, &TEST_IP, &db, now);
+    }
+    assert!(check_not_locked("james", &TEST_IP, &db, now).is_err());
+    assert!(check_not_locked("james", &other_ip, &db, now).is_ok());
+
+    // Logging in doesn't clear the IP
+    record_success("james", &db);
+    assert!(check_not_locked("james", &TEST_IP, &db, now).is_err());
+    assert!(check_not_locked("james", &TEST_IP, &db, now + BASE_LOCKOUT_S).is_ok());
+}
+
+#[test]
+fn test_concurrent_failures_all_count() {
+    let db = Arc::new(crate::LocalDatabase::new());
+    let now = 1_000_000;
+
+    std::thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| {
+                for _ in 0..50 {
+                    record_failure("james", &TEST_IP, &db, now);
+                }
+            });
+        }
+    });
+
+    assert_eq!(get_attempts(&username_key("james"), &db, now).count, 400);
+    assert_eq!(get_attempts(&ip_key(&TEST_IP), &db, now).count, 400);
+}
@@ -0,0 +1,95 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::tokens;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// Anything that can deliver mail to users. There's no real mail server yet,
+// but an SMTP sender just needs to implement this.
+pub trait Mailer: Send + Sync {
+    fn send(&self, mail: Mail) -> Result<(), String>;
+}
+
+// Writes each mail to its own JSON file in a directory rather than sending it.
+// Good enough for running locally, and lets tests read what was sent.
+pub struct OutboxMailer {
+    dir: PathBuf,
+}
+impl OutboxMailer {
+    pub fn new(dir: &Path) -> OutboxMailer {
+        OutboxMailer {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    // Everything sent so far, oldest first
+    pub fn read_all(&self) -> Vec<Mail> {
+        read_outbox(&self.dir)
+    }
+}
+impl Mailer for OutboxMailer {
+    fn send(&self, mail: Mail) -> Result<(), String> {
+        fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+
+        // Name sorts by send time, the token just stops clashes
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let path = self.dir.join(format!(
+            "{:020}-{}.json",
+            timestamp,
+            tokens::generate_token(6)
+        ));
+
+        info!("Writing mail for {:?} to {:?}", mail.to, path);
+        fs::write(path, serde_json::to_string_pretty(&mail).unwrap()).map_err(|e| e.to_string())
+    }
+}
+
+pub fn read_outbox(dir: &Path) -> Vec<Mail> {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.map(|entry| entry.unwrap().path()).collect(),
+        Err(_) => return vec![],
+    };
+    paths.sort();
+
+    paths
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .filter_map(|raw| serde_json::from_str(&raw).ok())
+        .collect()
+}
+
+#[test]
+fn test_outbox_mailer() {
+    let dir = std::env::temp_dir().join(format!("rrr-outbox-{}", tokens::generate_token(8)));
+    let mailer = OutboxMailer::new(&dir);
+    assert!(mailer.read_all().is_empty());
+
+    let first = Mail {
+        to: "james@gmail.com".to_string(),
+        subject: "First".to_string(),
+        body: "Hello".to_string(),
+    };
+    let second = Mail {
+        subject: "Second".to_string(),
+        ..first.clone()
+    };
+    mailer.send(first.clone()).unwrap();
+    mailer.send(second.clone()).unwrap();
+
+    assert_eq!(mailer.read_all(), vec![first, second]);
+    fs::remove_dir_all(dir).unwrap();
+}
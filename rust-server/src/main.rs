@@ -1,7 +1,11 @@
 use log::warn;
-use rust_book_server_example::{process_request, Database, LocalDatabase, ThreadPool};
+use rust_book_server_example::{
+    migrate_user_keys, process_request_from, promote_admins, Config, Database, KeyRing,
+    LocalDatabase, OutboxMailer, ServerState, ThreadPool, TickScheduler,
+};
 use std::str;
 use std::{
+    env,
     io::prelude::*,
     net::{TcpListener, TcpStream},
     sync::Arc,
@@ -16,32 +20,64 @@ fn main() {
     let listener = TcpListener::bind(binding_address).unwrap();
     let pool = ThreadPool::new(4);
     let db = Arc::new(LocalDatabase::new());
+    let migrated = migrate_user_keys(Arc::clone(&db));
+    if migrated > 0 {
+        warn!("Migrated {:?} users into the user namespace", migrated);
+    }
+    let config = Config::from_env();
+    let promoted = promote_admins(&config.admins, Arc::clone(&db));
+    if promoted > 0 {
+        warn!("Promoted {:?} users to admin", promoted);
+    }
+    let keys = load_keys(&config);
+    let mailer = Box::new(OutboxMailer::new(&config.outbox_dir));
+    let _scheduler = TickScheduler::new(config.tick_interval, Arc::clone(&db));
+    let state = Arc::new(ServerState::new(db, keys, config, mailer));
 
     for stream in listener.incoming() {
         let stream = stream.unwrap();
-        let db = Arc::clone(&db);
+        let state = Arc::clone(&state);
         pool.execute(move || {
-            handle_connection(stream, db);
+            handle_connection(stream, state);
         });
     }
 }
 
-fn handle_connection(mut stream: TcpStream, db: Arc<impl Database>) {
+// Run with `--rotate-keys` to switch to a new signing key.
+fn load_keys(config: &Config) -> KeyRing {
+    let mut keys = KeyRing::load_or_generate(&config.jwt_keys_file, config.jwt_alg).unwrap();
+
+    if env::args().any(|arg| arg == "--rotate-keys") {
+        warn!("Rotating JWT signing keys");
+        keys.rotate(config.jwt_alg).unwrap();
+        keys.save(&config.jwt_keys_file).unwrap();
+    }
+
+    keys
+}
+
+fn handle_connection(mut stream: TcpStream, state: Arc<ServerState<impl Database>>) {
     // let mut buf_reader = BufReader::new(&mut stream);
     // Todo, workout when should use buf reader ? buffer
     // TODO - how does this work in rust and buffers TCP generally
 
     let mut buffer = [0; 1024];
-    stream.read(&mut buffer).unwrap();
-    let request = str::from_utf8(&buffer).unwrap();
+    let end = stream.read(&mut buffer).unwrap();
 
     // Note, this is a hack and isn't complying with the HTTP spec
     // https://www.rfc-editor.org/rfc/rfc9112#name-message-body-length
     // Should be looking at the content-length instead
-    let end = request.find('\0').unwrap();
-    let request = request[..end].to_string();
+    let request = str::from_utf8(&buffer[..end]).unwrap().to_string();
 
-    let response = process_request(request, db);
+    // The client may have gone already, there's no one to answer
+    let peer = match stream.peer_addr() {
+        Ok(addr) => addr.ip(),
+        Err(err) => {
+            warn!("Dropping connection without a peer address: {:?}", err);
+            return;
+        }
+    };
+    let response = process_request_from(request, peer, &state);
 
     stream.write_all(response.as_bytes()).unwrap();
 }
@@ -0,0 +1,132 @@
+use crate::{
+    http::{HttpError, HttpErrorCode},
+    sessions, tokens, users, Database, Mail, Mailer,
+};
+use jsonwebtoken::get_current_timestamp;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const PASSWORD_RESET_NAME: &str = "password-reset";
+
+const RESET_TOKEN_TIMEOUT_S: u64 = 30 * 60;
+
+// Stored under the hash of the token that was mailed to the user
+#[derive(Serialize, Deserialize)]
+struct PasswordResetEntry {
+    username: String,
+    exp: u64,
+}
+
+fn password_reset_key(reset_token: &str) -> String {
+    PASSWORD_RESET_NAME.to_string() + ":" + &tokens::hash_token(reset_token)
+}
+
+// E.g. when the user is deleted, so the codes don't work for whoever signs up
+// with the name next
+pub fn remove_user_resets(username: &str, db: &Arc<impl Database>) {
+    for key in db.keys(&(PASSWORD_RESET_NAME.to_string() + ":")) {
+        let for_user = db
+            .get(&key)
+            .and_then(|entry| serde_json::from_str::<PasswordResetEntry>(&entry).ok())
+            .is_some_and(|entry| entry.username == username);
+        if for_user {
+            db.del(&key);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RequestResetRq {
+    email: String,
+}
+
+pub fn request_reset(
+    body: String,
+    db: Arc<impl Database>,
+    mailer: &dyn Mailer,
+) -> Result<String, HttpError> {
+    let body: RequestResetRq = if let Ok(valid_body) = serde_json::from_str(&body) {
+        valid_body
+    } else {
+        return Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: "Password reset request body has invalid format.".to_string(),
+        });
+    };
+
+    // Always looks like it worked, so this can't be used to find out who has
+    // an account.
+    for username in users::find_users_by_email(&body.email, Arc::clone(&db)) {
+        let reset_token = tokens::generate_token(48);
+        let entry = PasswordResetEntry {
+            username: username.clone(),
+            exp: get_current_timestamp() + RESET_TOKEN_TIMEOUT_S,
+        };
+        db.set(
+            password_reset_key(&reset_token),
+            serde_json::to_string(&entry).unwrap(),
+        );
+
+        let mail = Mail {
+            to: body.email.clone(),
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Someone asked to reset the password for {}. If it was you, use this code to \
+                 pick a new one, it expires in {} minutes:\n\n{}\n\nIf it wasn't you, you can \
+                 ignore this.",
+                username,
+                RESET_TOKEN_TIMEOUT_S / 60,
+                reset_token
+            ),
+        };
+        if let Err(e) = mailer.send(mail) {
+            warn!("Failed to send password reset for {:?}: {}", username, e);
+        }
+    }
+
+    Ok("".to_string())
+}
+
+#[derive(Serialize, Deserialize)]
+struct ConfirmResetRq {
+    token: String,
+    new_password: String,
+}
+
+pub fn confirm_reset(body: String, db: Arc<impl Database>) -> Result<String, HttpError> {
+    let body: ConfirmResetRq = if let Ok(valid_body) = serde_json::from_str(&body) {
+        valid_body
+    } else {
+        return Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: "Confirm password reset request body has invalid format.".to_string(),
+        });
+    };
+
+    let invalid_token_error = HttpError {
+        code: HttpErrorCode::Error401Unauthorized,
+        message: "Reset token is invalid or expired.".to_string(),
+    };
+
+    let key = password_reset_key(&body.token);
+    let entry: PasswordResetEntry = match db.get(&key) {
+        Some(entry) => serde_json::from_str(&entry).unwrap(),
+        None => return Err(invalid_token_error),
+    };
+    if entry.exp <= get_current_timestamp() {
+        db.del(&key);
+        return Err(invalid_token_error);
+    }
+
+    // Only use up the token once the new password is accepted, so the user
+    // can try again if it fails validation.
+    users::set_password(&entry.username, &body.new_password, Arc::clone(&db))?;
+    db.del(&key);
+
+    // Whoever knew the old password shouldn't stay logged in
+    sessions::revoke_refresh_tokens(&entry.username, db);
+
+    info!("Password reset for {:?}", entry.username);
+    Ok("".to_string())
+}
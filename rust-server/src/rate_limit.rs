@@ -0,0 +1,207 @@
+use crate::{
+    http::{HttpError, HttpErrorCode, Request},
+    jwt, Database, ServerState,
+};
+use log::info;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Limit used for any route without its own
+pub const DEFAULT_ROUTE: &str = "*";
+
+// Once there are this many buckets, the ones that have refilled are dropped
+const MAX_BUCKETS: usize = 10_000;
+
+// A client can make `capacity` requests in a burst, then `refill_per_s` a second
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub capacity: f64,
+    pub refill_per_s: f64,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    // When the bucket is back to capacity, after which it's the same as a new one
+    full_at: Instant,
+}
+
+// Token buckets for each client and route
+pub struct RateLimiter {
+    limits: HashMap<String, RateLimit>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+impl RateLimiter {
+    pub fn new(limits: HashMap<String, RateLimit>) -> RateLimiter {
+        RateLimiter {
+            limits,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn limit_for(&self, route: &str) -> Option<&RateLimit> {
+        self.limits
+            .get(route)
+            .or_else(|| self.limits.get(DEFAULT_ROUTE))
+    }
+
+    // Takes a token from the client's bucket for the route. If it's empty,
+    // returns how many seconds until there will be one.
+    fn take(&self, client: &str, route: &str, now: Instant) -> Result<(), u64> {
+        let limit = match self.limit_for(route) {
+            Some(limit) => *limit,
+            None => return Ok(()),
+        };
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS {
+            buckets.retain(|_, bucket| bucket.full_at > now);
+        }
+
+        let bucket = buckets
+            .entry(format!("{} {}", client, route))
+            .or_insert(Bucket {
+                tokens: limit.capacity,
+                last_refill: now,
+                full_at: now,
+            });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.refill_per_s).min(limit.capacity);
+        bucket.last_refill = now;
+
+        let result = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / limit.refill_per_s).ceil() as u64)
+        };
+
+        let until_full = (limit.capacity - bucket.tokens) / limit.refill_per_s;
+        bucket.full_at = now + Duration::from_secs_f64(until_full);
+        result
+    }
+}
+
+// The route the request is for, with IDs taken out so e.g. every game's
+// actions share a limit. Matches the keys used for the limits in `Config`.
+fn route_name(request: &Request) -> String {
+    let mut route = format!("{} {}", request.method.as_str(), request.resource);
+    if request.id.is_some() {
+        route += "/{id}";
+    }
+    if let Some(sub_resource) = &request.sub_resource {
+        route += &format!("/{}", sub_resource);
+    }
+    route
+}
+
+// Logged in users are limited by username, so they don't use up the limit for
+// everyone sharing their IP. Everyone else is limited by IP.
+fn client_name(peer: IpAddr, claims: Option<&jwt::Claims>) -> String {
+    match claims {
+        Some(claims) => format!("user:{}", claims.sub),
+        None => format!("ip:{}", peer),
+    }
+}
+
+// `claims` are from the request's token, if it has a valid one
+pub fn check_rate_limit(
+    request: &Request,
+    peer: IpAddr,
+    claims: Option<&jwt::Claims>,
+    state: &ServerState<impl Database>,
+) -> Result<(), HttpError> {
+    let route = route_name(request);
+    let client = client_name(peer, claims);
+
+    state
+        .rate_limiter
+        .take(&client, &route, Instant::now())
+        .map_err(|retry_after_s| {
+            info!("Rate limited {:?} on {:?}", client, route);
+            HttpError {
+                code: HttpErrorCode::Error429TooManyRequests(retry_after_s),
+                message: "Too many requests, slow down.".to_string(),
+            }
+        })
+}
+
+#[cfg(test)]
+fn test_limiter() -> RateLimiter {
+    RateLimiter::new(HashMap::from([
+        (
+            "POST /users".to_string(),
+            RateLimit {
+                capacity: 2.0,
+                refill_per_s: 0.5,
+            },
+        ),
+        (
+            DEFAULT_ROUTE.to_string(),
+            RateLimit {
+                capacity: 10.0,
+                refill_per_s: 10.0,
+            },
+        ),
+    ]))
+}
+
+#[test]
+fn test_rate_limit_burst_and_refill() {
+    let limiter = test_limiter();
+    let now = Instant::now();
+
+    // Burst up to the capacity
+    assert!(limiter.take("ip:10.0.0.1", "POST /users", now).is_ok());
+    assert!(limiter.take("ip:10.0.0.1", "POST /users", now).is_ok());
+    assert_eq!(limiter.take("ip:10.0.0.1", "POST /users", now), Err(2));
+
+    // Other clients and routes have their own buckets
+    assert!(limiter.take("ip:10.0.0.2", "POST /users", now).is_ok());
+    assert!(limiter.take("ip:10.0.0.1", "GET /keys", now).is_ok());
+
+    // Refills over time
+    let later = now + Duration::from_secs(1);
+    assert_eq!(limiter.take("ip:10.0.0.1", "POST /users", later), Err(1));
+    let later = now + Duration::from_secs(2);
+    assert!(limiter.take("ip:10.0.0.1", "POST /users", later).is_ok());
+    assert!(limiter.take("ip:10.0.0.1", "POST /users", later).is_err());
+
+    // But never past the capacity
+    let much_later = now + Duration::from_secs(3600);
+    assert!(limiter
+        .take("ip:10.0.0.1", "POST /users", much_later)
+        .is_ok());
+    assert!(limiter
+        .take("ip:10.0.0.1", "POST /users", much_later)
+        .is_ok());
+    assert!(limiter
+        .take("ip:10.0.0.1", "POST /users", much_later)
+        .is_err());
+}
+
+#[test]
+fn test_rate_limit_default_route() {
+    let limiter = test_limiter();
+    let now = Instant::now();
+
+    for _ in 0..10 {
+        assert!(limiter
+            .take("user:james", "POST /rrr-game/{id}/actions", now)
+            .is_ok());
+    }
+    assert!(limiter
+        .take("user:james", "POST /rrr-game/{id}/actions", now)
+        .is_err());
+
+    // No limits at all means nothing is limited
+    let limiter = RateLimiter::new(HashMap::new());
+    for _ in 0..100 {
+        assert!(limiter.take("user:james", "POST /users", now).is_ok());
+    }
+}
@@ -1,22 +1,55 @@
 use crate::{
+    auth::{self, Role},
+    email_verifications, friends,
     http::{self, HttpError, HttpErrorCode, HttpMethod, Response},
-    jwt, rrr_game, users, Database,
+    jwt, password_resets, rate_limit, rrr_game, sessions, users, Database, ServerState,
 };
-use std::sync::Arc;
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+};
+
+const KEYS_ROUTE: &str = "/keys";
 
 const USERS_ROUTE: &str = "/users";
+const USERS_ROLES_ROUTE: &str = "roles";
+const USERS_PASSWORD_ROUTE: &str = "password";
+const USERS_VERIFICATION_ROUTE: &str = "verification";
+const USERS_FRIENDS_ROUTE: &str = "friends";
+const USERS_INVITES_ROUTE: &str = "invites";
 
 const SESSIONS_ROUTE: &str = "/sessions";
+const SESSIONS_REFRESH_ROUTE: &str = "refresh";
+
+const PASSWORD_RESETS_ROUTE: &str = "/password-resets";
+const PASSWORD_RESETS_CONFIRM_ROUTE: &str = "confirm";
+
+const EMAIL_VERIFICATIONS_ROUTE: &str = "/email-verifications";
+const EMAIL_VERIFICATIONS_CONFIRM_ROUTE: &str = "confirm";
 
 const RRR_ROUTE: &str = "/rrr-game";
 const RRR_PLAYERS_ROUTE: &str = "players";
 const RRR_ACTIONS_ROUTE: &str = "actions";
+const RRR_INVITES_ROUTE: &str = "invites";
+const RRR_SPECTATORS_ROUTE: &str = "spectators";
+
+// For when the request didn't come over the network, e.g. in tests
+pub fn process_request(request: String, state: &ServerState<impl Database>) -> String {
+    process_request_from(request, IpAddr::V4(Ipv4Addr::LOCALHOST), state)
+}
 
-pub fn process_request(request: String, db: Arc<impl Database>) -> String {
+// `peer` is the address the request came from
+pub fn process_request_from(
+    request: String,
+    peer: IpAddr,
+    state: &ServerState<impl Database>,
+) -> String {
     let request = http::Request::new(request);
 
     let response = if let Some(valid_request) = request {
-        process_valid_request(valid_request, db)
+        let auth = authenticate(&valid_request, state);
+        rate_limit::check_rate_limit(&valid_request, peer, auth.as_ref().ok(), state)
+            .and_then(|_| process_valid_request(valid_request, auth, peer, state))
     } else {
         // The request wasn't valid
         // Maybe just drop this?
@@ -30,10 +63,43 @@ pub fn process_request(request: String, db: Arc<impl Database>) -> String {
     response.build_response()
 }
 
+// The claims from the request's token. Only checked once, as both the rate
+// limiter and the routes need them.
+fn authenticate(
+    request: &http::Request,
+    state: &ServerState<impl Database>,
+) -> Result<jwt::Claims, HttpError> {
+    let token = match request.headers.get("Authorization") {
+        Some(val) => match val.strip_prefix("Bearer ") {
+            Some(token) => token,
+            None => {
+                return Err(HttpError {
+                    code: HttpErrorCode::Error403Forbidden,
+                    message: "You must be logged in.".to_string(),
+                })
+            }
+        },
+        None => {
+            return Err(HttpError {
+                code: HttpErrorCode::Error403Forbidden,
+                message: "You must be logged in.".to_string(),
+            })
+        }
+    };
+
+    jwt::validate_jwt(token, &state.keys, Arc::clone(&state.db))
+}
+
+// `auth` is the result of checking the request's token, only routes that need
+// a login look at it
 fn process_valid_request(
     valid_request: http::Request,
-    db: Arc<impl Database>,
+    auth: Result<jwt::Claims, HttpError>,
+    peer: IpAddr,
+    state: &ServerState<impl Database>,
 ) -> Result<Response, HttpError> {
+    let db = Arc::clone(&state.db);
+
     let not_found_error = Err(HttpError {
         code: HttpErrorCode::Error404NotFround,
         message: "Route not found".to_string(),
@@ -48,16 +114,67 @@ fn process_valid_request(
     if valid_request.method == HttpMethod::OPTIONS {
         let allowed_headers = match (
             valid_request.resource.as_str(),
-            valid_request.id,
+            valid_request.id.as_deref(),
             valid_request.sub_resource.as_deref(),
         ) {
-            (SESSIONS_ROUTE, None, None) => Some(vec![HttpMethod::OPTIONS, HttpMethod::POST]),
+            (KEYS_ROUTE, None, None) => Some(vec![HttpMethod::OPTIONS, HttpMethod::GET]),
+            (SESSIONS_ROUTE, None, None) => Some(vec![
+                HttpMethod::OPTIONS,
+                HttpMethod::POST,
+                HttpMethod::DELETE,
+            ]),
+            (SESSIONS_ROUTE, Some(SESSIONS_REFRESH_ROUTE), None) => {
+                Some(vec![HttpMethod::OPTIONS, HttpMethod::POST])
+            }
             (USERS_ROUTE, None, None) => Some(vec![HttpMethod::OPTIONS, HttpMethod::POST]),
+            (USERS_ROUTE, Some(_), None) => Some(vec![
+                HttpMethod::OPTIONS,
+                HttpMethod::GET,
+                HttpMethod::PATCH,
+                HttpMethod::DELETE,
+            ]),
+            (USERS_ROUTE, Some(_), Some(USERS_PASSWORD_ROUTE)) => {
+                Some(vec![HttpMethod::OPTIONS, HttpMethod::POST])
+            }
+            (USERS_ROUTE, Some(_), Some(USERS_ROLES_ROUTE)) => {
+                Some(vec![HttpMethod::OPTIONS, HttpMethod::POST])
+            }
+            (USERS_ROUTE, Some(_), Some(USERS_VERIFICATION_ROUTE)) => {
+                Some(vec![HttpMethod::OPTIONS, HttpMethod::POST])
+            }
+            (USERS_ROUTE, Some(_), Some(USERS_FRIENDS_ROUTE | USERS_INVITES_ROUTE)) => Some(vec![
+                HttpMethod::OPTIONS,
+                HttpMethod::GET,
+                HttpMethod::POST,
+                HttpMethod::DELETE,
+            ]),
+            (EMAIL_VERIFICATIONS_ROUTE, Some(EMAIL_VERIFICATIONS_CONFIRM_ROUTE), None) => {
+                Some(vec![HttpMethod::OPTIONS, HttpMethod::POST])
+            }
+            (PASSWORD_RESETS_ROUTE, None, None) => {
+                Some(vec![HttpMethod::OPTIONS, HttpMethod::POST])
+            }
+            (PASSWORD_RESETS_ROUTE, Some(PASSWORD_RESETS_CONFIRM_ROUTE), None) => {
+                Some(vec![HttpMethod::OPTIONS, HttpMethod::POST])
+            }
             (RRR_ROUTE, None, None) => {
                 Some(vec![HttpMethod::OPTIONS, HttpMethod::POST, HttpMethod::GET])
             }
-            (RRR_ROUTE, Some(_), None) => Some(vec![HttpMethod::OPTIONS, HttpMethod::GET]),
-            (RRR_ROUTE, Some(_), Some(RRR_PLAYERS_ROUTE)) => Some(vec![
+            (RRR_ROUTE, Some(_), None) => Some(vec![
+                HttpMethod::OPTIONS,
+                HttpMethod::GET,
+                HttpMethod::DELETE,
+            ]),
+            (RRR_ROUTE, Some(_), Some(RRR_INVITES_ROUTE)) => {
+                Some(vec![HttpMethod::OPTIONS, HttpMethod::POST])
+            }
+            (RRR_ROUTE, Some(_), Some(RRR_ACTIONS_ROUTE)) => Some(vec![
+                HttpMethod::OPTIONS,
+                HttpMethod::GET,
+                HttpMethod::POST,
+                HttpMethod::DELETE,
+            ]),
+            (RRR_ROUTE, Some(_), Some(RRR_PLAYERS_ROUTE | RRR_SPECTATORS_ROUTE)) => Some(vec![
                 HttpMethod::OPTIONS,
                 HttpMethod::POST,
                 HttpMethod::DELETE,
@@ -79,23 +196,75 @@ fn process_valid_request(
     // Routes with no auth
     //
 
-    // Sessions
-    if valid_request.resource == SESSIONS_ROUTE && valid_request.id.is_none() {
-        match valid_request.method {
-            HttpMethod::POST => {
-                return Response::response_from_body(users::login(valid_request.body, db))
-            }
-            _ => (),
+    // Public signing keys
+    if valid_request.resource == KEYS_ROUTE && valid_request.id.is_none() {
+        return match valid_request.method {
+            HttpMethod::GET => Response::response_from_body(jwt::get_public_keys(&state.keys)),
+            _ => not_found_error,
         };
     }
+    // Sessions
+    else if valid_request.resource == SESSIONS_ROUTE
+        && valid_request.id.is_none()
+        && valid_request.method == HttpMethod::POST
+    {
+        return Response::response_from_body(users::login(
+            valid_request.body,
+            peer,
+            db,
+            &state.keys,
+        ));
+    } else if valid_request.resource == SESSIONS_ROUTE
+        && valid_request.id.as_deref() == Some(SESSIONS_REFRESH_ROUTE)
+        && valid_request.method == HttpMethod::POST
+    {
+        // Refresh doesn't need an access token, as it has probably expired
+        return Response::response_from_body(sessions::refresh(
+            valid_request.body,
+            db,
+            &state.keys,
+        ));
+    }
     // Users
-    else if valid_request.resource == USERS_ROUTE && valid_request.id.is_none() {
-        match valid_request.method {
-            HttpMethod::POST => {
-                return Response::response_from_body(users::create_user(valid_request.body, db))
-            }
-            _ => (),
-        }
+    else if valid_request.resource == USERS_ROUTE
+        && valid_request.id.is_none()
+        && valid_request.method == HttpMethod::POST
+    {
+        return Response::response_from_body(users::create_user(
+            valid_request.body,
+            db,
+            &state.keys,
+            state.mailer.as_ref(),
+        ));
+    }
+    // Email verification, the token in the mail is proof enough
+    else if valid_request.resource == EMAIL_VERIFICATIONS_ROUTE
+        && valid_request.id.as_deref() == Some(EMAIL_VERIFICATIONS_CONFIRM_ROUTE)
+        && valid_request.method == HttpMethod::POST
+    {
+        return Response::response_from_body(email_verifications::confirm_verification(
+            valid_request.body,
+            db,
+        ));
+    }
+    // Password resets, the user can't log in so these can't need auth
+    else if valid_request.resource == PASSWORD_RESETS_ROUTE
+        && valid_request.id.is_none()
+        && valid_request.method == HttpMethod::POST
+    {
+        return Response::response_from_body(password_resets::request_reset(
+            valid_request.body,
+            db,
+            state.mailer.as_ref(),
+        ));
+    } else if valid_request.resource == PASSWORD_RESETS_ROUTE
+        && valid_request.id.as_deref() == Some(PASSWORD_RESETS_CONFIRM_ROUTE)
+        && valid_request.method == HttpMethod::POST
+    {
+        return Response::response_from_body(password_resets::confirm_reset(
+            valid_request.body,
+            db,
+        ));
     }
 
     //
@@ -103,44 +272,116 @@ fn process_valid_request(
     //
 
     // Auth check
-    let token = match valid_request.headers.get("Authorization") {
-        Some(val) => match val.strip_prefix("Bearer ") {
-            Some(token) => token,
-            None => {
-                return Err(HttpError {
-                    code: HttpErrorCode::Error403Forbidden,
-                    message: "You must be logged in.".to_string(),
-                })
-            }
-        },
-        None => {
-            return Err(HttpError {
-                code: HttpErrorCode::Error403Forbidden,
-                message: "You must be logged in.".to_string(),
-            })
-        }
-    };
-
-    let username = jwt::validate_jwt(token, &"test".to_string())?;
+    let claims = auth?;
+    let username = claims.sub.clone();
 
     // Sessions
     if valid_request.resource == SESSIONS_ROUTE {
-        // Could support DELETE, but that is unlikely to every happen as this is
-        // fiddly for sessions.
-        not_found_error
+        match (valid_request.id, valid_request.method) {
+            (None, HttpMethod::DELETE) => {
+                Response::response_from_body(sessions::logout(claims, valid_request.body, db))
+            }
+            _ => not_found_error,
+        }
     }
     // Users
     else if valid_request.resource == USERS_ROUTE {
         if let Some(user_id) = valid_request.id {
-            if user_id != username {
-                return Err(HttpError {
-                    code: HttpErrorCode::Error403Forbidden,
-                    message: "You are not authorized to access this user.".to_string(),
-                });
-            }
-            match valid_request.method {
-                HttpMethod::GET => Response::response_from_body(users::get_user(username, db)),
-                _ => not_implemented_error,
+            let user_id = users::canonical_username(&user_id);
+            match valid_request.sub_resource.as_deref() {
+                Some(USERS_ROLES_ROUTE) => match valid_request.method {
+                    HttpMethod::POST => {
+                        auth::check_role(&claims, Role::Admin)?;
+                        Response::response_from_body(users::set_user_roles(
+                            user_id,
+                            valid_request.body,
+                            db,
+                        ))
+                    }
+                    _ => not_found_error,
+                },
+                Some(USERS_PASSWORD_ROUTE) => match valid_request.method {
+                    HttpMethod::POST => {
+                        // Needs the old password, so only the user can do this
+                        auth::check_owner(&claims, &user_id)?;
+                        Response::response_from_body(users::change_password(
+                            user_id,
+                            valid_request.body,
+                            db,
+                        ))
+                    }
+                    _ => not_found_error,
+                },
+                Some(USERS_VERIFICATION_ROUTE) => match valid_request.method {
+                    HttpMethod::POST => {
+                        // Resend the verification mail
+                        auth::check_owner(&claims, &user_id)?;
+                        Response::response_from_body(email_verifications::resend_verification(
+                            user_id,
+                            db,
+                            state.mailer.as_ref(),
+                        ))
+                    }
+                    _ => not_found_error,
+                },
+                Some(USERS_FRIENDS_ROUTE) => {
+                    auth::check_owner_or_admin(&claims, &user_id)?;
+                    match valid_request.method {
+                        HttpMethod::GET => {
+                            Response::response_from_body(friends::get_friends(user_id, db))
+                        }
+                        HttpMethod::POST => Response::response_from_body(friends::add_friend(
+                            user_id,
+                            valid_request.body,
+                            db,
+                        )),
+                        HttpMethod::DELETE => Response::response_from_body(friends::remove_friend(
+                            user_id,
+                            valid_request.body,
+                            db,
+                        )),
+                        _ => not_found_error,
+                    }
+                }
+                Some(USERS_INVITES_ROUTE) => {
+                    auth::check_owner_or_admin(&claims, &user_id)?;
+                    match valid_request.method {
+                        HttpMethod::GET => {
+                            Response::response_from_body(rrr_game::get_invites(user_id, db))
+                        }
+                        HttpMethod::POST => Response::response_from_body(rrr_game::accept_invite(
+                            user_id,
+                            valid_request.body,
+                            db,
+                        )),
+                        HttpMethod::DELETE => Response::response_from_body(
+                            rrr_game::decline_invite(user_id, valid_request.body, db),
+                        ),
+                        _ => not_found_error,
+                    }
+                }
+                Some(_) => not_found_error,
+                None => match valid_request.method {
+                    HttpMethod::GET => {
+                        // Anyone can see the profile, only some can see the private bits
+                        let include_private = auth::check_owner_or_admin(&claims, &user_id).is_ok();
+                        Response::response_from_body(users::get_user(user_id, include_private, db))
+                    }
+                    HttpMethod::PATCH => {
+                        auth::check_owner_or_admin(&claims, &user_id)?;
+                        Response::response_from_body(users::update_user(
+                            user_id,
+                            valid_request.body,
+                            db,
+                            state.mailer.as_ref(),
+                        ))
+                    }
+                    HttpMethod::DELETE => {
+                        auth::check_owner_or_admin(&claims, &user_id)?;
+                        Response::response_from_body(users::delete_user(user_id, db))
+                    }
+                    _ => not_implemented_error,
+                },
             }
         } else {
             not_found_error
@@ -149,62 +390,91 @@ fn process_valid_request(
     // RRR game
     else if valid_request.resource == RRR_ROUTE {
         if let Some(game_id) = valid_request.id {
-            // Request for existing game
-
-            // Todo, do I need to check user is in game?
-
+            // Request for existing game, membership is checked by the handlers
             let sub_resource = valid_request.sub_resource.as_deref();
             match sub_resource {
                 Some(RRR_ACTIONS_ROUTE) => match valid_request.method {
                     HttpMethod::POST => Response::response_from_body(rrr_game::do_action(
                         username,
                         valid_request.body,
-                        valid_request.parameters,
                         game_id,
                         db,
                     )),
+                    HttpMethod::GET => Response::response_from_body(rrr_game::get_queued_moves(
+                        username, game_id, db,
+                    )),
+                    HttpMethod::DELETE => Response::response_from_body(
+                        rrr_game::cancel_queued_moves(username, game_id, db),
+                    ),
                     _ => not_found_error,
                 },
-                Some(RRR_PLAYERS_ROUTE) => {
-                    match valid_request.method {
-                        HttpMethod::POST => {
-                            // join game
-                            not_implemented_error
-                        }
-                        HttpMethod::DELETE => {
-                            // leave game
-                            not_implemented_error
-                        }
-                        _ => not_found_error,
+                Some(RRR_PLAYERS_ROUTE) => match valid_request.method {
+                    HttpMethod::POST => Response::response_from_body(rrr_game::join_game(
+                        username,
+                        valid_request.body,
+                        game_id,
+                        db,
+                    )),
+                    HttpMethod::DELETE => {
+                        Response::response_from_body(rrr_game::leave_game(username, game_id, db))
                     }
-                }
+                    _ => not_found_error,
+                },
+                Some(RRR_SPECTATORS_ROUTE) => match valid_request.method {
+                    HttpMethod::POST => Response::response_from_body(rrr_game::spectate_game(
+                        username,
+                        valid_request.body,
+                        game_id,
+                        db,
+                    )),
+                    HttpMethod::DELETE => Response::response_from_body(rrr_game::stop_spectating(
+                        username, game_id, db,
+                    )),
+                    _ => not_found_error,
+                },
+                Some(RRR_INVITES_ROUTE) => match valid_request.method {
+                    HttpMethod::POST => Response::response_from_body(rrr_game::invite_player(
+                        username,
+                        valid_request.body,
+                        game_id,
+                        db,
+                    )),
+                    _ => not_found_error,
+                },
                 Some(_) => not_found_error,
-                None => {
-                    match valid_request.method {
-                        HttpMethod::GET => Response::response_from_body(rrr_game::get_gamestate(
-                            username,
-                            valid_request.parameters,
-                            game_id,
-                            db,
-                        )),
-                        HttpMethod::DELETE => {
-                            // Delete the game
-                            not_implemented_error
-                        }
-                        _ => not_found_error,
+                None => match valid_request.method {
+                    HttpMethod::GET => Response::response_from_body(rrr_game::get_gamestate(
+                        username,
+                        claims.has_role(Role::Admin),
+                        valid_request.parameters,
+                        game_id,
+                        db,
+                    )),
+                    HttpMethod::DELETE => {
+                        auth::check_role(&claims, Role::Admin)?;
+                        Response::response_from_body(rrr_game::delete_game(game_id, db))
                     }
-                }
+                    _ => not_found_error,
+                },
             }
         } else {
             // No game_id specified
             match valid_request.method {
                 HttpMethod::POST => {
-                    Response::response_from_body(rrr_game::create_game(username, db))
-                }
-                HttpMethod::GET => {
-                    // Get list of available games - probably won't do
-                    not_implemented_error
+                    if state.config.require_verified_email {
+                        users::check_verified(&username, Arc::clone(&db))?;
+                    }
+                    Response::response_from_body(rrr_game::create_game(
+                        username,
+                        valid_request.body,
+                        db,
+                    ))
                 }
+                HttpMethod::GET => Response::response_from_body(rrr_game::list_games(
+                    username,
+                    valid_request.parameters,
+                    db,
+                )),
                 _ => not_found_error,
             }
         }
@@ -1,27 +1,91 @@
 // Todo - rename the create file.
 use crate::{
     http::{HttpError, HttpErrorCode},
-    rrr_game::{coord, create, CHUNK_LENGTH, GAME_NAME},
-    Database,
+    rrr_game::{
+        coord, create, explored, lock, meta, path, position, queue, tiles, timers, CHUNK_LENGTH,
+        GAME_NAME,
+    },
+    users, Database,
 };
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-#[derive(Serialize, Deserialize)]
-enum Move {
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Move {
     North,
     East,
     South,
     West,
 }
+impl Move {
+    pub const ALL: [Move; 4] = [Move::North, Move::East, Move::South, Move::West];
+
+    // x ->
+    // y
+    // |
+    // v
+    pub fn destination(&self, user_coord: &coord::UserCoord) -> coord::UserCoord {
+        match self {
+            Move::North => coord::UserCoord {
+                x: user_coord.x,
+                y: user_coord.y - 1,
+            },
+            Move::East => coord::UserCoord {
+                x: user_coord.x + 1,
+                y: user_coord.y,
+            },
+            Move::South => coord::UserCoord {
+                x: user_coord.x,
+                y: user_coord.y + 1,
+            },
+            Move::West => coord::UserCoord {
+                x: user_coord.x - 1,
+                y: user_coord.y,
+            },
+        }
+    }
+}
 
+// Exactly one of `move`, `moves` or `target`
 #[derive(Serialize, Deserialize)]
 struct ActionRq {
-    r#move: Move,
+    r#move: Option<Move>,
+    // Queued moves happen on the next tick, instead of straight away
+    #[serde(default)]
+    queue: bool,
+    // Always queued, one step a tick
+    moves: Option<Vec<Move>>,
+    // Walked to by the shortest way over the grass, replacing anything already
+    // queued
+    target: Option<coord::UserCoord>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Outcome {
+    Moved,
+    // Rock, water or anything else that can't be walked on
+    BlockedByTerrain,
+    // Someone else is there, and the game doesn't let players pass through
+    BlockedByPlayer,
+    // Too soon after the last move, for games with a minimum interval
+    RateLimited,
+    // Past the edge of the world made so far
+    OutOfBounds,
+    // Taken off the queue without being tried, e.g. because a move before it
+    // was blocked, so the rest of the path no longer leads where it did
+    Cancelled,
 }
 
 #[derive(Serialize, Deserialize)]
-struct ActionRsp {
+pub struct ActionRsp {
+    // Counts up for each of the player's actions in the game, queued or not
+    pub action_seq: u64,
+    pub outcome: Outcome,
+    // What was on the tile moved onto, or tried to. None if it wasn't looked at.
+    pub tile: Option<char>,
     user_coord: coord::UserCoord,
     top_left_visible_coord: coord::UserCoord,
 }
@@ -29,7 +93,6 @@ struct ActionRsp {
 pub fn do_action(
     username: String,
     body: String,
-    parameters: Option<Vec<(String, String)>>,
     game_id: String,
     db: Arc<impl Database>,
 ) -> Result<String, HttpError> {
@@ -43,90 +106,166 @@ pub fn do_action(
         });
     };
 
-    // Workout the gamestate chunk coord
-    let parameters = parameters.ok_or(HttpError {
-        code: HttpErrorCode::Error400BadRequest,
-        message: "User coords not supplied in POST action request.".to_string(),
-    })?;
-    let mut user_coord = coord::get_usercoord_from_params(parameters)?;
+    meta::get_meta(&game_id, &db)?.check_player(&username)?;
+
+    match (action.r#move, action.moves, action.target) {
+        (Some(r#move), None, None) if action.queue => {
+            queue::queue_moves(&username, &[r#move], &game_id, &db)
+        }
+        (Some(r#move), None, None) => {
+            let rsp = apply_move(&username, r#move, &game_id, db)?;
+            Ok(serde_json::to_string(&rsp).unwrap())
+        }
+        (None, Some(moves), None) => queue::queue_moves(&username, &moves, &game_id, &db),
+        (None, None, Some(target)) => {
+            let user_coord = position::find_player(&game_id, &username, &db)?;
+            let moves = path::find_path(&game_id, &user_coord, &target, &db).ok_or(HttpError {
+                code: HttpErrorCode::Error400BadRequest,
+                message: "Can't get to the target".to_string(),
+            })?;
+            queue::clear_user_queue(&username, &game_id, &db);
+            queue::queue_moves(&username, &moves, &game_id, &db)
+        }
+        _ => Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: "Request needs exactly one of move, moves or target.".to_string(),
+        }),
+    }
+}
+
+// Moves the player straight away, if they can go there
+pub fn apply_move(
+    username: &str,
+    r#move: Move,
+    game_id: &str,
+    db: Arc<impl Database>,
+) -> Result<ActionRsp, HttpError> {
+    let game_lock = lock::game_lock(game_id);
+    let _guard = game_lock.lock().unwrap();
+    let action_seq = position::next_action_seqs(game_id, username, 1, &db)?.start;
+    move_player(username, r#move, action_seq, game_id, db)
+}
+
+// Moves the player, if they can go there. Used for moves made straight away,
+// and ones queued up for the tick. The caller has to hold the game's lock.
+pub fn move_player(
+    username: &str,
+    r#move: Move,
+    action_seq: u64,
+    game_id: &str,
+    db: Arc<impl Database>,
+) -> Result<ActionRsp, HttpError> {
+    let game_meta = meta::get_meta(game_id, &db)?;
+    // Where the server thinks the user is, any coords the client sent are ignored
+    let user_coord = position::find_player(game_id, username, &db)?;
     let gamestate_coord = coord::user_coord_to_gamestate_coord(&user_coord, CHUNK_LENGTH);
-    let top_left_visible_coord = coord::get_top_left_visible_coord(&gamestate_coord, CHUNK_LENGTH);
-
-    // Get the gamestate chunk
-    let gamestate_chunk = db
-        .get(&(GAME_NAME.to_string() + ":" + &game_id + ":" + &gamestate_coord.id()))
-        .unwrap(); // Todo error handling - goes for all unwrap() calls
-    let mut gamestate_chunk: create::GamestateChunk =
-        serde_json::from_str(&gamestate_chunk).unwrap();
-
-    // Check user is in chunk
-    if gamestate_chunk.users.contains_key(&username) {
-        // Trust db over user supplied value
-        user_coord = gamestate_chunk.users.get(&username).unwrap().clone();
-    } else {
-        // Possible todo - could add recovery code in here to search the neighbours
-        return Err(HttpError {
-            code: HttpErrorCode::Error500InternalServerError,
-            message: "Can't find user in the gamestate chunk".to_string(),
-        });
+    let not_moved = |outcome, tile| ActionRsp {
+        action_seq,
+        outcome,
+        tile,
+        user_coord: user_coord.clone(),
+        top_left_visible_coord: coord::get_top_left_visible_coord(&gamestate_coord, CHUNK_LENGTH),
+    };
+
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let moved_at_ms = position::get_moved_at_ms(game_id, username, &db);
+    if now_ms < moved_at_ms + u128::from(game_meta.settings.min_move_interval_ms) {
+        return Ok(not_moved(Outcome::RateLimited, None));
     }
 
     // Do move
-    // x ->
-    // y
-    // |
-    // v
-    let new_user_coord = match action.r#move {
-        Move::North => coord::UserCoord {
-            x: user_coord.x,
-            y: user_coord.y - 1,
-        },
-        Move::East => coord::UserCoord {
-            x: user_coord.x + 1,
-            y: user_coord.y,
-        },
-        Move::South => coord::UserCoord {
-            x: user_coord.x,
-            y: user_coord.y + 1,
-        },
-        Move::West => coord::UserCoord {
-            x: user_coord.x - 1,
-            y: user_coord.y,
-        },
-    };
+    let new_user_coord = r#move.destination(&user_coord);
 
-    let new_relative_x = new_user_coord.x - (top_left_visible_coord.x + (CHUNK_LENGTH as i32));
-    let new_relative_y = new_user_coord.y - (top_left_visible_coord.y + (CHUNK_LENGTH as i32));
-    assert!(new_relative_x > 0);
-    assert!(new_relative_y > 0);
-    let new_relative_x = new_relative_x as usize;
-    let new_relative_y = new_relative_y as usize;
-    // Todo - deal with going off edge of chunk, requires updating top_left_visible_coord
-    let rsp = if gamestate_chunk.terrain[new_relative_y][new_relative_x] == create::TILE_GRASS {
-        // Move is valid
-
-        gamestate_chunk
-            .users
-            .insert(username, new_user_coord.clone());
-
-        // Write to DB
-        db.set(
-            GAME_NAME.to_string() + ":" + &game_id + ":" + &gamestate_chunk.get_id(),
-            serde_json::to_string(&gamestate_chunk).unwrap(),
-        );
-
-        // Return
-        // Todo - workout if want to return the gamestate here...
-        ActionRsp {
-            user_coord: new_user_coord,
-            top_left_visible_coord,
-        }
-    } else {
-        // Move is invalid
-        ActionRsp {
-            user_coord,
-            top_left_visible_coord,
-        }
+    // The move can take the user into a neighbouring chunk, which exists
+    // unless it's past the edge of the world
+    let new_gamestate_coord = coord::user_coord_to_gamestate_coord(&new_user_coord, CHUNK_LENGTH);
+    let mut new_gamestate_chunk = match get_chunk(game_id, &new_gamestate_coord, &db) {
+        Ok(new_gamestate_chunk) => new_gamestate_chunk,
+        Err(_) => return Ok(not_moved(Outcome::OutOfBounds, None)),
     };
-    Ok(serde_json::to_string(&rsp).unwrap())
+
+    let (new_relative_x, new_relative_y) = coord::get_terrain_index(&new_user_coord, CHUNK_LENGTH);
+    let tile = new_gamestate_chunk.terrain[new_relative_y][new_relative_x];
+    if !tiles::is_walkable(tile) {
+        return Ok(not_moved(Outcome::BlockedByTerrain, Some(tile)));
+    }
+    // Players are only ever in the chunk their tile is in, so this holds
+    // across chunk borders too
+    if !game_meta.settings.pass_through
+        && new_gamestate_chunk
+            .get_occupant(&new_user_coord, username)
+            .is_some()
+    {
+        return Ok(not_moved(Outcome::BlockedByPlayer, Some(tile)));
+    }
+
+    // Walking through forest tramples it for a while
+    if tile == tiles::TILE_FOREST {
+        new_gamestate_chunk.terrain[new_relative_y][new_relative_x] = tiles::TILE_GRASS;
+        let regrow = timers::TimerEvent::Regrow {
+            user_coord: new_user_coord.clone(),
+            tile,
+        };
+        timers::set_timer(game_id, now_ms + timers::REGROW_MS, &regrow, &db);
+    }
+
+    // Move is valid
+    if new_gamestate_coord != gamestate_coord {
+        let mut gamestate_chunk = get_chunk(game_id, &gamestate_coord, &db)?;
+        gamestate_chunk.users.remove(username);
+        set_chunk(game_id, &gamestate_chunk, &db);
+        create::create_missing_neighbours(game_id, game_meta.seed, &new_gamestate_chunk, &db);
+        users::set_user_curr_chunk(
+            username,
+            GAME_NAME,
+            new_gamestate_coord.id(),
+            Arc::clone(&db),
+        )?;
+    }
+    new_gamestate_chunk
+        .users
+        .insert(username.to_string(), new_user_coord.clone());
+
+    // Write to DB
+    set_chunk(game_id, &new_gamestate_chunk, &db);
+    position::set_moved_position(game_id, username, &new_user_coord, now_ms, &db);
+    let explored_new_tile = explored::mark_explored(game_id, username, &new_user_coord, &db);
+    users::record_move(username, 1, explored_new_tile, db)?;
+
+    // Return
+    // Todo - workout if want to return the gamestate here...
+    Ok(ActionRsp {
+        action_seq,
+        outcome: Outcome::Moved,
+        tile: Some(tile),
+        user_coord: new_user_coord,
+        top_left_visible_coord: coord::get_top_left_visible_coord(
+            &new_gamestate_coord,
+            CHUNK_LENGTH,
+        ),
+    })
+}
+
+pub fn get_chunk(
+    game_id: &str,
+    gamestate_coord: &coord::GamestateCoord,
+    db: &Arc<impl Database>,
+) -> Result<create::GamestateChunk, HttpError> {
+    match db.get(&(GAME_NAME.to_string() + ":" + game_id + ":" + &gamestate_coord.id())) {
+        Some(gamestate_chunk) => Ok(serde_json::from_str(&gamestate_chunk).unwrap()),
+        None => Err(HttpError {
+            code: HttpErrorCode::Error500InternalServerError,
+            message: "Gamestate chunk missing".to_string(),
+        }),
+    }
+}
+
+pub fn set_chunk(game_id: &str, gamestate_chunk: &create::GamestateChunk, db: &Arc<impl Database>) {
+    db.set(
+        GAME_NAME.to_string() + ":" + game_id + ":" + &gamestate_chunk.get_id(),
+        serde_json::to_string(gamestate_chunk).unwrap(),
+    );
 }
@@ -0,0 +1,300 @@
+use crate::rrr_game::{coord, tiles, CHUNK_LENGTH};
+
+// Roughly how many tiles across the temperature and moisture change over
+const CLIMATE_SCALE: f64 = 48.0;
+const RIVER_SCALE: f64 = 32.0;
+// There's always a way through along every 16th row and column, bridging
+// rivers and lakes and cutting passes through mountains, so every part of the
+// world joins up
+const ROAD_SPACING: i32 = 16;
+// Tiles this far from (0, 0) are always grass, so players start somewhere open
+const SPAWN_RADIUS: i32 = 2;
+
+// Each noise map gets its own layer, so they don't line up
+const TEMPERATURE_LAYER: u64 = 1;
+const MOISTURE_LAYER: u64 = 2;
+const RIVER_LAYER: u64 = 3;
+const DETAIL_LAYER: u64 = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Biome {
+    Plains,
+    Forest,
+    Desert,
+    Mountains,
+    Lake,
+}
+
+// The same for a seed, coord and layer every time
+fn hash(seed: u64, layer: u64, x: i64, y: i64) -> u64 {
+    // splitmix64
+    let mut z = seed
+        .wrapping_add(layer.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+        .wrapping_add((x as u64).wrapping_mul(0xBF58_476D_1CE4_E5B9))
+        .wrapping_add((y as u64).wrapping_mul(0x94D0_49BB_1331_11EB));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// Between 0 and 1
+fn random(seed: u64, layer: u64, x: i64, y: i64) -> f64 {
+    (hash(seed, layer, x, y) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// Random values on a grid `scale` tiles apart, smoothly blended in between.
+// Between 0 and 1.
+fn value_noise(seed: u64, layer: u64, x: i32, y: i32, scale: f64) -> f64 {
+    let (x, y) = (f64::from(x) / scale, f64::from(y) / scale);
+    let (cell_x, cell_y) = (x.floor(), y.floor());
+    let smooth = |t: f64| t * t * (3.0 - 2.0 * t);
+    let (tx, ty) = (smooth(x - cell_x), smooth(y - cell_y));
+
+    let corner = |dx, dy| random(seed, layer, cell_x as i64 + dx, cell_y as i64 + dy);
+    let top = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * tx;
+    let bottom = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * tx;
+    top + (bottom - top) * ty
+}
+
+// Big features, with some smaller ones on top. Between 0 and 1.
+fn fractal_noise(seed: u64, layer: u64, x: i32, y: i32, scale: f64) -> f64 {
+    (value_noise(seed, layer, x, y, scale) * 2.0
+        + value_noise(seed, layer + 100, x, y, scale / 2.0))
+        / 3.0
+}
+
+pub fn get_biome(seed: u64, user_coord: &coord::UserCoord) -> Biome {
+    let (x, y) = (user_coord.x, user_coord.y);
+    let temperature = fractal_noise(seed, TEMPERATURE_LAYER, x, y, CLIMATE_SCALE);
+    let moisture = fractal_noise(seed, MOISTURE_LAYER, x, y, CLIMATE_SCALE);
+
+    if moisture > 0.68 {
+        Biome::Lake
+    } else if temperature < 0.32 {
+        Biome::Mountains
+    } else if temperature > 0.62 && moisture < 0.45 {
+        Biome::Desert
+    } else if moisture > 0.52 {
+        Biome::Forest
+    } else {
+        Biome::Plains
+    }
+}
+
+// Rivers follow the middle of a noise map, which is continuous, so they wind
+// across chunk borders without needing to know about the chunks. A tile is
+// river if the noise crosses the middle between its corners. Wherever a river
+// goes out of a tile it comes into the one next door, so it never breaks up
+// however steep the noise gets.
+fn is_river(seed: u64, user_coord: &coord::UserCoord) -> bool {
+    let is_high = |dx, dy| {
+        let (x, y) = (user_coord.x + dx, user_coord.y + dy);
+        value_noise(seed, RIVER_LAYER, x, y, RIVER_SCALE) >= 0.5
+    };
+    let corners = [is_high(0, 0), is_high(1, 0), is_high(0, 1), is_high(1, 1)];
+    corners.contains(&true) && corners.contains(&false)
+}
+
+fn is_road(user_coord: &coord::UserCoord) -> bool {
+    user_coord.x.rem_euclid(ROAD_SPACING) == 0 || user_coord.y.rem_euclid(ROAD_SPACING) == 0
+}
+
+// The tile at a point in the world. Only depends on the seed and where it is,
+// so chunks can be made in any order and still join up.
+pub fn get_tile(seed: u64, user_coord: &coord::UserCoord) -> char {
+    if user_coord.x.abs() <= SPAWN_RADIUS && user_coord.y.abs() <= SPAWN_RADIUS {
+        return tiles::TILE_GRASS;
+    }
+    get_natural_tile(seed, user_coord)
+}
+
+// What's there without the open square in the middle
+fn get_natural_tile(seed: u64, user_coord: &coord::UserCoord) -> char {
+    let biome = get_biome(seed, user_coord);
+    let detail = random(
+        seed,
+        DETAIL_LAYER,
+        i64::from(user_coord.x),
+        i64::from(user_coord.y),
+    );
+    let tile = if biome != Biome::Lake && is_river(seed, user_coord) {
+        tiles::TILE_WATER
+    } else {
+        match biome {
+            Biome::Plains if detail < 0.05 => tiles::TILE_FOREST,
+            Biome::Plains if detail < 0.08 => tiles::TILE_ROCK,
+            Biome::Plains => tiles::TILE_GRASS,
+            Biome::Forest if detail < 0.6 => tiles::TILE_FOREST,
+            Biome::Forest if detail < 0.65 => tiles::TILE_WATER,
+            Biome::Forest => tiles::TILE_GRASS,
+            Biome::Desert if detail < 0.05 => tiles::TILE_ROCK,
+            Biome::Desert => tiles::TILE_SAND,
+            Biome::Mountains if detail < 0.02 => tiles::TILE_LAVA,
+            Biome::Mountains if detail < 0.75 => tiles::TILE_ROCK,
+            Biome::Mountains => tiles::TILE_GRASS,
+            Biome::Lake if detail < 0.5 => tiles::TILE_DEEP_WATER,
+            Biome::Lake => tiles::TILE_WATER,
+        }
+    };
+
+    if is_road(user_coord) && !tiles::is_walkable(tile) {
+        match tile {
+            tiles::TILE_WATER | tiles::TILE_DEEP_WATER => tiles::TILE_BRIDGE,
+            _ => tiles::TILE_GRASS,
+        }
+    } else {
+        tile
+    }
+}
+
+pub fn generate_terrain(seed: u64, gamestate_coord: &coord::GamestateCoord) -> Vec<Vec<char>> {
+    cut_chunk(gamestate_coord, |user_coord| get_tile(seed, user_coord))
+}
+
+// Without the open square in the middle, to test spawning into the world as
+// it would otherwise be
+#[cfg(test)]
+pub fn generate_natural_terrain(
+    seed: u64,
+    gamestate_coord: &coord::GamestateCoord,
+) -> Vec<Vec<char>> {
+    cut_chunk(gamestate_coord, |user_coord| {
+        get_natural_tile(seed, user_coord)
+    })
+}
+
+fn cut_chunk(
+    gamestate_coord: &coord::GamestateCoord,
+    get_tile: impl Fn(&coord::UserCoord) -> char,
+) -> Vec<Vec<char>> {
+    let chunk_length = CHUNK_LENGTH as i32;
+    let left = gamestate_coord.x * chunk_length - chunk_length / 2;
+    let top = gamestate_coord.y * chunk_length - chunk_length / 2;
+
+    (top..top + chunk_length)
+        .map(|y| {
+            (left..left + chunk_length)
+                .map(|x| get_tile(&coord::UserCoord { x, y }))
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn test_generate_terrain() {
+    // The chunks are cut from one world, so they always join up
+    for seed in 0..10 {
+        for (chunk_x, chunk_y) in [(0, 0), (1, 0), (-3, 2)] {
+            let gamestate_coord = coord::GamestateCoord {
+                x: chunk_x,
+                y: chunk_y,
+            };
+            let terrain = generate_terrain(seed, &gamestate_coord);
+            assert_eq!(terrain, generate_terrain(seed, &gamestate_coord));
+            assert_eq!(terrain.len(), CHUNK_LENGTH);
+
+            let top_left = coord::get_top_left_visible_coord(&gamestate_coord, CHUNK_LENGTH);
+            for (y, row) in terrain.iter().enumerate() {
+                assert_eq!(row.len(), CHUNK_LENGTH);
+                for (x, tile) in row.iter().enumerate() {
+                    let user_coord = coord::UserCoord {
+                        x: top_left.x + (CHUNK_LENGTH + x) as i32,
+                        y: top_left.y + (CHUNK_LENGTH + y) as i32,
+                    };
+                    assert_eq!(coord::get_terrain_index(&user_coord, CHUNK_LENGTH), (x, y));
+                    assert_eq!(*tile, get_tile(seed, &user_coord));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_biomes() {
+    // A big enough world has a bit of everything
+    let mut biomes = std::collections::HashSet::new();
+    let mut river_tiles = 0;
+    for x in -200..200 {
+        for y in -200..200 {
+            let user_coord = coord::UserCoord { x, y };
+            biomes.insert(get_biome(7, &user_coord));
+            if is_river(7, &user_coord) {
+                river_tiles += 1;
+            }
+        }
+    }
+    assert_eq!(biomes.len(), 5);
+    assert!(river_tiles > 0);
+}
+
+#[test]
+fn test_spawn_is_connected() {
+    // From the spawn area, a player can always walk out of the middle of the
+    // world
+    const RADIUS: i32 = 40;
+    for seed in 0..20 {
+        for x in -SPAWN_RADIUS..=SPAWN_RADIUS {
+            for y in -SPAWN_RADIUS..=SPAWN_RADIUS {
+                assert!(tiles::is_walkable(get_tile(
+                    seed,
+                    &coord::UserCoord { x, y }
+                )));
+            }
+        }
+
+        let mut seen = std::collections::HashSet::from([(0i32, 0i32)]);
+        let mut to_visit = vec![(0i32, 0i32)];
+        let mut reached_edge = false;
+        while let Some((x, y)) = to_visit.pop() {
+            if x.abs() == RADIUS || y.abs() == RADIUS {
+                reached_edge = true;
+                break;
+            }
+            for (dx, dy) in [(0, -1), (1, 0), (0, 1), (-1, 0)] {
+                let next = (x + dx, y + dy);
+                let tile = get_tile(
+                    seed,
+                    &coord::UserCoord {
+                        x: next.0,
+                        y: next.1,
+                    },
+                );
+                if tiles::is_walkable(tile) && seen.insert(next) {
+                    to_visit.push(next);
+                }
+            }
+        }
+        assert!(reached_edge, "Spawn is boxed in for seed {}", seed);
+    }
+}
+
+#[test]
+fn test_rivers_are_connected() {
+    // Every bit of river carries on into at least two of the tiles next to
+    // it, so there are no gaps, dead ends or diagonal steps
+    let is_river_at = |seed, x, y| is_river(seed, &coord::UserCoord { x, y });
+    let chunk_length = CHUNK_LENGTH as i32;
+    let mut border_crossings = 0;
+    for seed in 0..10 {
+        for x in -100..100 {
+            for y in -100..100 {
+                if !is_river_at(seed, x, y) {
+                    continue;
+                }
+                let next_to = [(0, -1), (1, 0), (0, 1), (-1, 0)]
+                    .iter()
+                    .filter(|(dx, dy)| is_river_at(seed, x + dx, y + dy))
+                    .count();
+                assert!(next_to >= 2, "River breaks at ({}, {})", x, y);
+
+                // The last column of a chunk, going on into the next one
+                if (x + chunk_length / 2).rem_euclid(chunk_length) == chunk_length - 1
+                    && is_river_at(seed, x + 1, y)
+                {
+                    border_crossings += 1;
+                }
+            }
+        }
+    }
+    assert!(border_crossings > 0);
+}
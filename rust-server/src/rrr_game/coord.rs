@@ -73,6 +73,20 @@ pub fn get_top_left_visible_coord(
     }
 }
 
+// Where the user coord is in its chunk's terrain, as (x, y) indexes
+pub fn get_terrain_index(user_coord: &UserCoord, chunk_length: usize) -> (usize, usize) {
+    let gamestate_coord = user_coord_to_gamestate_coord(user_coord, chunk_length);
+    let top_left_visible_coord = get_top_left_visible_coord(&gamestate_coord, chunk_length);
+    let chunk_length = chunk_length as i32;
+
+    // The chunk is the middle of the visible 3*3 chunks
+    let x = user_coord.x - (top_left_visible_coord.x + chunk_length);
+    let y = user_coord.y - (top_left_visible_coord.y + chunk_length);
+    assert!((0..chunk_length).contains(&x));
+    assert!((0..chunk_length).contains(&y));
+    (x as usize, y as usize)
+}
+
 pub fn get_usercoord_from_params(
     parameters: Vec<(String, String)>,
 ) -> Result<UserCoord, HttpError> {
@@ -175,3 +189,12 @@ fn test_get_top_left_visible_coord() {
         UserCoord { x: -22, y: -22 }
     );
 }
+
+#[test]
+fn test_get_terrain_index() {
+    assert_eq!(get_terrain_index(&UserCoord { x: 0, y: 0 }, 9), (4, 4));
+    assert_eq!(get_terrain_index(&UserCoord { x: -4, y: 4 }, 9), (0, 8));
+    assert_eq!(get_terrain_index(&UserCoord { x: 5, y: -5 }, 9), (0, 8));
+    assert_eq!(get_terrain_index(&UserCoord { x: -5, y: 13 }, 9), (8, 8));
+    assert_eq!(get_terrain_index(&UserCoord { x: -14, y: -13 }, 9), (8, 0));
+}
@@ -1,16 +1,14 @@
 use crate::{
     http::{HttpError, HttpErrorCode},
-    rrr_game::{coord, get, CHUNK_LENGTH, GAME_NAME},
-    users, Database,
+    rrr_game::{biomes, coord, explored, get, lock, meta, spawn, GAME_NAME},
+    users,
+    validation::Validator,
+    Database,
 };
 use rand::{distributions::Alphanumeric, Rng}; // 0.8
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, sync::Arc};
 
-pub const TILE_GRASS: char = 'G';
-pub const TILE_ROCK: char = 'R';
-pub const TILE_WATER: char = 'W';
-
 #[derive(Serialize, Deserialize, Clone)]
 pub struct GamestateChunk {
     pub coord: coord::GamestateCoord,
@@ -21,36 +19,24 @@ impl GamestateChunk {
     pub fn get_id(&self) -> String {
         self.coord.id()
     }
-    fn new(
-        coord: coord::GamestateCoord,
-        username: &str,
-        user_coord: Option<coord::UserCoord>,
-    ) -> GamestateChunk {
-        let mut terrain = vec![vec![TILE_GRASS; CHUNK_LENGTH]; CHUNK_LENGTH];
-
-        let mut rng = rand::thread_rng();
-        for row in terrain.iter_mut() {
-            for i in 0..row.len() {
-                let y: f64 = rng.gen();
-
-                if y < 0.1 {
-                    row[i] = TILE_WATER;
-                } else if y > 0.9 {
-                    row[i] = TILE_ROCK;
-                }
-            }
-        }
 
-        let mut users = HashMap::new();
+    // Whoever else is standing on the tile
+    pub fn get_occupant(&self, user_coord: &coord::UserCoord, username: &str) -> Option<&String> {
+        self.users
+            .iter()
+            .find(|(occupant, occupant_coord)| {
+                occupant.as_str() != username && *occupant_coord == user_coord
+            })
+            .map(|(occupant, _)| occupant)
+    }
 
-        if let Some(user_coord) = user_coord {
-            users.insert(username.to_owned(), user_coord);
-        }
+    fn new(coord: coord::GamestateCoord, seed: u64) -> GamestateChunk {
+        let terrain = biomes::generate_terrain(seed, &coord);
 
         GamestateChunk {
             coord,
             terrain,
-            users,
+            users: HashMap::new(),
         }
     }
 
@@ -71,11 +57,29 @@ impl GamestateChunk {
     }
 }
 
+// Called when a player moves into a chunk, so there's always a full visible
+// gamestate around them
+pub fn create_missing_neighbours(
+    game_id: &str,
+    seed: u64,
+    chunk: &GamestateChunk,
+    db: &Arc<impl Database>,
+) {
+    for neighbour in chunk.get_neighbours() {
+        let key = GAME_NAME.to_string() + ":" + game_id + ":" + &neighbour.id();
+        if db.get(&key).is_none() {
+            let neighbour_chunk = GamestateChunk::new(neighbour, seed);
+            db.set(key, serde_json::to_string(&neighbour_chunk).unwrap());
+        }
+    }
+}
+
+// Joining a game gets the same response
 #[derive(Serialize)]
-struct CreateGameRsp {
-    game_id: String,
-    user_coord: coord::UserCoord,
-    visible_gamestate: get::VisibleGamestate, // Todo - decide if want to do this or not
+pub struct CreateGameRsp {
+    pub game_id: String,
+    pub user_coord: coord::UserCoord,
+    pub visible_gamestate: get::VisibleGamestate, // Todo - decide if want to do this or not
 }
 
 fn generate_game_id() -> String {
@@ -86,9 +90,41 @@ fn generate_game_id() -> String {
         .collect()
 }
 
-pub fn create_game(username: String, db: Arc<impl Database>) -> Result<String, HttpError> {
+// The body is optional, without one the game is public with no password
+#[derive(Serialize, Deserialize, Default)]
+struct CreateGameRq {
+    #[serde(default)]
+    visibility: meta::Visibility,
+    password: Option<String>,
+    #[serde(default)]
+    pass_through: bool,
+    #[serde(default)]
+    min_move_interval_ms: u64,
+}
+
+pub fn create_game(
+    username: String,
+    body: String,
+    db: Arc<impl Database>,
+) -> Result<String, HttpError> {
+    let body: CreateGameRq = if body.is_empty() {
+        CreateGameRq::default()
+    } else if let Ok(valid_body) = serde_json::from_str(&body) {
+        valid_body
+    } else {
+        return Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: "Create game body has invalid format.".to_string(),
+        });
+    };
+    if let Some(password) = &body.password {
+        Validator::new()
+            .join_password("password", password)
+            .finish()?;
+    }
+
     // Check if user is in a game already
-    let curr_game_id = users::get_user_curr_game_info(&username, Arc::clone(&db), "rrr-game")?;
+    let curr_game_id = users::get_user_curr_game_info(&username, Arc::clone(&db), GAME_NAME)?;
 
     if curr_game_id.is_some() {
         return Err(HttpError {
@@ -100,31 +136,25 @@ pub fn create_game(username: String, db: Arc<impl Database>) -> Result<String, H
     // Create new current game ID
     let game_id: String = generate_game_id();
     let centre_chunk_coord = coord::GamestateCoord { x: 0, y: 0 };
-    if db
-        .get(&(GAME_NAME.to_string() + ":" + &game_id + ":" + &centre_chunk_coord.id()))
-        .is_some()
-    {
+    if meta::get_meta(&game_id, &db).is_ok() {
         return Err(HttpError {
             code: HttpErrorCode::Error503ServiceUnavailable,
             message: "Clash when creating new game ID".to_string(),
         });
     }
 
+    // No one else has the ID yet, but the tick finds the game once it has a meta
+    let game_lock = lock::game_lock(&game_id);
+    let _guard = game_lock.lock().unwrap();
+
     // Create new chunks
-    let user_coord = coord::UserCoord { x: 0, y: 0 };
-    let centre_chunk = GamestateChunk::new(
-        centre_chunk_coord.clone(),
-        &username,
-        Some(user_coord.clone()),
-    );
+    let seed = rand::thread_rng().gen();
+    let centre_chunk = GamestateChunk::new(centre_chunk_coord.clone(), seed);
 
     let neighbours = centre_chunk.get_neighbours();
     let mut chunks = HashMap::from([(centre_chunk.coord.clone(), centre_chunk.clone())]);
     for neighbour in neighbours {
-        chunks.insert(
-            neighbour.clone(),
-            GamestateChunk::new(neighbour, &username, None),
-        );
+        chunks.insert(neighbour.clone(), GamestateChunk::new(neighbour, seed));
     }
 
     // Store chunks in DB
@@ -134,9 +164,39 @@ pub fn create_game(username: String, db: Arc<impl Database>) -> Result<String, H
             serde_json::to_string(&chunk).unwrap(),
         );
     }
+    let user_coord = spawn::place_player(&game_id, &username, &db)?;
+
+    let password_hash = body
+        .password
+        .map(|password| users::hash_password(&password).0);
+    let settings = meta::GameSettings {
+        visibility: body.visibility,
+        password_hash,
+        max_players: meta::MAX_PLAYERS,
+        pass_through: body.pass_through,
+        min_move_interval_ms: body.min_move_interval_ms,
+    };
+    meta::set_meta(
+        &meta::GameMeta::new(&game_id, &username, seed, settings),
+        &db,
+    );
+
+    // Remember the user is in this game
+    users::set_user_curr_game_info(
+        &username,
+        Arc::clone(&db),
+        GAME_NAME,
+        users::UserGameInfo {
+            game_id: game_id.clone(),
+            chunk_id: centre_chunk_coord.id(),
+        },
+    )?;
+    users::record_game_played(&username, Arc::clone(&db))?;
+    let explored_new_tile = explored::mark_explored(&game_id, &username, &user_coord, &db);
+    users::record_move(&username, 0, explored_new_tile, Arc::clone(&db))?;
 
     // Todo - consider if should hit db here - maybe just to be sure it was written?
-    let visible_gamestate = get::get_visible_gamestate(&user_coord, username, &game_id, db)?;
+    let visible_gamestate = get::get_visible_gamestate(&user_coord, username, false, &game_id, db)?;
     let rsp = CreateGameRsp {
         game_id,
         user_coord,
@@ -0,0 +1,66 @@
+use crate::{
+    http::HttpError,
+    rrr_game::{create, explored, invites, lock, meta, position, queue, timers, GAME_NAME},
+    users, Database,
+};
+use std::sync::Arc;
+
+fn get_chunk_keys(game_id: &str, db: &Arc<impl Database>) -> Vec<String> {
+    db.keys(&(GAME_NAME.to_string() + ":" + game_id + ":"))
+}
+
+pub fn delete_game(game_id: String, db: Arc<impl Database>) -> Result<String, HttpError> {
+    meta::get_meta(&game_id, &db)?;
+    let game_lock = lock::game_lock(&game_id);
+    let _guard = game_lock.lock().unwrap();
+    // It might have gone while waiting for the lock
+    let game_meta = meta::get_meta(&game_id, &db)?;
+
+    for key in get_chunk_keys(&game_id, &db) {
+        db.del(&key);
+    }
+    meta::delete_meta(&game_id, &db);
+    position::remove_game_positions(&game_id, &db);
+    queue::remove_game_queue(&game_id, &db);
+    explored::remove_game_explored(&game_id, &db);
+    timers::remove_game_timers(&game_id, &db);
+    invites::remove_game_invites(&game_id, &db);
+    // Only once everything's gone, so anyone waiting on the lock finds nothing
+    lock::remove_game_lock(&game_id);
+
+    // The roster says who was playing, even if they went missing from the chunks
+    for player in game_meta.roster.keys() {
+        users::remove_user_curr_game(player, GAME_NAME, &game_id, Arc::clone(&db));
+    }
+
+    Ok("".to_string())
+}
+
+// Take a player off the roster, and out of whichever chunk they are in
+pub fn remove_player(game_id: &str, username: &str, db: Arc<impl Database>) {
+    let game_lock = lock::game_lock(game_id);
+    let _guard = game_lock.lock().unwrap();
+    remove_player_locked(game_id, username, db);
+}
+
+// The caller has to hold the game's lock
+pub fn remove_player_locked(game_id: &str, username: &str, db: Arc<impl Database>) {
+    if let Ok(mut game_meta) = meta::get_meta(game_id, &db) {
+        if game_meta.roster.remove(username).is_some() {
+            meta::set_meta(&game_meta, &db);
+        }
+    }
+    position::remove_position(game_id, username, &db);
+    queue::clear_user_queue(username, game_id, &db);
+    explored::remove_player_explored(game_id, username, &db);
+
+    for key in get_chunk_keys(game_id, &db) {
+        if let Some(gamestate_chunk) = db.get(&key) {
+            let mut gamestate_chunk: create::GamestateChunk =
+                serde_json::from_str(&gamestate_chunk).unwrap();
+            if gamestate_chunk.users.remove(username).is_some() {
+                db.set(key, serde_json::to_string(&gamestate_chunk).unwrap());
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use crate::{
+    rrr_game::{coord, CHUNK_LENGTH},
+    Database,
+};
+use std::sync::Arc;
+
+const EXPLORED_NAME: &str = "rrr-game-explored";
+
+// The tiles a player has stood on, kept as a bitmap for each chunk so the
+// user entry doesn't grow the more they explore. The user only keeps the count.
+fn explored_key(game_id: &str, username: &str, chunk_id: &str) -> String {
+    EXPLORED_NAME.to_string() + ":" + game_id + ":" + username + ":" + chunk_id
+}
+
+// Which bit of the chunk's bitmap is the tile
+fn tile_bit(user_coord: &coord::UserCoord) -> u128 {
+    let (x, y) = coord::get_terrain_index(user_coord, CHUNK_LENGTH);
+    1 << (y * CHUNK_LENGTH + x)
+}
+
+// Returns whether the tile is new to the player
+pub fn mark_explored(
+    game_id: &str,
+    username: &str,
+    user_coord: &coord::UserCoord,
+    db: &Arc<impl Database>,
+) -> bool {
+    let chunk_id = coord::user_coord_to_gamestate_coord(user_coord, CHUNK_LENGTH).id();
+    let bit = tile_bit(user_coord);
+    let mut is_new = false;
+    db.update(
+        &explored_key(game_id, username, &chunk_id),
+        &mut |explored| {
+            let explored: u128 = explored.map_or(0, |explored| explored.parse().unwrap());
+            is_new = explored & bit == 0;
+            Some((explored | bit).to_string())
+        },
+    );
+    is_new
+}
+
+pub fn remove_player_explored(game_id: &str, username: &str, db: &Arc<impl Database>) {
+    for key in db.keys(&(EXPLORED_NAME.to_string() + ":" + game_id + ":" + username + ":")) {
+        db.del(&key);
+    }
+}
+
+pub fn remove_game_explored(game_id: &str, db: &Arc<impl Database>) {
+    for key in db.keys(&(EXPLORED_NAME.to_string() + ":" + game_id + ":")) {
+        db.del(&key);
+    }
+}
+
+#[test]
+fn test_explored() {
+    let db = Arc::new(crate::LocalDatabase::new());
+    let origin = coord::UserCoord { x: 0, y: 0 };
+    // Opposite corners of a chunk, and the next chunk over
+    let corners = [
+        coord::UserCoord { x: -4, y: -4 },
+        coord::UserCoord { x: 4, y: 4 },
+        coord::UserCoord { x: 5, y: 4 },
+    ];
+
+    // Only new the first time
+    assert!(mark_explored("abc", "james", &origin, &db));
+    assert!(!mark_explored("abc", "james", &origin, &db));
+    for user_coord in &corners {
+        assert!(mark_explored("abc", "james", user_coord, &db));
+    }
+    for user_coord in &corners {
+        assert!(!mark_explored("abc", "james", user_coord, &db));
+    }
+
+    // Each player and game has their own
+    assert!(mark_explored("abc", "alex", &origin, &db));
+    assert!(mark_explored("def", "james", &origin, &db));
+
+    remove_player_explored("abc", "james", &db);
+    assert!(mark_explored("abc", "james", &origin, &db));
+    remove_game_explored("abc", &db);
+    assert!(mark_explored("abc", "alex", &origin, &db));
+    assert!(!mark_explored("def", "james", &origin, &db));
+}
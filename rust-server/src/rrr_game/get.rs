@@ -1,33 +1,45 @@
 use crate::{
     http::{HttpError, HttpErrorCode},
-    rrr_game::{coord, create, CHUNK_LENGTH, GAME_NAME},
+    rrr_game::{coord, create, meta, position, tiles, CHUNK_LENGTH, GAME_NAME},
     Database,
 };
 
 use serde::{Serialize, Serializer};
 use serde_with::{serde_as, SerializeAs};
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+};
 
 use super::coord::UserCoord;
 
+// Spectators and admins aren't in the gamestate, so are just watching
 pub fn get_visible_gamestate(
     user_coord: &coord::UserCoord,
     username: String,
-    game_id: &String,
+    is_watching: bool,
+    game_id: &str,
     db: Arc<impl Database>,
 ) -> Result<VisibleGamestate, HttpError> {
     let centre_gamestate_coord = coord::user_coord_to_gamestate_coord(user_coord, CHUNK_LENGTH);
 
+    // Chunks are only made as players get near them, so watchers can look
+    // somewhere there isn't anything yet
+    let get_chunk = |gamestate_coord: &coord::GamestateCoord| match db
+        .get(&(GAME_NAME.to_string() + ":" + game_id + ":" + &gamestate_coord.id()))
+    {
+        Some(gamestate_chunk) => Ok(serde_json::from_str(&gamestate_chunk).unwrap()),
+        None => Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: "Nothing to see there, no one has been near it yet".to_string(),
+        }),
+    };
+
     // Get centre create::GamestateChunk
-    let centre_gamestate_chunk = db
-        .get(&(GAME_NAME.to_string() + ":" + game_id + ":" + &centre_gamestate_coord.id()))
-        .unwrap(); // Todo error handling - goes for all unwrap() calls
-    let centre_gamestate_chunk: create::GamestateChunk =
-        serde_json::from_str(&centre_gamestate_chunk).unwrap();
-
-    // Check user is in chunk
-    if !centre_gamestate_chunk.users.contains_key(&username) {
-        // Possible todo - could add recovery code in here to search the neighbours
+    let centre_gamestate_chunk: create::GamestateChunk = get_chunk(&centre_gamestate_coord)?;
+
+    // Check user is in chunk, watchers can look anywhere
+    if !is_watching && !centre_gamestate_chunk.users.contains_key(&username) {
         return Err(HttpError {
             code: HttpErrorCode::Error500InternalServerError,
             message: "Can't find user in the gamestate chunk".to_string(),
@@ -38,14 +50,8 @@ pub fn get_visible_gamestate(
     let neighbours = centre_gamestate_chunk.get_neighbours();
     let mut chunks = HashMap::from([(centre_gamestate_coord.clone(), centre_gamestate_chunk)]);
     for neighbour in neighbours {
-        let neighbour_gamestate_chunk = db
-            .get(&(GAME_NAME.to_string() + ":" + game_id + ":" + &neighbour.id()))
-            .unwrap();
-        let neighbour_gamestate_chunk = serde_json::from_str(&neighbour_gamestate_chunk).unwrap();
-        chunks.insert(
-            neighbour.clone(),
-            neighbour_gamestate_chunk, // Todo error handling - goes for all unwrap() calls,
-        );
+        let neighbour_gamestate_chunk = get_chunk(&neighbour)?;
+        chunks.insert(neighbour, neighbour_gamestate_chunk);
     }
 
     // Return visible gamestate
@@ -54,18 +60,32 @@ pub fn get_visible_gamestate(
 
 pub fn get_gamestate(
     username: String,
+    is_admin: bool,
     parameters: Option<Vec<(String, String)>>,
     game_id: String,
     db: Arc<impl Database>,
 ) -> Result<String, HttpError> {
-    // Convert the passed in params to coord::coord::UserCoord
-    let parameters = parameters.ok_or(HttpError {
-        code: HttpErrorCode::Error400BadRequest,
-        message: "User coords not supplied in GET gamestate request.".to_string(),
-    })?;
-    let user_coord = coord::get_usercoord_from_params(parameters)?;
-
-    let visible_gamestate = get_visible_gamestate(&user_coord, username, &game_id, db)?;
+    // Admins can look at any game
+    let game_meta = meta::get_meta(&game_id, &db)?;
+    if !is_admin {
+        game_meta.check_member(&username)?;
+    }
+
+    // Players see around where the server has them, so any coords they send
+    // are ignored. Everyone else picks the centre of their viewport with the
+    // coords, and starts off looking at the centre of the world.
+    let is_watching = !game_meta.is_player(&username);
+    let user_coord = if is_watching {
+        match parameters {
+            Some(parameters) => coord::get_usercoord_from_params(parameters)?,
+            None => coord::UserCoord { x: 0, y: 0 },
+        }
+    } else {
+        position::find_player(&game_id, &username, &db)?
+    };
+
+    let visible_gamestate =
+        get_visible_gamestate(&user_coord, username, is_watching, &game_id, db)?;
     Ok(serde_json::to_string(&visible_gamestate).unwrap())
 }
 
@@ -76,6 +96,8 @@ pub struct VisibleGamestate {
     top_left_coord: UserCoord,
     #[serde_as(as = "Vec<TerrainLine>")]
     terrain: Vec<Vec<char>>,
+    // What the tiles in the terrain are, so clients don't need their own copy
+    tiles: Vec<&'static tiles::TileType>,
 }
 
 // Terrain line is only used for serialization and
@@ -142,6 +164,10 @@ fn create_visible_gamestate(
     // Bottom
     terrain.extend(get_new_rows(1));
 
+    // Get the tile types used, in id order
+    let tile_ids: BTreeSet<char> = terrain.iter().flatten().copied().collect();
+    let tiles = tile_ids.into_iter().filter_map(tiles::get_tile).collect();
+
     // Get top left
     let top_left_coord = coord::get_top_left_visible_coord(&centre, CHUNK_LENGTH);
 
@@ -149,6 +175,7 @@ fn create_visible_gamestate(
         terrain,
         users,
         top_left_coord,
+        tiles,
     }
 }
 
@@ -244,7 +271,19 @@ fn test_create_visible_gamestate() {
                 vec!['N', 'O', 'P', 'Q', 'R', 'S'],
             ],
             users: HashMap::new(),
-            top_left_coord: UserCoord { x: 77, y: 77 }
+            top_left_coord: UserCoord { x: 77, y: 77 },
+            // Only the ones the server knows about
+            tiles: [
+                tiles::TILE_BRIDGE,
+                tiles::TILE_DEEP_WATER,
+                tiles::TILE_FOREST,
+                tiles::TILE_LAVA,
+                tiles::TILE_ROCK,
+                tiles::TILE_SAND
+            ]
+            .into_iter()
+            .map(|id| tiles::get_tile(id).unwrap())
+            .collect(),
         }
     )
 }
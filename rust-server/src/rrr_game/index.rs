@@ -0,0 +1,168 @@
+use crate::{
+    rrr_game::meta::{GameMeta, GameStatus, Visibility},
+    Database,
+};
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+const GAME_INDEX_NAME: &str = "rrr-game-index";
+
+// Keys kept for each game, so the lobby and the tick can find the games they
+// want without reading every meta. Kept in step with the meta by `meta`.
+//  public:{order}            public games, for the lobby
+//  has-space:{order}         public games that aren't full
+//  member:{username}:{order} games the user is playing or watching
+//  active:{game_id}          games with players, for the tick
+// The order sorts newest first, so listing the keys in order pages the lobby.
+const PUBLIC_NAME: &str = "public";
+const HAS_SPACE_NAME: &str = "has-space";
+const MEMBER_NAME: &str = "member";
+const ACTIVE_NAME: &str = "active";
+
+// Held while a meta and its index keys change, so two changes to the same game
+// can't leave the index saying something the meta doesn't
+static INDEX_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+
+pub fn index_lock() -> &'static Mutex<()> {
+    INDEX_LOCK.get_or_init(|| Mutex::new(()))
+}
+
+fn prefix(kind: &str) -> String {
+    GAME_INDEX_NAME.to_string() + ":" + kind + ":"
+}
+
+fn member_prefix(username: &str) -> String {
+    prefix(MEMBER_NAME) + username + ":"
+}
+
+// Newest games first, with the ID to break ties. Padded so the keys sort.
+pub fn order(created_at: u64, game_id: &str) -> String {
+    format!("{:020}:{}", u64::MAX - created_at, game_id)
+}
+
+pub fn order_game_id(order: &str) -> &str {
+    order.split_once(':').map_or(order, |(_, game_id)| game_id)
+}
+
+fn index_keys(game_meta: &GameMeta) -> BTreeSet<String> {
+    let order = order(game_meta.created_at, &game_meta.game_id);
+    let mut keys = BTreeSet::new();
+    if game_meta.settings.visibility == Visibility::Public {
+        keys.insert(prefix(PUBLIC_NAME) + &order);
+        if game_meta.status() == GameStatus::Open {
+            keys.insert(prefix(HAS_SPACE_NAME) + &order);
+        }
+    }
+    for username in game_meta.roster.keys() {
+        keys.insert(member_prefix(username) + &order);
+    }
+    if game_meta.player_count() > 0 {
+        keys.insert(prefix(ACTIVE_NAME) + &game_meta.game_id);
+    }
+    keys
+}
+
+// Moves the game's keys from what they were for the old meta to the new one.
+// The caller has to hold `index_lock`.
+pub fn reindex(old_meta: Option<&GameMeta>, new_meta: Option<&GameMeta>, db: &Arc<impl Database>) {
+    let old_keys = old_meta.map(index_keys).unwrap_or_default();
+    let new_keys = new_meta.map(index_keys).unwrap_or_default();
+    for key in old_keys.difference(&new_keys) {
+        db.del(key);
+    }
+    if let Some(new_meta) = new_meta {
+        for key in new_keys.difference(&old_keys) {
+            db.set(key.clone(), new_meta.game_id.clone());
+        }
+    }
+}
+
+fn orders(prefix: &str, db: &Arc<impl Database>) -> BTreeSet<String> {
+    db.keys(prefix)
+        .into_iter()
+        .map(|key| key[prefix.len()..].to_string())
+        .collect()
+}
+
+// In lobby order
+pub fn public_games(has_space: bool, db: &Arc<impl Database>) -> BTreeSet<String> {
+    orders(
+        &prefix(if has_space {
+            HAS_SPACE_NAME
+        } else {
+            PUBLIC_NAME
+        }),
+        db,
+    )
+}
+
+// In lobby order
+pub fn member_games(username: &str, db: &Arc<impl Database>) -> BTreeSet<String> {
+    orders(&member_prefix(username), db)
+}
+
+// The IDs of the games with players in
+pub fn active_games(db: &Arc<impl Database>) -> Vec<String> {
+    db.keys(&prefix(ACTIVE_NAME))
+        .into_iter()
+        .map(|key| key[prefix(ACTIVE_NAME).len()..].to_string())
+        .collect()
+}
+
+#[test]
+fn test_index_follows_meta() {
+    use crate::rrr_game::meta::{self, GameSettings, RosterEntry, RosterRole, MAX_PLAYERS};
+
+    let db = Arc::new(crate::LocalDatabase::new());
+    let mut game_meta = GameMeta::new(
+        "abc",
+        "james",
+        0,
+        GameSettings {
+            visibility: Visibility::Public,
+            password_hash: None,
+            max_players: 2,
+            pass_through: false,
+            min_move_interval_ms: 0,
+        },
+    );
+    let abc_order = order(game_meta.created_at, "abc");
+    meta::set_meta(&game_meta, &db);
+    assert!(public_games(false, &db).contains(&abc_order));
+    assert!(public_games(true, &db).contains(&abc_order));
+    assert!(member_games("james", &db).contains(&abc_order));
+    assert_eq!(active_games(&db), vec!["abc"]);
+
+    // Full games don't have space
+    game_meta.roster.insert(
+        "alex".to_string(),
+        RosterEntry {
+            joined_at: 0,
+            role: RosterRole::Player,
+        },
+    );
+    meta::set_meta(&game_meta, &db);
+    assert!(public_games(false, &db).contains(&abc_order));
+    assert!(public_games(true, &db).is_empty());
+    assert!(member_games("alex", &db).contains(&abc_order));
+
+    // Only spectators left, so nothing to tick, and unlisted isn't public
+    game_meta.settings.max_players = MAX_PLAYERS;
+    game_meta.settings.visibility = Visibility::Unlisted;
+    game_meta.roster.remove("james");
+    game_meta.roster.get_mut("alex").unwrap().role = RosterRole::Spectator;
+    meta::set_meta(&game_meta, &db);
+    assert!(public_games(false, &db).is_empty());
+    assert!(member_games("james", &db).is_empty());
+    assert!(member_games("alex", &db).contains(&abc_order));
+    assert!(active_games(&db).is_empty());
+
+    meta::delete_meta("abc", &db);
+    assert!(db.keys(GAME_INDEX_NAME).is_empty());
+
+    // Newer games sort first
+    assert!(order(20, "bbb") < order(10, "aaa"));
+    assert_eq!(order_game_id(&abc_order), "abc");
+}
@@ -0,0 +1,219 @@
+use crate::{
+    friends,
+    http::{HttpError, HttpErrorCode},
+    rrr_game::{meta, players},
+    users, Database,
+};
+use jsonwebtoken::get_current_timestamp;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const GAME_INVITES_NAME: &str = "game-invites";
+
+#[derive(Serialize, Deserialize, Clone)]
+struct GameInvite {
+    game_id: String,
+    from: String,
+    sent_at: u64,
+}
+
+// Stored per invitee, so they can list them
+fn game_invites_key(username: &str) -> String {
+    GAME_INVITES_NAME.to_string() + ":" + username
+}
+
+fn get_invites_raw(username: &str, db: &Arc<impl Database>) -> Vec<GameInvite> {
+    match db.get(&game_invites_key(username)) {
+        Some(invites) => serde_json::from_str(&invites).unwrap(),
+        None => vec![],
+    }
+}
+
+// Changes the user's invites in one go, so an invite sent while another is
+// being answered isn't lost. Returns what `f` returns.
+fn update_invites_raw<T>(
+    username: &str,
+    db: &Arc<impl Database>,
+    mut f: impl FnMut(&mut Vec<GameInvite>) -> T,
+) -> T {
+    let mut result = None;
+    db.update(&game_invites_key(username), &mut |invites| {
+        let mut invites: Vec<GameInvite> = match invites {
+            Some(invites) => serde_json::from_str(&invites).unwrap(),
+            None => vec![],
+        };
+        result = Some(f(&mut invites));
+        (!invites.is_empty()).then(|| serde_json::to_string(&invites).unwrap())
+    });
+    result.unwrap()
+}
+
+#[derive(Serialize, Deserialize)]
+struct InviteRq {
+    username: String,
+}
+
+pub fn invite_player(
+    username: String,
+    body: String,
+    game_id: String,
+    db: Arc<impl Database>,
+) -> Result<String, HttpError> {
+    let body: InviteRq = if let Ok(valid_body) = serde_json::from_str(&body) {
+        valid_body
+    } else {
+        return Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: "Invite request body has invalid format.".to_string(),
+        });
+    };
+    let invitee = users::canonical_username(&body.username);
+
+    // Only players in the game can invite people to it
+    let in_game =
+        meta::get_meta(&game_id, &db).is_ok_and(|game_meta| game_meta.is_player(&username));
+    if !in_game {
+        return Err(HttpError {
+            code: HttpErrorCode::Error403Forbidden,
+            message: "You can only invite people to a game you are in.".to_string(),
+        });
+    }
+    if !friends::are_friends(&username, &invitee, &db) {
+        return Err(HttpError {
+            code: HttpErrorCode::Error403Forbidden,
+            message: "You can only invite friends.".to_string(),
+        });
+    }
+
+    update_invites_raw(&invitee, &db, |invites| {
+        if invites.iter().any(|invite| invite.game_id == game_id) {
+            return Err(HttpError {
+                code: HttpErrorCode::Error409Conflict,
+                message: "User has already been invited to this game.".to_string(),
+            });
+        }
+        invites.push(GameInvite {
+            game_id: game_id.clone(),
+            from: username.clone(),
+            sent_at: get_current_timestamp(),
+        });
+        Ok(())
+    })?;
+
+    Ok("".to_string())
+}
+
+#[derive(Serialize)]
+struct InvitesRsp {
+    invites: Vec<GameInvite>,
+}
+
+pub fn get_invites(username: String, db: Arc<impl Database>) -> Result<String, HttpError> {
+    let rsp = InvitesRsp {
+        invites: get_invites_raw(&username, &db),
+    };
+    Ok(serde_json::to_string(&rsp).unwrap())
+}
+
+#[derive(Serialize, Deserialize)]
+struct InviteReplyRq {
+    game_id: String,
+}
+
+fn parse_invite_reply(body: &str) -> Result<String, HttpError> {
+    let body: InviteReplyRq = if let Ok(valid_body) = serde_json::from_str(body) {
+        valid_body
+    } else {
+        return Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: "Invite reply body has invalid format.".to_string(),
+        });
+    };
+    Ok(body.game_id)
+}
+
+fn remove_invite(username: &str, game_id: &str, db: &Arc<impl Database>) -> Result<(), HttpError> {
+    let removed = update_invites_raw(username, db, |invites| {
+        let invite_count = invites.len();
+        invites.retain(|invite| invite.game_id != game_id);
+        invites.len() != invite_count
+    });
+    if !removed {
+        return Err(HttpError {
+            code: HttpErrorCode::Error404NotFround,
+            message: "No invite to that game.".to_string(),
+        });
+    }
+    Ok(())
+}
+
+// Accepting an invite joins the game. The invite is kept if joining fails,
+// e.g. because the user is still in another game.
+pub fn accept_invite(
+    username: String,
+    body: String,
+    db: Arc<impl Database>,
+) -> Result<String, HttpError> {
+    let game_id = parse_invite_reply(&body)?;
+    let invited = get_invites_raw(&username, &db)
+        .iter()
+        .any(|invite| invite.game_id == game_id);
+    if !invited {
+        return Err(HttpError {
+            code: HttpErrorCode::Error404NotFround,
+            message: "No invite to that game.".to_string(),
+        });
+    }
+
+    let rsp = players::add_player(username.clone(), &game_id, Arc::clone(&db))?;
+    remove_invite(&username, &game_id, &db)?;
+    Ok(rsp)
+}
+
+pub fn decline_invite(
+    username: String,
+    body: String,
+    db: Arc<impl Database>,
+) -> Result<String, HttpError> {
+    let game_id = parse_invite_reply(&body)?;
+    remove_invite(&username, &game_id, &db)?;
+    Ok("".to_string())
+}
+
+// Called when a user is deleted
+pub fn remove_user_invites(username: &str, db: Arc<impl Database>) {
+    db.del(&game_invites_key(username));
+}
+
+// Called when a game is deleted, so nobody is left with an invite to nowhere
+pub fn remove_game_invites(game_id: &str, db: &Arc<impl Database>) {
+    for key in db.keys(&(GAME_INVITES_NAME.to_string() + ":")) {
+        let username = &key[GAME_INVITES_NAME.len() + 1..];
+        update_invites_raw(username, db, |invites| {
+            invites.retain(|invite| invite.game_id != game_id);
+        });
+    }
+}
+
+#[test]
+fn test_remove_game_invites() {
+    let db = Arc::new(crate::LocalDatabase::new());
+    let invite = |game_id: &str| GameInvite {
+        game_id: game_id.to_string(),
+        from: "james".to_string(),
+        sent_at: 0,
+    };
+    for (username, game_ids) in [("alex", vec!["abc", "def"]), ("sam", vec!["abc"])] {
+        update_invites_raw(username, &db, |invites| {
+            invites.extend(game_ids.iter().map(|game_id| invite(game_id)));
+        });
+    }
+
+    remove_game_invites("abc", &db);
+
+    let invites = get_invites_raw("alex", &db);
+    assert_eq!(invites.len(), 1);
+    assert_eq!(invites[0].game_id, "def");
+    // Nothing left for sam, so the key goes
+    assert_eq!(db.get(&game_invites_key("sam")), None);
+}
@@ -0,0 +1,209 @@
+use crate::{
+    friends,
+    http::{HttpError, HttpErrorCode},
+    rrr_game::{
+        index,
+        meta::{self, GameMeta, GameStatus, Visibility},
+    },
+    Database,
+};
+use serde::Serialize;
+use std::{collections::BTreeSet, ops::Bound, sync::Arc};
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 100;
+
+fn cursor(meta: &GameMeta) -> String {
+    format!("{}-{}", meta.created_at, meta.game_id)
+}
+
+#[derive(Serialize)]
+struct GameSummary {
+    game_id: String,
+    creator: String,
+    player_count: usize,
+    max_players: usize,
+    created_at: u64,
+    status: GameStatus,
+    visibility: Visibility,
+    has_password: bool,
+}
+
+#[derive(Serialize)]
+struct ListGamesRsp {
+    games: Vec<GameSummary>,
+    // Pass as `cursor` to get the next page, missing on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+}
+
+struct ListGamesParams {
+    has_space: bool,
+    mine: bool,
+    friends: bool,
+    cursor: Option<(u64, String)>,
+    limit: usize,
+}
+
+fn invalid_param(name: &str) -> HttpError {
+    HttpError {
+        code: HttpErrorCode::Error400BadRequest,
+        message: format!("Invalid value for parameter {}.", name),
+    }
+}
+
+fn parse_list_params(
+    parameters: Option<Vec<(String, String)>>,
+) -> Result<ListGamesParams, HttpError> {
+    let mut list_params = ListGamesParams {
+        has_space: false,
+        mine: false,
+        friends: false,
+        cursor: None,
+        limit: DEFAULT_PAGE_SIZE,
+    };
+    for (key, value) in parameters.unwrap_or_default() {
+        match key.as_str() {
+            "has-space" => {
+                list_params.has_space = value.parse().map_err(|_| invalid_param(&key))?
+            }
+            "mine" => list_params.mine = value.parse().map_err(|_| invalid_param(&key))?,
+            "friends" => list_params.friends = value.parse().map_err(|_| invalid_param(&key))?,
+            "cursor" => {
+                let cursor = value
+                    .split_once('-')
+                    .and_then(|(created_at, game_id)| {
+                        Some((created_at.parse().ok()?, game_id.to_string()))
+                    })
+                    .ok_or_else(|| invalid_param(&key))?;
+                list_params.cursor = Some(cursor);
+            }
+            "limit" => {
+                let limit: usize = value.parse().map_err(|_| invalid_param(&key))?;
+                if limit == 0 {
+                    return Err(invalid_param(&key));
+                }
+                list_params.limit = limit.min(MAX_PAGE_SIZE);
+            }
+            // Ignored, the same as other endpoints
+            _ => (),
+        }
+    }
+    Ok(list_params)
+}
+
+pub fn list_games(
+    username: String,
+    parameters: Option<Vec<(String, String)>>,
+    db: Arc<impl Database>,
+) -> Result<String, HttpError> {
+    let list_params = parse_list_params(parameters)?;
+    let friend_list = if list_params.friends {
+        friends::get_friend_list(&username, &db)
+    } else {
+        BTreeSet::new()
+    };
+
+    // Players can still find their own unlisted and private games, so theirs
+    // are looked at as well as the public ones. Both are in lobby order.
+    let mut orders = index::public_games(list_params.has_space, &db);
+    orders.extend(index::member_games(&username, &db));
+
+    // Everything after the cursor, which doesn't have to still exist
+    let start = match &list_params.cursor {
+        Some((created_at, game_id)) => Bound::Excluded(index::order(*created_at, game_id)),
+        None => Bound::Unbounded,
+    };
+
+    // Only as many metas are read as it takes to fill the page
+    let mut metas: Vec<GameMeta> = orders
+        .range((start, Bound::Unbounded))
+        .filter_map(|order| meta::get_meta(index::order_game_id(order), &db).ok())
+        .filter(|meta| {
+            (meta.settings.visibility == Visibility::Public || meta.is_member(&username))
+                && (!list_params.has_space || meta.status() == GameStatus::Open)
+                && (!list_params.mine || meta.creator == username)
+                && (!list_params.friends || friend_list.iter().any(|friend| meta.is_player(friend)))
+        })
+        .take(list_params.limit + 1)
+        .collect();
+
+    let next_cursor = if metas.len() > list_params.limit {
+        metas.truncate(list_params.limit);
+        metas.last().map(cursor)
+    } else {
+        None
+    };
+
+    let rsp = ListGamesRsp {
+        games: metas
+            .into_iter()
+            .map(|meta| GameSummary {
+                status: meta.status(),
+                player_count: meta.player_count(),
+                game_id: meta.game_id,
+                creator: meta.creator,
+                max_players: meta.settings.max_players,
+                created_at: meta.created_at,
+                visibility: meta.settings.visibility,
+                has_password: meta.settings.password_hash.is_some(),
+            })
+            .collect(),
+        next_cursor,
+    };
+    Ok(serde_json::to_string(&rsp).unwrap())
+}
+
+#[test]
+fn test_list_games_pagination() {
+    let db = Arc::new(crate::LocalDatabase::new());
+    // Two games created in the same second, to check ties are broken
+    for (game_id, created_at) in [("aaa", 10), ("bbb", 30), ("ccc", 20), ("ddd", 20)] {
+        let mut game_meta = GameMeta::new(
+            game_id,
+            "james",
+            0,
+            meta::GameSettings {
+                visibility: Visibility::Public,
+                password_hash: None,
+                max_players: meta::MAX_PLAYERS,
+                pass_through: false,
+                min_move_interval_ms: 0,
+            },
+        );
+        game_meta.created_at = created_at;
+        meta::set_meta(&game_meta, &db);
+    }
+
+    let mut cursor = None;
+    let mut pages = vec![];
+    loop {
+        let mut parameters = vec![("limit".to_string(), "3".to_string())];
+        if let Some(cursor) = cursor {
+            parameters.push(("cursor".to_string(), cursor));
+        }
+        let rsp = list_games("james".to_string(), Some(parameters), Arc::clone(&db)).unwrap();
+        let rsp: serde_json::Value = serde_json::from_str(&rsp).unwrap();
+        let page: Vec<String> = rsp["games"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|game| game["game_id"].as_str().unwrap().to_string())
+            .collect();
+        pages.push(page);
+        match rsp["next_cursor"].as_str() {
+            Some(next_cursor) => cursor = Some(next_cursor.to_string()),
+            None => break,
+        }
+    }
+    assert_eq!(pages, vec![vec!["bbb", "ccc", "ddd"], vec!["aaa"]]);
+
+    // A cursor for a game that has since been deleted still works
+    meta::delete_meta("ddd", &db);
+    let parameters = vec![("cursor".to_string(), "20-ddd".to_string())];
+    let rsp = list_games("james".to_string(), Some(parameters), Arc::clone(&db)).unwrap();
+    assert!(rsp.contains("\"aaa\"") && !rsp.contains("\"ccc\""));
+
+    let parameters = vec![("cursor".to_string(), "ddd".to_string())];
+    assert!(list_games("james".to_string(), Some(parameters), db).is_err());
+}
@@ -0,0 +1,43 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+// A lock for each game, held while changing the world. Moves read the chunks,
+// check the tile is free and write them back, so without it a move made
+// straight away and one from the tick can both go ahead on stale chunks.
+static GAME_LOCKS: OnceLock<Mutex<HashMap<String, Arc<Mutex<()>>>>> = OnceLock::new();
+
+fn game_locks() -> &'static Mutex<HashMap<String, Arc<Mutex<()>>>> {
+    GAME_LOCKS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// Lock the returned mutex for as long as the game's chunks are being changed
+pub fn game_lock(game_id: &str) -> Arc<Mutex<()>> {
+    let mut game_locks = game_locks().lock().unwrap();
+    Arc::clone(
+        game_locks
+            .entry(game_id.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(()))),
+    )
+}
+
+// So locks don't pile up for games that are gone
+pub fn remove_game_lock(game_id: &str) {
+    game_locks().lock().unwrap().remove(game_id);
+}
+
+#[test]
+fn test_game_lock() {
+    // The same lock for the same game, a different one for others
+    let lock = game_lock("test-lock");
+    assert!(Arc::ptr_eq(&lock, &game_lock("test-lock")));
+    assert!(!Arc::ptr_eq(&lock, &game_lock("test-lock-other")));
+
+    let _guard = lock.lock().unwrap();
+    assert!(game_lock("test-lock").try_lock().is_err());
+    assert!(game_lock("test-lock-other").try_lock().is_ok());
+
+    remove_game_lock("test-lock");
+    remove_game_lock("test-lock-other");
+}
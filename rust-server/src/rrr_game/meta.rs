@@ -0,0 +1,211 @@
+use crate::{
+    http::{HttpError, HttpErrorCode},
+    rrr_game::index,
+    users, Database,
+};
+use jsonwebtoken::get_current_timestamp;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, sync::Arc};
+
+const GAME_META_NAME: &str = "rrr-game-meta";
+
+pub const MAX_PLAYERS: usize = 8;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum GameStatus {
+    Open,
+    Full,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    // Listed in the lobby, anyone can join
+    #[default]
+    Public,
+    // Not listed, anyone with the ID can join
+    Unlisted,
+    // Not listed, only people invited or with the password can join
+    Private,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GameSettings {
+    pub visibility: Visibility,
+    pub password_hash: Option<String>,
+    pub max_players: usize,
+    // Players can walk through each other, instead of blocking the way
+    #[serde(default)]
+    pub pass_through: bool,
+    // Moves closer together than this are turned down. 0 for no limit.
+    #[serde(default)]
+    pub min_move_interval_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RosterRole {
+    #[default]
+    Player,
+    // Can watch the game, but isn't in it
+    Spectator,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RosterEntry {
+    pub joined_at: u64,
+    #[serde(default)]
+    pub role: RosterRole,
+}
+
+// Everything about a game apart from the world itself, which is in the chunks.
+// The roster is the source of truth for who is in the game, the chunks just
+// say where they are.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GameMeta {
+    pub game_id: String,
+    pub creator: String,
+    pub created_at: u64,
+    // The world is made from this, so it's the same whichever order the
+    // chunks are made in
+    #[serde(default)]
+    pub seed: u64,
+    pub settings: GameSettings,
+    pub roster: BTreeMap<String, RosterEntry>,
+}
+impl GameMeta {
+    pub fn new(game_id: &str, creator: &str, seed: u64, settings: GameSettings) -> GameMeta {
+        let now = get_current_timestamp();
+        GameMeta {
+            game_id: game_id.to_string(),
+            creator: creator.to_string(),
+            created_at: now,
+            seed,
+            settings,
+            roster: BTreeMap::from([(
+                creator.to_string(),
+                RosterEntry {
+                    joined_at: now,
+                    role: RosterRole::Player,
+                },
+            )]),
+        }
+    }
+
+    // Spectators don't take up a space
+    pub fn player_count(&self) -> usize {
+        self.roster
+            .values()
+            .filter(|entry| entry.role == RosterRole::Player)
+            .count()
+    }
+
+    pub fn status(&self) -> GameStatus {
+        if self.player_count() >= self.settings.max_players {
+            GameStatus::Full
+        } else {
+            GameStatus::Open
+        }
+    }
+
+    // Players and spectators
+    pub fn is_member(&self, username: &str) -> bool {
+        self.roster.contains_key(username)
+    }
+
+    pub fn is_player(&self, username: &str) -> bool {
+        self.roster
+            .get(username)
+            .is_some_and(|entry| entry.role == RosterRole::Player)
+    }
+
+    pub fn check_member(&self, username: &str) -> Result<(), HttpError> {
+        if !self.is_member(username) {
+            return Err(HttpError {
+                code: HttpErrorCode::Error403Forbidden,
+                message: "User isn't in this game".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn check_player(&self, username: &str) -> Result<(), HttpError> {
+        if !self.is_player(username) {
+            return Err(HttpError {
+                code: HttpErrorCode::Error403Forbidden,
+                message: "User isn't playing this game".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn check_has_space(&self) -> Result<(), HttpError> {
+        if self.status() == GameStatus::Full {
+            return Err(HttpError {
+                code: HttpErrorCode::Error409Conflict,
+                message: "Game is full".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    // For joining without an invite. A password, if the game has one, is
+    // enough to get into a private game.
+    pub fn check_can_join(&self, password: Option<&str>) -> Result<(), HttpError> {
+        match (&self.settings.password_hash, password) {
+            (Some(password_hash), Some(password)) => {
+                if users::verify_password(password, password_hash) {
+                    Ok(())
+                } else {
+                    Err(HttpError {
+                        code: HttpErrorCode::Error403Forbidden,
+                        message: "Game password incorrect".to_string(),
+                    })
+                }
+            }
+            (Some(_), None) => Err(HttpError {
+                code: HttpErrorCode::Error403Forbidden,
+                message: "Game needs a password to join".to_string(),
+            }),
+            (None, _) if self.settings.visibility == Visibility::Private => Err(HttpError {
+                code: HttpErrorCode::Error403Forbidden,
+                message: "Game is private, you need an invite to join".to_string(),
+            }),
+            (None, _) => Ok(()),
+        }
+    }
+}
+
+fn game_meta_key(game_id: &str) -> String {
+    GAME_META_NAME.to_string() + ":" + game_id
+}
+
+pub fn get_meta(game_id: &str, db: &Arc<impl Database>) -> Result<GameMeta, HttpError> {
+    match db.get(&game_meta_key(game_id)) {
+        Some(meta) => Ok(serde_json::from_str(&meta).unwrap()),
+        None => Err(HttpError {
+            code: HttpErrorCode::Error404NotFround,
+            message: "Game doesn't exist".to_string(),
+        }),
+    }
+}
+
+// Also keeps the game index in step, so every change to a game has to go
+// through here
+pub fn set_meta(meta: &GameMeta, db: &Arc<impl Database>) {
+    let _guard = index::index_lock().lock().unwrap();
+    let old_meta = get_meta(&meta.game_id, db).ok();
+    db.set(
+        game_meta_key(&meta.game_id),
+        serde_json::to_string(meta).unwrap(),
+    );
+    index::reindex(old_meta.as_ref(), Some(meta), db);
+}
+
+pub fn delete_meta(game_id: &str, db: &Arc<impl Database>) {
+    let _guard = index::index_lock().lock().unwrap();
+    let old_meta = get_meta(game_id, db).ok();
+    db.del(&game_meta_key(game_id));
+    index::reindex(old_meta.as_ref(), None, db);
+}
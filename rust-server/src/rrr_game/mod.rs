@@ -7,8 +7,49 @@ pub use create::create_game;
 
 mod coord;
 
+mod tiles;
+
+mod biomes;
+
+mod spawn;
+
 mod get;
 pub use get::get_gamestate;
 
 mod action;
 pub use action::do_action;
+
+mod delete;
+pub use delete::{delete_game, remove_player};
+
+mod players;
+pub use players::{join_game, leave_game};
+
+mod meta;
+
+mod position;
+
+mod path;
+
+mod explored;
+
+mod lock;
+
+mod queue;
+pub use queue::{cancel_queued_moves, get_queued_moves};
+
+mod timers;
+
+mod tick;
+pub use tick::tick_games;
+
+mod spectators;
+pub use spectators::{remove_user_spectating, spectate_game, stop_spectating};
+
+mod index;
+
+mod lobby;
+pub use lobby::list_games;
+
+mod invites;
+pub use invites::{accept_invite, decline_invite, get_invites, invite_player, remove_user_invites};
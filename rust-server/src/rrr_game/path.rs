@@ -0,0 +1,229 @@
+use crate::{
+    rrr_game::{action::Move, coord, create, tiles, CHUNK_LENGTH, GAME_NAME},
+    Database,
+};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    sync::Arc,
+};
+
+// Stops a search for somewhere unreachable from looking at the whole world
+const MAX_SEARCHED_TILES: usize = 10_000;
+
+// Reads chunks as a search reaches them, at most once each
+pub struct Terrain<'a, D: Database> {
+    game_id: &'a str,
+    db: &'a Arc<D>,
+    chunks: HashMap<coord::GamestateCoord, Option<create::GamestateChunk>>,
+}
+impl<'a, D: Database> Terrain<'a, D> {
+    pub fn new(game_id: &'a str, db: &'a Arc<D>) -> Terrain<'a, D> {
+        Terrain {
+            game_id,
+            db,
+            chunks: HashMap::new(),
+        }
+    }
+
+    // None if the chunk it's in hasn't been made yet
+    pub fn get_chunk(&mut self, user_coord: &coord::UserCoord) -> Option<&create::GamestateChunk> {
+        let gamestate_coord = coord::user_coord_to_gamestate_coord(user_coord, CHUNK_LENGTH);
+        self.chunks
+            .entry(gamestate_coord.clone())
+            .or_insert_with(|| {
+                self.db
+                    .get(
+                        &(GAME_NAME.to_string() + ":" + self.game_id + ":" + &gamestate_coord.id()),
+                    )
+                    .map(|chunk| serde_json::from_str(&chunk).unwrap())
+            })
+            .as_ref()
+    }
+
+    pub fn get_tile(&mut self, user_coord: &coord::UserCoord) -> Option<char> {
+        let (x, y) = coord::get_terrain_index(user_coord, CHUNK_LENGTH);
+        Some(self.get_chunk(user_coord)?.terrain[y][x])
+    }
+
+    // How much it costs to walk onto the tile, None if it can't be. Chunks
+    // that haven't been made yet can't be walked on.
+    pub fn movement_cost(&mut self, user_coord: &coord::UserCoord) -> Option<u32> {
+        let tile = tiles::get_tile(self.get_tile(user_coord)?)?;
+        tile.walkable.then_some(tile.movement_cost)
+    }
+}
+
+fn distance(a: &coord::UserCoord, b: &coord::UserCoord) -> u32 {
+    a.x.abs_diff(b.x) + a.y.abs_diff(b.y)
+}
+
+// A* over the walkable tiles, across chunk borders. Returns the cheapest moves
+// to get from `start` to `target`, or None if there's no way there. Every tile
+// costs at least 1, so the distance never overestimates.
+pub fn find_path(
+    game_id: &str,
+    start: &coord::UserCoord,
+    target: &coord::UserCoord,
+    db: &Arc<impl Database>,
+) -> Option<Vec<Move>> {
+    let mut terrain = Terrain::new(game_id, db);
+    terrain.movement_cost(target)?;
+
+    // How each tile was best reached, and what it cost from the start
+    let mut came_from: HashMap<coord::UserCoord, (coord::UserCoord, Move)> = HashMap::new();
+    let mut cost = HashMap::from([(start.clone(), 0)]);
+    // Lowest estimated total cost first, as (estimate, cost so far, x, y)
+    let mut to_search = BinaryHeap::from([Reverse((distance(start, target), 0, start.x, start.y))]);
+
+    while let Some(Reverse((_, start_cost, x, y))) = to_search.pop() {
+        let user_coord = coord::UserCoord { x, y };
+        if user_coord == *target {
+            let mut moves = vec![];
+            let mut step = user_coord;
+            while let Some((previous, r#move)) = came_from.get(&step) {
+                moves.push(*r#move);
+                step = previous.clone();
+            }
+            moves.reverse();
+            return Some(moves);
+        }
+        // Already found a shorter way here
+        if start_cost > cost[&user_coord] {
+            continue;
+        }
+        if cost.len() > MAX_SEARCHED_TILES {
+            return None;
+        }
+
+        for r#move in Move::ALL {
+            let next = r#move.destination(&user_coord);
+            let Some(movement_cost) = terrain.movement_cost(&next) else {
+                continue;
+            };
+            let next_cost = start_cost + movement_cost;
+            if cost.get(&next).is_some_and(|&cost| cost <= next_cost) {
+                continue;
+            }
+            cost.insert(next.clone(), next_cost);
+            came_from.insert(next.clone(), (user_coord.clone(), r#move));
+            to_search.push(Reverse((
+                next_cost + distance(&next, target),
+                next_cost,
+                next.x,
+                next.y,
+            )));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+fn test_game(rows: &[&str], db: &Arc<crate::LocalDatabase>) {
+    // Just the centre chunk, so everything else is off the edge of the world
+    let chunk = create::GamestateChunk {
+        coord: coord::GamestateCoord { x: 0, y: 0 },
+        terrain: rows.iter().map(|row| row.chars().collect()).collect(),
+        users: HashMap::new(),
+    };
+    db.set(
+        GAME_NAME.to_string() + ":test:0-0",
+        serde_json::to_string(&chunk).unwrap(),
+    );
+}
+
+#[test]
+fn test_find_path() {
+    let db = Arc::new(crate::LocalDatabase::new());
+    // (0, 0) is the middle, (-4, -4) the top left
+    test_game(
+        &[
+            "GGGGGGGGG",
+            "GRRRRRRRG",
+            "GGGGGGGRG",
+            "GRRRRRGRG",
+            "GRGGGRGRG",
+            "GRGRRRGRG",
+            "GRGGGGGRG",
+            "GRRRRRRRG",
+            "GGGGGGGGG",
+        ],
+        &db,
+    );
+    let start = coord::UserCoord { x: 0, y: 0 };
+
+    // Already there
+    assert_eq!(find_path("test", &start, &start, &db), Some(vec![]));
+
+    // Round the spiral
+    let moves = find_path("test", &start, &coord::UserCoord { x: -4, y: -4 }, &db).unwrap();
+    assert_eq!(moves.len(), 20);
+    let end = moves.iter().fold(start.clone(), |user_coord, r#move| {
+        r#move.destination(&user_coord)
+    });
+    assert_eq!(end, coord::UserCoord { x: -4, y: -4 });
+
+    // Rock, and off the edge of the world
+    assert_eq!(
+        find_path("test", &start, &coord::UserCoord { x: -3, y: -3 }, &db),
+        None
+    );
+    assert_eq!(
+        find_path("test", &start, &coord::UserCoord { x: 5, y: 0 }, &db),
+        None
+    );
+}
+
+#[test]
+fn test_find_path_no_way_through() {
+    let db = Arc::new(crate::LocalDatabase::new());
+    test_game(
+        &[
+            "GGGGGGGGG",
+            "GGGGGGGGG",
+            "GGGWWWGGG",
+            "GGGWGWGGG",
+            "GGGWWWGGG",
+            "GGGGGGGGG",
+            "GGGGGGGGG",
+            "GGGGGGGGG",
+            "GGGGGGGGG",
+        ],
+        &db,
+    );
+
+    // Walled in by water
+    let start = coord::UserCoord { x: 0, y: -1 };
+    assert_eq!(
+        find_path("test", &start, &coord::UserCoord { x: 4, y: 4 }, &db),
+        None
+    );
+    assert_eq!(
+        find_path("test", &coord::UserCoord { x: 4, y: 4 }, &start, &db),
+        None
+    );
+}
+
+#[test]
+fn test_find_path_cost() {
+    let db = Arc::new(crate::LocalDatabase::new());
+    test_game(
+        &[
+            "GGGGGGGGG",
+            "GGGGGGGGG",
+            "GGGGGGGGG",
+            "GGGGGGGGG",
+            "GGGGGFFFG",
+            "GGGGGGGGG",
+            "GGGGGGGGG",
+            "GGGGGGGGG",
+            "GGGGGGGGG",
+        ],
+        &db,
+    );
+
+    // Round the forest is further, but quicker
+    let start = coord::UserCoord { x: 0, y: 0 };
+    let moves = find_path("test", &start, &coord::UserCoord { x: 4, y: 0 }, &db).unwrap();
+    assert_eq!(moves.len(), 6);
+}
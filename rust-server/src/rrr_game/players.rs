@@ -0,0 +1,113 @@
+use crate::{
+    http::{HttpError, HttpErrorCode},
+    rrr_game::{coord, create, delete, explored, get, lock, meta, spawn, CHUNK_LENGTH, GAME_NAME},
+    users, Database,
+};
+use jsonwebtoken::get_current_timestamp;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// The body is optional, as only games with a password need it
+#[derive(Serialize, Deserialize, Default)]
+struct JoinGameRq {
+    password: Option<String>,
+}
+
+pub fn join_game(
+    username: String,
+    body: String,
+    game_id: String,
+    db: Arc<impl Database>,
+) -> Result<String, HttpError> {
+    let body: JoinGameRq = if body.is_empty() {
+        JoinGameRq::default()
+    } else if let Ok(valid_body) = serde_json::from_str(&body) {
+        valid_body
+    } else {
+        return Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: "Join game body has invalid format.".to_string(),
+        });
+    };
+    let game_meta = meta::get_meta(&game_id, &db)?;
+    game_meta.check_can_join(body.password.as_deref())?;
+
+    add_player(username, &game_id, db)
+}
+
+// Used directly by invites, as being invited gets round the password
+pub fn add_player(
+    username: String,
+    game_id: &str,
+    db: Arc<impl Database>,
+) -> Result<String, HttpError> {
+    // The meta is read again under the lock, so two players can't both take
+    // the last space
+    let game_lock = lock::game_lock(game_id);
+    let _guard = game_lock.lock().unwrap();
+    let mut game_meta = meta::get_meta(game_id, &db)?;
+    let game_id = game_meta.game_id.clone();
+
+    // Check if user is in a game already
+    let curr_game_info = users::get_user_curr_game_info(&username, Arc::clone(&db), GAME_NAME)?;
+    if curr_game_info.is_some() {
+        return Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: "User is already in a game".to_string(),
+        });
+    }
+    game_meta.check_has_space()?;
+
+    // Players join near the centre, the same as the creator
+    let user_coord = spawn::place_player(&game_id, &username, &db)?;
+    game_meta.roster.insert(
+        username.clone(),
+        meta::RosterEntry {
+            joined_at: get_current_timestamp(),
+            role: meta::RosterRole::Player,
+        },
+    );
+    meta::set_meta(&game_meta, &db);
+
+    users::set_user_curr_game_info(
+        &username,
+        Arc::clone(&db),
+        GAME_NAME,
+        users::UserGameInfo {
+            game_id: game_id.clone(),
+            chunk_id: coord::user_coord_to_gamestate_coord(&user_coord, CHUNK_LENGTH).id(),
+        },
+    )?;
+    users::record_game_played(&username, Arc::clone(&db))?;
+    let explored_new_tile = explored::mark_explored(&game_id, &username, &user_coord, &db);
+    users::record_move(&username, 0, explored_new_tile, Arc::clone(&db))?;
+
+    let visible_gamestate = get::get_visible_gamestate(&user_coord, username, false, &game_id, db)?;
+    let rsp = create::CreateGameRsp {
+        game_id,
+        user_coord,
+        visible_gamestate,
+    };
+    Ok(serde_json::to_string(&rsp).unwrap())
+}
+
+pub fn leave_game(
+    username: String,
+    game_id: String,
+    db: Arc<impl Database>,
+) -> Result<String, HttpError> {
+    meta::get_meta(&game_id, &db)?;
+    let game_lock = lock::game_lock(&game_id);
+    let _guard = game_lock.lock().unwrap();
+    if !meta::get_meta(&game_id, &db)?.is_player(&username) {
+        return Err(HttpError {
+            code: HttpErrorCode::Error404NotFround,
+            message: "User isn't in this game".to_string(),
+        });
+    }
+
+    delete::remove_player_locked(&game_id, &username, Arc::clone(&db));
+    users::remove_user_curr_game(&username, GAME_NAME, &game_id, db);
+
+    Ok("".to_string())
+}
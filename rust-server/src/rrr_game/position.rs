@@ -0,0 +1,182 @@
+use crate::{
+    http::{HttpError, HttpErrorCode},
+    rrr_game::{action::Outcome, coord, create, queue, GAME_NAME},
+    Database,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, ops::Range, sync::Arc};
+
+const PLAYER_INDEX_NAME: &str = "rrr-game-player";
+
+// Where each player is, so requests don't need to say and the server doesn't
+// have to trust them
+#[derive(Serialize, Deserialize)]
+struct PlayerEntry {
+    user_coord: coord::UserCoord,
+    // When they last moved, in ms since the epoch. 0 if they haven't yet.
+    #[serde(default)]
+    moved_at_ms: u128,
+    // The last number given to one of their actions, so clients can tell
+    // which of their predictions a response is for
+    #[serde(default)]
+    action_seq: u64,
+    // What happened to their queued actions, by number, as they're only
+    // tried on the tick. Just the latest few are kept.
+    #[serde(default)]
+    queued_outcomes: BTreeMap<u64, Outcome>,
+}
+
+fn player_key(game_id: &str, username: &str) -> String {
+    PLAYER_INDEX_NAME.to_string() + ":" + game_id + ":" + username
+}
+
+// Keeps the action numbers going, they're handed out without the game's lock
+fn set_entry(
+    game_id: &str,
+    username: &str,
+    user_coord: &coord::UserCoord,
+    moved_at_ms: u128,
+    db: &Arc<impl Database>,
+) {
+    db.update(&player_key(game_id, username), &mut |entry| {
+        let entry = match entry {
+            Some(entry) => PlayerEntry {
+                user_coord: user_coord.clone(),
+                moved_at_ms,
+                ..serde_json::from_str(&entry).unwrap()
+            },
+            None => PlayerEntry {
+                user_coord: user_coord.clone(),
+                moved_at_ms,
+                action_seq: 0,
+                queued_outcomes: BTreeMap::new(),
+            },
+        };
+        Some(serde_json::to_string(&entry).unwrap())
+    });
+}
+
+// Puts the player somewhere, e.g. when they join
+pub fn set_position(
+    game_id: &str,
+    username: &str,
+    user_coord: &coord::UserCoord,
+    db: &Arc<impl Database>,
+) {
+    set_entry(game_id, username, user_coord, 0, db);
+}
+
+pub fn set_moved_position(
+    game_id: &str,
+    username: &str,
+    user_coord: &coord::UserCoord,
+    moved_at_ms: u128,
+    db: &Arc<impl Database>,
+) {
+    set_entry(game_id, username, user_coord, moved_at_ms, db);
+}
+
+// Numbers the player's next `count` actions, counting up from 1 for each
+// player in each game
+pub fn next_action_seqs(
+    game_id: &str,
+    username: &str,
+    count: u64,
+    db: &Arc<impl Database>,
+) -> Result<Range<u64>, HttpError> {
+    // Makes sure they have an entry
+    find_player(game_id, username, db)?;
+
+    let mut action_seqs = None;
+    db.update(&player_key(game_id, username), &mut |entry| {
+        let mut entry: PlayerEntry = serde_json::from_str(&entry?).unwrap();
+        action_seqs = Some(entry.action_seq + 1..entry.action_seq + 1 + count);
+        entry.action_seq += count;
+        Some(serde_json::to_string(&entry).unwrap())
+    });
+    action_seqs.ok_or(HttpError {
+        code: HttpErrorCode::Error500InternalServerError,
+        message: "Can't find user in the gamestate".to_string(),
+    })
+}
+
+// Nothing is kept for players that have gone
+pub fn record_queued_outcome(
+    game_id: &str,
+    username: &str,
+    action_seq: u64,
+    outcome: Outcome,
+    db: &Arc<impl Database>,
+) {
+    db.update(&player_key(game_id, username), &mut |entry| {
+        let mut entry: PlayerEntry = serde_json::from_str(&entry?).unwrap();
+        entry.queued_outcomes.insert(action_seq, outcome);
+        while entry.queued_outcomes.len() > queue::MAX_QUEUED_MOVES {
+            entry.queued_outcomes.pop_first();
+        }
+        Some(serde_json::to_string(&entry).unwrap())
+    });
+}
+
+pub fn get_queued_outcomes(
+    game_id: &str,
+    username: &str,
+    db: &Arc<impl Database>,
+) -> BTreeMap<u64, Outcome> {
+    db.get(&player_key(game_id, username))
+        .map(|entry| {
+            serde_json::from_str::<PlayerEntry>(&entry)
+                .unwrap()
+                .queued_outcomes
+        })
+        .unwrap_or_default()
+}
+
+pub fn get_moved_at_ms(game_id: &str, username: &str, db: &Arc<impl Database>) -> u128 {
+    db.get(&player_key(game_id, username))
+        .map(|entry| {
+            serde_json::from_str::<PlayerEntry>(&entry)
+                .unwrap()
+                .moved_at_ms
+        })
+        .unwrap_or(0)
+}
+
+pub fn remove_position(game_id: &str, username: &str, db: &Arc<impl Database>) {
+    db.del(&player_key(game_id, username));
+}
+
+pub fn remove_game_positions(game_id: &str, db: &Arc<impl Database>) {
+    for key in db.keys(&(PLAYER_INDEX_NAME.to_string() + ":" + game_id + ":")) {
+        db.del(&key);
+    }
+}
+
+// Players without an entry, e.g. from before the index existed, are looked for
+// in the chunks and then remembered
+pub fn find_player(
+    game_id: &str,
+    username: &str,
+    db: &Arc<impl Database>,
+) -> Result<coord::UserCoord, HttpError> {
+    if let Some(entry) = db.get(&player_key(game_id, username)) {
+        let entry: PlayerEntry = serde_json::from_str(&entry).unwrap();
+        return Ok(entry.user_coord);
+    }
+
+    for key in db.keys(&(GAME_NAME.to_string() + ":" + game_id + ":")) {
+        if let Some(gamestate_chunk) = db.get(&key) {
+            let gamestate_chunk: create::GamestateChunk =
+                serde_json::from_str(&gamestate_chunk).unwrap();
+            if let Some(user_coord) = gamestate_chunk.users.get(username) {
+                set_position(game_id, username, user_coord, db);
+                return Ok(user_coord.clone());
+            }
+        }
+    }
+
+    Err(HttpError {
+        code: HttpErrorCode::Error500InternalServerError,
+        message: "Can't find user in the gamestate".to_string(),
+    })
+}
@@ -0,0 +1,214 @@
+use crate::{
+    http::{HttpError, HttpErrorCode},
+    rrr_game::{action, meta, position},
+    Database,
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+const ACTION_QUEUE_NAME: &str = "rrr-game-queue";
+
+// Per player, enough to walk a few chunks
+pub const MAX_QUEUED_MOVES: usize = 100;
+
+// Orders the queued actions across all games. Each action has its own key, so
+// queuing one never races with the tick taking the others.
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Serialize, Deserialize)]
+struct QueuedAction {
+    username: String,
+    r#move: action::Move,
+    // Given out when it's queued, 0 for ones queued before they were
+    #[serde(default)]
+    action_seq: u64,
+}
+
+fn queue_prefix(game_id: &str) -> String {
+    ACTION_QUEUE_NAME.to_string() + ":" + game_id + ":"
+}
+
+#[derive(Serialize)]
+struct QueueActionRsp {
+    // How many of the user's actions are waiting, including these
+    queued: usize,
+    // What the moves just queued are numbered, in order
+    action_seqs: Vec<u64>,
+}
+
+#[derive(Serialize)]
+struct QueuedMovesRsp {
+    moves: Vec<action::Move>,
+    // What the waiting moves are numbered, in order
+    action_seqs: Vec<u64>,
+    // What happened to the latest ones that have been taken off the queue
+    outcomes: BTreeMap<u64, action::Outcome>,
+}
+
+pub fn queue_moves(
+    username: &str,
+    moves: &[action::Move],
+    game_id: &str,
+    db: &Arc<impl Database>,
+) -> Result<String, HttpError> {
+    let queued = get_user_queue(username, game_id, db).len();
+    if queued + moves.len() > MAX_QUEUED_MOVES {
+        return Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: format!("Can't queue more than {} moves", MAX_QUEUED_MOVES),
+        });
+    }
+
+    let action_seqs: Vec<u64> =
+        position::next_action_seqs(game_id, username, moves.len() as u64, db)?.collect();
+    for (r#move, action_seq) in moves.iter().zip(&action_seqs) {
+        // Zero padded, so the keys sort in the order they were queued
+        let seq = NEXT_SEQ.fetch_add(1, Ordering::SeqCst);
+        let queued_action = QueuedAction {
+            username: username.to_string(),
+            r#move: *r#move,
+            action_seq: *action_seq,
+        };
+        db.set(
+            format!("{}{:020}", queue_prefix(game_id), seq),
+            serde_json::to_string(&queued_action).unwrap(),
+        );
+    }
+
+    let rsp = QueueActionRsp {
+        queued: queued + moves.len(),
+        action_seqs,
+    };
+    Ok(serde_json::to_string(&rsp).unwrap())
+}
+
+pub fn get_queued_moves(
+    username: String,
+    game_id: String,
+    db: Arc<impl Database>,
+) -> Result<String, HttpError> {
+    meta::get_meta(&game_id, &db)?.check_player(&username)?;
+
+    let (moves, action_seqs) = get_user_queue(&username, &game_id, &db)
+        .into_values()
+        .map(|queued_action| (queued_action.r#move, queued_action.action_seq))
+        .unzip();
+    let rsp = QueuedMovesRsp {
+        moves,
+        action_seqs,
+        outcomes: position::get_queued_outcomes(&game_id, &username, &db),
+    };
+    Ok(serde_json::to_string(&rsp).unwrap())
+}
+
+pub fn cancel_queued_moves(
+    username: String,
+    game_id: String,
+    db: Arc<impl Database>,
+) -> Result<String, HttpError> {
+    meta::get_meta(&game_id, &db)?.check_player(&username)?;
+
+    clear_user_queue(&username, &game_id, &db);
+    Ok("".to_string())
+}
+
+pub fn clear_user_queue(username: &str, game_id: &str, db: &Arc<impl Database>) {
+    for (key, queued_action) in get_user_queue(username, game_id, db) {
+        db.del(&key);
+        position::record_queued_outcome(
+            game_id,
+            username,
+            queued_action.action_seq,
+            action::Outcome::Cancelled,
+            db,
+        );
+    }
+}
+
+fn get_queue(game_id: &str, db: &Arc<impl Database>) -> BTreeMap<String, QueuedAction> {
+    db.keys(&queue_prefix(game_id))
+        .into_iter()
+        .filter_map(|key| {
+            let queued_action = serde_json::from_str(&db.get(&key)?).unwrap();
+            Some((key, queued_action))
+        })
+        .collect()
+}
+
+fn get_user_queue(
+    username: &str,
+    game_id: &str,
+    db: &Arc<impl Database>,
+) -> BTreeMap<String, QueuedAction> {
+    get_queue(game_id, db)
+        .into_iter()
+        .filter(|(_, queued_action)| queued_action.username == username)
+        .collect()
+}
+
+// Applies the first thing each player has queued, in the order it was queued.
+// The rest wait for the following ticks, so queued paths are walked one step
+// at a time. Moves turned down for being too soon are tried again next tick,
+// any other move that doesn't go through cancels the rest of the player's
+// queue, as it was planned from where they'd have been. The caller has to
+// hold the game's lock.
+pub fn apply_queued_actions(game_id: &str, db: &Arc<impl Database>) {
+    let game_meta = meta::get_meta(game_id, db);
+    let mut moved = HashSet::new();
+    for (key, queued_action) in get_queue(game_id, db) {
+        if moved.contains(&queued_action.username) {
+            continue;
+        }
+
+        // Players can leave, or the game be deleted, while their actions wait
+        let is_player = game_meta
+            .as_ref()
+            .is_ok_and(|game_meta| game_meta.is_player(&queued_action.username));
+        if !is_player {
+            db.del(&key);
+            continue;
+        }
+        moved.insert(queued_action.username.clone());
+        let outcome = match action::move_player(
+            &queued_action.username,
+            queued_action.r#move,
+            queued_action.action_seq,
+            game_id,
+            Arc::clone(db),
+        ) {
+            Ok(rsp) if rsp.outcome == action::Outcome::RateLimited => continue,
+            Ok(rsp) => rsp.outcome,
+            Err(error) => {
+                warn!(
+                    "Queued action for {:?} in {:?} failed: {:?}",
+                    queued_action.username, game_id, error.message
+                );
+                action::Outcome::Cancelled
+            }
+        };
+        db.del(&key);
+        position::record_queued_outcome(
+            game_id,
+            &queued_action.username,
+            queued_action.action_seq,
+            outcome,
+            db,
+        );
+        if outcome != action::Outcome::Moved {
+            clear_user_queue(&queued_action.username, game_id, db);
+        }
+    }
+}
+
+pub fn remove_game_queue(game_id: &str, db: &Arc<impl Database>) {
+    for key in db.keys(&queue_prefix(game_id)) {
+        db.del(&key);
+    }
+}
@@ -0,0 +1,269 @@
+use crate::{
+    http::{HttpError, HttpErrorCode},
+    rrr_game::{action::Move, coord, create, path, position, tiles, CHUNK_LENGTH, GAME_NAME},
+    Database,
+};
+use std::{collections::HashSet, sync::Arc};
+#[cfg(test)]
+use {
+    crate::rrr_game::{biomes, meta},
+    std::collections::HashMap,
+};
+
+// How many tiles a player needs to be able to get to from where they start,
+// so they aren't boxed in. More than the grass every world starts with in the
+// middle, so the terrain around it has to be open too.
+pub const MIN_REACHABLE_AREA: usize = 40;
+
+// Spawn points are in the centre chunk, so it and its neighbours always exist
+fn spawn_candidates() -> Vec<coord::UserCoord> {
+    let radius = (CHUNK_LENGTH / 2) as i32;
+    let mut candidates: Vec<coord::UserCoord> = (-radius..=radius)
+        .flat_map(|y| (-radius..=radius).map(move |x| coord::UserCoord { x, y }))
+        .collect();
+    // Closest to the centre first, in rings
+    candidates.sort_by_key(|user_coord| {
+        (
+            user_coord.x.abs().max(user_coord.y.abs()),
+            user_coord.x.abs() + user_coord.y.abs(),
+            user_coord.y,
+            user_coord.x,
+        )
+    });
+    candidates
+}
+
+// How many tiles can be walked to from `start`, counting up to `limit`. Goes
+// across chunk borders, but not into chunks that haven't been made yet. Other
+// players are in the way too, as they may not move.
+fn reachable_area(
+    terrain: &mut path::Terrain<impl Database>,
+    start: &coord::UserCoord,
+    username: &str,
+    limit: usize,
+) -> usize {
+    let mut seen = HashSet::from([start.clone()]);
+    let mut to_visit = vec![start.clone()];
+    while let Some(user_coord) = to_visit.pop() {
+        if seen.len() >= limit {
+            break;
+        }
+        for r#move in Move::ALL {
+            let next = r#move.destination(&user_coord);
+            let is_occupied = terrain
+                .get_chunk(&next)
+                .is_some_and(|chunk| chunk.get_occupant(&next, username).is_some());
+            if !seen.contains(&next) && !is_occupied && terrain.movement_cost(&next).is_some() {
+                seen.insert(next.clone());
+                to_visit.push(next);
+            }
+        }
+    }
+    seen.len()
+}
+
+// Where a player joining the game starts. The closest grass tile to the centre
+// that no one else is on, and that isn't boxed in.
+pub fn find_spawn(
+    game_id: &str,
+    username: &str,
+    db: &Arc<impl Database>,
+) -> Result<coord::UserCoord, HttpError> {
+    let mut terrain = path::Terrain::new(game_id, db);
+    for candidate in spawn_candidates() {
+        if terrain.get_tile(&candidate) != Some(tiles::TILE_GRASS) {
+            continue;
+        }
+        let is_occupied = terrain
+            .get_chunk(&candidate)
+            .is_some_and(|chunk| chunk.get_occupant(&candidate, username).is_some());
+        if is_occupied {
+            continue;
+        }
+        let area = reachable_area(&mut terrain, &candidate, username, MIN_REACHABLE_AREA);
+        if area >= MIN_REACHABLE_AREA {
+            return Ok(candidate);
+        }
+    }
+
+    Err(HttpError {
+        code: HttpErrorCode::Error503ServiceUnavailable,
+        message: "Can't find anywhere to start in this game".to_string(),
+    })
+}
+
+// Puts a player joining the game at their spawn point. The caller has to hold
+// the game's lock, so no one else can take the spot in the meantime.
+pub fn place_player(
+    game_id: &str,
+    username: &str,
+    db: &Arc<impl Database>,
+) -> Result<coord::UserCoord, HttpError> {
+    let user_coord = find_spawn(game_id, username, db)?;
+
+    let gamestate_coord = coord::user_coord_to_gamestate_coord(&user_coord, CHUNK_LENGTH);
+    let key = GAME_NAME.to_string() + ":" + game_id + ":" + &gamestate_coord.id();
+    let mut chunk: create::GamestateChunk = serde_json::from_str(&db.get(&key).unwrap()).unwrap();
+    chunk.users.insert(username.to_string(), user_coord.clone());
+    db.set(key, serde_json::to_string(&chunk).unwrap());
+    position::set_position(game_id, username, &user_coord, db);
+
+    Ok(user_coord)
+}
+
+#[cfg(test)]
+type GenerateTerrain = fn(u64, &coord::GamestateCoord) -> Vec<Vec<char>>;
+
+#[cfg(test)]
+fn test_game(seed: u64, generate_terrain: GenerateTerrain, db: &Arc<crate::LocalDatabase>) {
+    // The chunks a new game starts with
+    for x in -1..=1 {
+        for y in -1..=1 {
+            let gamestate_coord = coord::GamestateCoord { x, y };
+            let chunk = create::GamestateChunk {
+                terrain: generate_terrain(seed, &gamestate_coord),
+                coord: gamestate_coord,
+                users: HashMap::new(),
+            };
+            db.set(
+                GAME_NAME.to_string() + ":test:" + &chunk.get_id(),
+                serde_json::to_string(&chunk).unwrap(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+fn add_to_chunk(username: &str, user_coord: &coord::UserCoord, db: &Arc<crate::LocalDatabase>) {
+    let key = GAME_NAME.to_string() + ":test:0-0";
+    let mut chunk: create::GamestateChunk = serde_json::from_str(&db.get(&key).unwrap()).unwrap();
+    chunk.users.insert(username.to_string(), user_coord.clone());
+    db.set(key, serde_json::to_string(&chunk).unwrap());
+}
+
+#[test]
+fn test_find_spawn() {
+    // Whatever the world looks like, everyone gets somewhere to start that's
+    // grass, open and theirs
+    for seed in 0..100 {
+        let db = Arc::new(crate::LocalDatabase::new());
+        test_game(seed, biomes::generate_terrain, &db);
+
+        let mut spawns = HashSet::new();
+        for player in 0..meta::MAX_PLAYERS {
+            let username = format!("player{}", player);
+            let spawn = find_spawn("test", &username, &db).unwrap();
+
+            let mut terrain = path::Terrain::new("test", &db);
+            assert_eq!(terrain.get_tile(&spawn), Some(tiles::TILE_GRASS));
+            let area = reachable_area(&mut terrain, &spawn, &username, usize::MAX);
+            assert!(area >= MIN_REACHABLE_AREA);
+            assert!(spawns.insert(spawn.clone()), "Spawned on someone");
+            add_to_chunk(&username, &spawn, &db);
+        }
+        // The first player is in the middle
+        assert!(spawns.contains(&coord::UserCoord { x: 0, y: 0 }));
+    }
+}
+
+#[test]
+fn test_find_spawn_natural_terrain() {
+    // Without the open square in the middle, some grass is boxed in by the
+    // terrain or other players, and passed over
+    let mut boxed_in = 0;
+    for seed in 0..100 {
+        let db = Arc::new(crate::LocalDatabase::new());
+        test_game(seed, biomes::generate_natural_terrain, &db);
+
+        for player in 0..meta::MAX_PLAYERS {
+            let username = format!("player{}", player);
+            let Ok(spawn) = find_spawn("test", &username, &db) else {
+                break;
+            };
+
+            let mut terrain = path::Terrain::new("test", &db);
+            assert_eq!(terrain.get_tile(&spawn), Some(tiles::TILE_GRASS));
+            let area = reachable_area(&mut terrain, &spawn, &username, usize::MAX);
+            assert!(area >= MIN_REACHABLE_AREA);
+
+            // Every free grass tile closer to the middle is too closed in
+            for candidate in spawn_candidates()
+                .into_iter()
+                .take_while(|candidate| *candidate != spawn)
+            {
+                let is_free = terrain.get_tile(&candidate) == Some(tiles::TILE_GRASS)
+                    && terrain
+                        .get_chunk(&candidate)
+                        .is_some_and(|chunk| chunk.get_occupant(&candidate, &username).is_none());
+                if is_free {
+                    let area = reachable_area(&mut terrain, &candidate, &username, usize::MAX);
+                    assert!(area < MIN_REACHABLE_AREA);
+                    boxed_in += 1;
+                }
+            }
+            add_to_chunk(&username, &spawn, &db);
+        }
+    }
+    assert!(boxed_in > 0);
+}
+
+#[test]
+fn test_find_spawn_boxed_in() {
+    let db = Arc::new(crate::LocalDatabase::new());
+    let chunk = create::GamestateChunk {
+        coord: coord::GamestateCoord { x: 0, y: 0 },
+        terrain: [
+            "GGGGGGGGG",
+            "GGGGGGGGG",
+            "GGGGGGGGG",
+            "GGGRWRGGG",
+            "GGGWGWGGG",
+            "GGGRWRGGG",
+            "GGGGGGGGG",
+            "GGGGGGGGG",
+            "GGGGGGGGG",
+        ]
+        .iter()
+        .map(|row| row.chars().collect())
+        .collect(),
+        users: HashMap::new(),
+    };
+    db.set(
+        GAME_NAME.to_string() + ":test:0-0",
+        serde_json::to_string(&chunk).unwrap(),
+    );
+
+    // The middle is walled in, so the closest open tile is next to the wall
+    assert_eq!(
+        find_spawn("test", "james", &db).unwrap(),
+        coord::UserCoord { x: 0, y: -2 }
+    );
+
+    // Players standing round the middle box it in just the same
+    let grass = create::GamestateChunk {
+        terrain: vec![vec![tiles::TILE_GRASS; CHUNK_LENGTH]; CHUNK_LENGTH],
+        ..chunk.clone()
+    };
+    db.set(
+        GAME_NAME.to_string() + ":test:0-0",
+        serde_json::to_string(&grass).unwrap(),
+    );
+    for (username, x, y) in [("a", 1, 0), ("b", -1, 0), ("c", 0, 1), ("d", 0, -1)] {
+        add_to_chunk(username, &coord::UserCoord { x, y }, &db);
+    }
+    assert_eq!(
+        find_spawn("test", "james", &db).unwrap(),
+        coord::UserCoord { x: -1, y: -1 }
+    );
+
+    // Nowhere is open enough
+    let rocks = create::GamestateChunk {
+        terrain: vec![vec![tiles::TILE_ROCK; CHUNK_LENGTH]; CHUNK_LENGTH],
+        ..chunk
+    };
+    db.set(
+        GAME_NAME.to_string() + ":test:0-0",
+        serde_json::to_string(&rocks).unwrap(),
+    );
+    assert!(find_spawn("test", "james", &db).is_err());
+}
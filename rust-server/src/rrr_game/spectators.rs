@@ -0,0 +1,96 @@
+use crate::{
+    http::{HttpError, HttpErrorCode},
+    rrr_game::{index, lock, meta},
+    Database,
+};
+use jsonwebtoken::get_current_timestamp;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// The body is optional, as only games with a password need it
+#[derive(Serialize, Deserialize, Default)]
+struct SpectateGameRq {
+    password: Option<String>,
+}
+
+// Spectators get the same access rules as joining, but aren't limited to one
+// game at a time, and don't take up a space
+pub fn spectate_game(
+    username: String,
+    body: String,
+    game_id: String,
+    db: Arc<impl Database>,
+) -> Result<String, HttpError> {
+    let body: SpectateGameRq = if body.is_empty() {
+        SpectateGameRq::default()
+    } else if let Ok(valid_body) = serde_json::from_str(&body) {
+        valid_body
+    } else {
+        return Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: "Spectate game body has invalid format.".to_string(),
+        });
+    };
+
+    meta::get_meta(&game_id, &db)?;
+    let game_lock = lock::game_lock(&game_id);
+    let _guard = game_lock.lock().unwrap();
+    let mut game_meta = meta::get_meta(&game_id, &db)?;
+    if game_meta.is_member(&username) {
+        return Err(HttpError {
+            code: HttpErrorCode::Error409Conflict,
+            message: "User is already in this game".to_string(),
+        });
+    }
+    game_meta.check_can_join(body.password.as_deref())?;
+
+    game_meta.roster.insert(
+        username,
+        meta::RosterEntry {
+            joined_at: get_current_timestamp(),
+            role: meta::RosterRole::Spectator,
+        },
+    );
+    meta::set_meta(&game_meta, &db);
+
+    Ok("".to_string())
+}
+
+pub fn stop_spectating(
+    username: String,
+    game_id: String,
+    db: Arc<impl Database>,
+) -> Result<String, HttpError> {
+    meta::get_meta(&game_id, &db)?;
+    let game_lock = lock::game_lock(&game_id);
+    let _guard = game_lock.lock().unwrap();
+    let mut game_meta = meta::get_meta(&game_id, &db)?;
+    if !game_meta.is_member(&username) || game_meta.is_player(&username) {
+        return Err(HttpError {
+            code: HttpErrorCode::Error404NotFround,
+            message: "User isn't spectating this game".to_string(),
+        });
+    }
+
+    game_meta.roster.remove(&username);
+    meta::set_meta(&game_meta, &db);
+
+    Ok("".to_string())
+}
+
+// Called when a user is deleted. Spectating isn't recorded against the user,
+// so the game index is used to find the games.
+pub fn remove_user_spectating(username: &str, db: Arc<impl Database>) {
+    for order in index::member_games(username, &db) {
+        let game_id = index::order_game_id(&order);
+        let game_lock = lock::game_lock(game_id);
+        let _guard = game_lock.lock().unwrap();
+        let Ok(mut game_meta) = meta::get_meta(game_id, &db) else {
+            continue;
+        };
+        if game_meta.is_member(username) && !game_meta.is_player(username) {
+            game_meta.roster.remove(username);
+            meta::set_meta(&game_meta, &db);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::{
+    rrr_game::{index, lock, meta, queue, timers},
+    Database,
+};
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Moves every game with players on by one tick. Run by the `TickScheduler`.
+pub fn tick_games(db: &Arc<impl Database>) {
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    for game_id in index::active_games(db) {
+        tick_game(&game_id, now_ms, db);
+    }
+}
+
+// The world systems, run in this order every tick. Player actions go first, so
+// the rest see where everyone ended up, then the timers that are due, e.g.
+// forest growing back. NPC movement slots in after as the world gets it. The
+// game is locked for the whole tick, so moves made straight away go before or
+// after it, not in the middle.
+fn tick_game(game_id: &str, now_ms: u128, db: &Arc<impl Database>) {
+    let game_lock = lock::game_lock(game_id);
+    let _guard = game_lock.lock().unwrap();
+    // Deleted since the games were listed, so the lock isn't needed again
+    if meta::get_meta(game_id, db).is_err() {
+        lock::remove_game_lock(game_id);
+        return;
+    }
+    queue::apply_queued_actions(game_id, db);
+    timers::run_due_timers(game_id, now_ms, db);
+}
+
+#[test]
+fn test_tick_runs_timers() {
+    use crate::rrr_game::{coord, create, tiles, CHUNK_LENGTH, GAME_NAME};
+
+    let db = Arc::new(crate::LocalDatabase::new());
+    let chunk = create::GamestateChunk {
+        coord: coord::GamestateCoord { x: 0, y: 0 },
+        terrain: vec![vec![tiles::TILE_GRASS; CHUNK_LENGTH]; CHUNK_LENGTH],
+        users: std::collections::HashMap::new(),
+    };
+    let chunk_key = GAME_NAME.to_string() + ":test:0-0";
+    db.set(chunk_key.clone(), serde_json::to_string(&chunk).unwrap());
+    let regrow = timers::TimerEvent::Regrow {
+        user_coord: coord::UserCoord { x: 0, y: 0 },
+        tile: tiles::TILE_FOREST,
+    };
+    timers::set_timer("test", 1000, &regrow, &db);
+    let settings = meta::GameSettings {
+        visibility: meta::Visibility::Public,
+        password_hash: None,
+        max_players: meta::MAX_PLAYERS,
+        pass_through: false,
+        min_move_interval_ms: 0,
+    };
+    meta::set_meta(&meta::GameMeta::new("test", "james", 0, settings), &db);
+
+    // Not yet
+    tick_game("test", 999, &db);
+    assert!(!db.get(&chunk_key).unwrap().contains("\"F\""));
+
+    // The forest grows back on the first tick after it's due
+    tick_game("test", 1500, &db);
+    let chunk: create::GamestateChunk = serde_json::from_str(&db.get(&chunk_key).unwrap()).unwrap();
+    let (x, y) = coord::get_terrain_index(&coord::UserCoord { x: 0, y: 0 }, CHUNK_LENGTH);
+    assert_eq!(chunk.terrain[y][x], tiles::TILE_FOREST);
+    assert!(db.keys("rrr-game-timer:test:").is_empty());
+}
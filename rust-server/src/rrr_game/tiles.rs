@@ -0,0 +1,90 @@
+use serde::Serialize;
+
+pub const TILE_GRASS: char = 'G';
+pub const TILE_ROCK: char = 'R';
+pub const TILE_WATER: char = 'W';
+pub const TILE_SAND: char = 'S';
+pub const TILE_FOREST: char = 'F';
+pub const TILE_BRIDGE: char = 'B';
+pub const TILE_DEEP_WATER: char = 'D';
+pub const TILE_LAVA: char = 'L';
+
+// Everything the server knows about a kind of tile. The id is what's stored
+// in the chunks and sent to clients in the terrain.
+#[derive(Serialize, PartialEq, Eq, Debug)]
+pub struct TileType {
+    pub id: char,
+    pub name: &'static str,
+    pub walkable: bool,
+    // How much walking onto it counts for when finding a way somewhere, at
+    // least 1
+    pub movement_cost: u32,
+    pub blocks_visibility: bool,
+    pub harvestable: bool,
+}
+
+const fn tile(
+    id: char,
+    name: &'static str,
+    walkable: bool,
+    movement_cost: u32,
+    blocks_visibility: bool,
+    harvestable: bool,
+) -> TileType {
+    TileType {
+        id,
+        name,
+        walkable,
+        movement_cost,
+        blocks_visibility,
+        harvestable,
+    }
+}
+
+// Sorted by id
+pub const TILES: [TileType; 8] = [
+    tile(TILE_BRIDGE, "bridge", true, 1, false, false),
+    tile(TILE_DEEP_WATER, "deep water", false, 1, false, false),
+    tile(TILE_FOREST, "forest", true, 2, true, true),
+    tile(TILE_GRASS, "grass", true, 1, false, false),
+    tile(TILE_LAVA, "lava", false, 1, false, false),
+    tile(TILE_ROCK, "rock", false, 1, true, true),
+    tile(TILE_SAND, "sand", true, 2, false, false),
+    tile(TILE_WATER, "water", false, 1, false, false),
+];
+
+pub fn get_tile(id: char) -> Option<&'static TileType> {
+    TILES
+        .binary_search_by_key(&id, |tile| tile.id)
+        .ok()
+        .map(|index| &TILES[index])
+}
+
+// Tiles the server doesn't know about can't be walked on
+pub fn is_walkable(id: char) -> bool {
+    get_tile(id).is_some_and(|tile| tile.walkable)
+}
+
+#[test]
+fn test_tiles() {
+    // Sorted, so they can be searched
+    assert!(TILES.windows(2).all(|pair| pair[0].id < pair[1].id));
+    assert!(TILES.iter().all(|tile| tile.movement_cost >= 1));
+
+    assert_eq!(get_tile(TILE_FOREST).unwrap().name, "forest");
+    assert!(is_walkable(TILE_GRASS));
+    assert!(is_walkable(TILE_BRIDGE));
+    assert!(!is_walkable(TILE_LAVA));
+    assert!(!is_walkable('?'));
+    assert_eq!(get_tile('?'), None);
+}
+
+#[test]
+fn test_frontend_draws_every_tile() {
+    // The client falls back to a colour for tiles it has no image for
+    let game_js = include_str!("../../../frontend/js/game.js");
+    for tile in TILES {
+        let colour = format!("  {}: \"#", tile.id);
+        assert!(game_js.contains(&colour), "No colour for {}", tile.name);
+    }
+}
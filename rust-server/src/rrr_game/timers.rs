@@ -0,0 +1,143 @@
+use crate::{
+    rrr_game::{action, coord, CHUNK_LENGTH},
+    Database,
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const TIMER_NAME: &str = "rrr-game-timer";
+
+// How long trampled forest takes to grow back
+pub const REGROW_MS: u128 = 60 * 1000;
+
+// Things that happen to the world some time later
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub enum TimerEvent {
+    // Puts the tile back, e.g. forest after it's been trampled
+    Regrow {
+        user_coord: coord::UserCoord,
+        tile: char,
+    },
+}
+impl TimerEvent {
+    // Tells timers due at the same time apart
+    fn id(&self) -> String {
+        match self {
+            TimerEvent::Regrow { user_coord, .. } => {
+                format!("regrow:{}_{}", user_coord.x, user_coord.y)
+            }
+        }
+    }
+}
+
+fn timer_prefix(game_id: &str) -> String {
+    TIMER_NAME.to_string() + ":" + game_id + ":"
+}
+
+// Due times are padded, so the keys sort in the order they go off
+fn timer_key(game_id: &str, due_at_ms: u128, event: &TimerEvent) -> String {
+    format!("{}{:020}:{}", timer_prefix(game_id), due_at_ms, event.id())
+}
+
+fn due_at_ms(key: &str, game_id: &str) -> u128 {
+    key[timer_prefix(game_id).len()..]
+        .split(':')
+        .next()
+        .and_then(|due_at_ms| due_at_ms.parse().ok())
+        .unwrap_or(0)
+}
+
+pub fn set_timer(game_id: &str, due_at_ms: u128, event: &TimerEvent, db: &Arc<impl Database>) {
+    db.set(
+        timer_key(game_id, due_at_ms, event),
+        serde_json::to_string(event).unwrap(),
+    );
+}
+
+// Runs every timer that's due, oldest first. Returns how many ran. The caller
+// has to hold the game's lock.
+pub fn run_due_timers(game_id: &str, now_ms: u128, db: &Arc<impl Database>) -> usize {
+    let mut due_keys: Vec<String> = db
+        .keys(&timer_prefix(game_id))
+        .into_iter()
+        .filter(|key| due_at_ms(key, game_id) <= now_ms)
+        .collect();
+    due_keys.sort();
+
+    for key in &due_keys {
+        if let Some(event) = db.get(key) {
+            run_timer(game_id, serde_json::from_str(&event).unwrap(), db);
+        }
+        db.del(key);
+    }
+    due_keys.len()
+}
+
+fn run_timer(game_id: &str, event: TimerEvent, db: &Arc<impl Database>) {
+    match event {
+        TimerEvent::Regrow { user_coord, tile } => {
+            let gamestate_coord = coord::user_coord_to_gamestate_coord(&user_coord, CHUNK_LENGTH);
+            let Ok(mut gamestate_chunk) = action::get_chunk(game_id, &gamestate_coord, db) else {
+                warn!("Chunk to regrow in {:?} is missing", game_id);
+                return;
+            };
+            let (x, y) = coord::get_terrain_index(&user_coord, CHUNK_LENGTH);
+            gamestate_chunk.terrain[y][x] = tile;
+            action::set_chunk(game_id, &gamestate_chunk, db);
+        }
+    }
+}
+
+pub fn remove_game_timers(game_id: &str, db: &Arc<impl Database>) {
+    for key in db.keys(&timer_prefix(game_id)) {
+        db.del(&key);
+    }
+}
+
+#[test]
+fn test_timers() {
+    use crate::rrr_game::{create, tiles, GAME_NAME};
+
+    let db = Arc::new(crate::LocalDatabase::new());
+    let chunk = create::GamestateChunk {
+        coord: coord::GamestateCoord { x: 0, y: 0 },
+        terrain: vec![vec![tiles::TILE_GRASS; CHUNK_LENGTH]; CHUNK_LENGTH],
+        users: std::collections::HashMap::new(),
+    };
+    let chunk_key = GAME_NAME.to_string() + ":test:0-0";
+    db.set(chunk_key.clone(), serde_json::to_string(&chunk).unwrap());
+    let regrow = |x, y| TimerEvent::Regrow {
+        user_coord: coord::UserCoord { x, y },
+        tile: tiles::TILE_FOREST,
+    };
+    let get_tile = |x, y| {
+        let chunk: create::GamestateChunk =
+            serde_json::from_str(&db.get(&chunk_key).unwrap()).unwrap();
+        let (x, y) = coord::get_terrain_index(&coord::UserCoord { x, y }, CHUNK_LENGTH);
+        chunk.terrain[y][x]
+    };
+
+    set_timer("test", 2000, &regrow(1, 0), &db);
+    set_timer("test", 1000, &regrow(0, 1), &db);
+    // Outside the world made so far, it's skipped
+    set_timer("test", 1000, &regrow(100, 0), &db);
+    // Other games aren't touched
+    set_timer("test2", 0, &regrow(0, 0), &db);
+
+    // Nothing happens until they're due
+    assert_eq!(run_due_timers("test", 999, &db), 0);
+    assert_eq!(get_tile(0, 1), tiles::TILE_GRASS);
+
+    assert_eq!(run_due_timers("test", 1000, &db), 2);
+    assert_eq!(get_tile(0, 1), tiles::TILE_FOREST);
+    assert_eq!(get_tile(1, 0), tiles::TILE_GRASS);
+
+    assert_eq!(run_due_timers("test", 5000, &db), 1);
+    assert_eq!(get_tile(1, 0), tiles::TILE_FOREST);
+    assert_eq!(run_due_timers("test", 5000, &db), 0);
+    assert_eq!(get_tile(0, 0), tiles::TILE_GRASS);
+
+    remove_game_timers("test2", &db);
+    assert!(db.keys(&timer_prefix("test2")).is_empty());
+}
@@ -0,0 +1,76 @@
+use crate::{rrr_game, sessions, Database};
+use log::{info, warn};
+use std::{
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+// Expired tokens are cleared out much less often than the games tick
+const TOKEN_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+// Runs the game tick on its own thread, alongside the `ThreadPool` handling
+// requests. Stops when dropped, the same as the pool.
+pub struct TickScheduler {
+    thread: Option<thread::JoinHandle<()>>,
+    stop: Option<mpsc::Sender<()>>,
+}
+
+impl TickScheduler {
+    pub fn new<D: Database + Send + Sync + 'static>(
+        interval: Duration,
+        db: Arc<D>,
+    ) -> TickScheduler {
+        assert!(!interval.is_zero());
+
+        warn!("Ticking games every {:?}", interval);
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            let mut next_tick = Instant::now() + interval;
+            let mut next_token_sweep = Instant::now();
+            loop {
+                let until_next_tick = next_tick.saturating_duration_since(Instant::now());
+                match stopped.recv_timeout(until_next_tick) {
+                    Err(RecvTimeoutError::Timeout) => (),
+                    _ => {
+                        info!("Tick scheduler shutting down.");
+                        break;
+                    }
+                }
+
+                rrr_game::tick_games(&db);
+                if Instant::now() >= next_token_sweep {
+                    sessions::remove_expired_tokens(&db);
+                    next_token_sweep = Instant::now() + TOKEN_SWEEP_INTERVAL;
+                }
+
+                // A fixed rate, so slow ticks don't push the rest back. If the
+                // tick falls right behind, ticks are skipped rather than rushed.
+                next_tick += interval;
+                let now = Instant::now();
+                if next_tick < now {
+                    warn!("Game tick running behind, skipping ticks");
+                    next_tick = now + interval;
+                }
+            }
+        });
+
+        TickScheduler {
+            thread: Some(thread),
+            stop: Some(stop),
+        }
+    }
+}
+impl Drop for TickScheduler {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use crate::{
+    http::{HttpError, HttpErrorCode},
+    jwt::{self, Claims, KeyRing},
+    tokens, users, Database,
+};
+use jsonwebtoken::get_current_timestamp;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const REFRESH_TOKEN_NAME: &str = "refresh-token";
+const REVOKED_TOKEN_NAME: &str = "revoked-jti";
+const TOKEN_CUTOFF_NAME: &str = "tokens-valid-after";
+
+const THIRTY_DAY_TIMEOUT_S: u64 = 30 * 86400;
+
+#[derive(Serialize, Deserialize)]
+pub struct TokenBody {
+    access_token: String,
+    refresh_token: String,
+}
+
+// Refresh tokens are stored server side so they can be revoked
+#[derive(Serialize, Deserialize)]
+struct RefreshTokenEntry {
+    username: String,
+    exp: u64,
+}
+
+fn refresh_token_key(refresh_token: &str) -> String {
+    REFRESH_TOKEN_NAME.to_string() + ":" + &tokens::hash_token(refresh_token)
+}
+
+fn revoked_token_key(jti: &str) -> String {
+    REVOKED_TOKEN_NAME.to_string() + ":" + jti
+}
+
+fn token_cutoff_key(username: &str) -> String {
+    TOKEN_CUTOFF_NAME.to_string() + ":" + username
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
+
+// Give the user a new access token and refresh token pair
+pub fn issue_tokens(
+    username: &str,
+    db: Arc<impl Database>,
+    keys: &KeyRing,
+) -> Result<TokenBody, HttpError> {
+    // Roles are looked up every time, so role changes apply on the next refresh
+    let roles = users::get_user_roles(username, Arc::clone(&db))?;
+
+    let refresh_token = tokens::generate_token(48);
+    let entry = RefreshTokenEntry {
+        username: username.to_owned(),
+        exp: get_current_timestamp() + THIRTY_DAY_TIMEOUT_S,
+    };
+    db.set(
+        refresh_token_key(&refresh_token),
+        serde_json::to_string(&entry).unwrap(),
+    );
+
+    Ok(TokenBody {
+        access_token: jwt::create_jwt(username, &roles, keys),
+        refresh_token,
+    })
+}
+
+// Removes the refresh token from the DB, returning who it belonged to if it
+// was still valid. Done in one update, so only one refresh can take it.
+fn take_refresh_token(refresh_token: &str, db: &Arc<impl Database>) -> Option<String> {
+    let mut taken = None;
+    db.update(&refresh_token_key(refresh_token), &mut |entry| {
+        taken = entry.map(|entry| serde_json::from_str::<RefreshTokenEntry>(&entry).unwrap());
+        None
+    });
+
+    let entry = taken?;
+    if entry.exp > get_current_timestamp() {
+        Some(entry.username)
+    } else {
+        None
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RefreshRq {
+    refresh_token: String,
+}
+
+pub fn refresh(body: String, db: Arc<impl Database>, keys: &KeyRing) -> Result<String, HttpError> {
+    let body: RefreshRq = if let Ok(valid_body) = serde_json::from_str(&body) {
+        valid_body
+    } else {
+        return Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: "Refresh request body has invalid format.".to_string(),
+        });
+    };
+
+    // Refresh tokens are single use, each refresh gets a new one
+    let username = take_refresh_token(&body.refresh_token, &db).ok_or(HttpError {
+        code: HttpErrorCode::Error401Unauthorized,
+        message: "Refresh token is invalid or expired.".to_string(),
+    })?;
+
+    let token_body = issue_tokens(&username, db, keys)?;
+    Ok(serde_json::to_string(&token_body).unwrap())
+}
+
+#[derive(Serialize, Deserialize)]
+struct LogoutRq {
+    refresh_token: Option<String>,
+}
+
+pub fn logout(claims: Claims, body: String, db: Arc<impl Database>) -> Result<String, HttpError> {
+    // The refresh token is optional, without it only the access token is revoked
+    let body: LogoutRq = if body.is_empty() {
+        LogoutRq {
+            refresh_token: None,
+        }
+    } else if let Ok(valid_body) = serde_json::from_str(&body) {
+        valid_body
+    } else {
+        return Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: "Logout request body has invalid format.".to_string(),
+        });
+    };
+
+    if let Some(refresh_token) = body.refresh_token {
+        let key = refresh_token_key(&refresh_token);
+        if let Some(entry) = db.get(&key) {
+            let entry: RefreshTokenEntry = serde_json::from_str(&entry).unwrap();
+            if entry.username != claims.sub {
+                return Err(HttpError {
+                    code: HttpErrorCode::Error403Forbidden,
+                    message: "Refresh token belongs to another user.".to_string(),
+                });
+            }
+            db.del(&key);
+        }
+    }
+
+    // Deny the access token until it would have expired anyway
+    db.set(revoked_token_key(&claims.jti), claims.exp.to_string());
+
+    Ok("".to_string())
+}
+
+// Log the user out everywhere, e.g. after their password is reset. Access
+// tokens still work until they expire, but they're short lived.
+pub fn revoke_refresh_tokens(username: &str, db: Arc<impl Database>) {
+    for key in db.keys(&(REFRESH_TOKEN_NAME.to_string() + ":")) {
+        let owned_by_user = db
+            .get(&key)
+            .and_then(|entry| serde_json::from_str::<RefreshTokenEntry>(&entry).ok())
+            .is_some_and(|entry| entry.username == username);
+        if owned_by_user {
+            db.del(&key);
+        }
+    }
+}
+
+// Drops denied access tokens and refresh tokens that have expired anyway, so
+// they don't build up. Run now and then by the `TickScheduler`.
+pub fn remove_expired_tokens(db: &Arc<impl Database>) -> usize {
+    let now = get_current_timestamp();
+    let mut removed = 0;
+    for key in db.keys(&(REVOKED_TOKEN_NAME.to_string() + ":")) {
+        let expired = db
+            .get(&key)
+            .is_some_and(|exp| exp.parse::<u64>().unwrap() <= now);
+        if expired {
+            db.del(&key);
+            removed += 1;
+        }
+    }
+    for key in db.keys(&(REFRESH_TOKEN_NAME.to_string() + ":")) {
+        let expired = db
+            .get(&key)
+            .and_then(|entry| serde_json::from_str::<RefreshTokenEntry>(&entry).ok())
+            .is_some_and(|entry| entry.exp <= now);
+        if expired {
+            db.del(&key);
+            removed += 1;
+        }
+    }
+    // Every token from before the cutoff has expired by now
+    let cutoff_expired_ms = now_ms() - u128::from(jwt::ACCESS_TOKEN_TIMEOUT_S) * 1000;
+    for key in db.keys(&(TOKEN_CUTOFF_NAME.to_string() + ":")) {
+        let expired = db
+            .get(&key)
+            .is_some_and(|cutoff_ms| cutoff_ms.parse::<u128>().unwrap() < cutoff_expired_ms);
+        if expired {
+            db.del(&key);
+            removed += 1;
+        }
+    }
+    removed
+}
+
+// Denies every access token the user has been given so far, e.g. when they're
+// deleted, so they can't be used by whoever signs up with the name next
+pub fn revoke_access_tokens(username: &str, db: &Arc<impl Database>) {
+    db.set(token_cutoff_key(username), now_ms().to_string());
+}
+
+pub fn issued_before_cutoff(claims: &Claims, db: &Arc<impl Database>) -> bool {
+    db.get(&token_cutoff_key(&claims.sub))
+        .is_some_and(|cutoff_ms| claims.iat_ms <= cutoff_ms.parse::<u128>().unwrap())
+}
+
+pub fn is_revoked(jti: &str, db: Arc<impl Database>) -> bool {
+    let key = revoked_token_key(jti);
+    match db.get(&key) {
+        Some(exp) => {
+            if exp.parse::<u64>().unwrap() > get_current_timestamp() {
+                true
+            } else {
+                // Token has expired, so no need to keep denying it
+                db.del(&key);
+                false
+            }
+        }
+        None => false,
+    }
+}
+
+#[test]
+fn test_refresh_token_taken_once() {
+    let db = Arc::new(crate::LocalDatabase::new());
+    let entry = RefreshTokenEntry {
+        username: "james".to_string(),
+        exp: get_current_timestamp() + 60,
+    };
+    db.set(
+        refresh_token_key("token"),
+        serde_json::to_string(&entry).unwrap(),
+    );
+
+    // However many try at once, only one gets it
+    let taken: Vec<Option<String>> = std::thread::scope(|scope| {
+        let takers: Vec<_> = (0..8)
+            .map(|_| scope.spawn(|| take_refresh_token("token", &db)))
+            .collect();
+        takers
+            .into_iter()
+            .map(|taker| taker.join().unwrap())
+            .collect()
+    });
+    assert_eq!(taken.iter().filter(|taken| taken.is_some()).count(), 1);
+    assert_eq!(db.get(&refresh_token_key("token")), None);
+}
+
+#[test]
+fn test_remove_expired_tokens() {
+    let db = Arc::new(crate::LocalDatabase::new());
+    let now = get_current_timestamp();
+    db.set(revoked_token_key("old"), (now - 1).to_string());
+    db.set(revoked_token_key("new"), (now + 60).to_string());
+    let old_cutoff_ms = now_ms() - u128::from(jwt::ACCESS_TOKEN_TIMEOUT_S) * 1000 - 1;
+    db.set(token_cutoff_key("old"), old_cutoff_ms.to_string());
+    revoke_access_tokens("new", &db);
+    for (refresh_token, exp) in [("old", now - 1), ("new", now + 60)] {
+        let entry = RefreshTokenEntry {
+            username: "james".to_string(),
+            exp,
+        };
+        db.set(
+            refresh_token_key(refresh_token),
+            serde_json::to_string(&entry).unwrap(),
+        );
+    }
+
+    assert_eq!(remove_expired_tokens(&db), 3);
+    assert_eq!(db.get(&revoked_token_key("old")), None);
+    assert_eq!(db.get(&token_cutoff_key("old")), None);
+    assert!(db.get(&token_cutoff_key("new")).is_some());
+    assert!(is_revoked("new", Arc::clone(&db)));
+    assert_eq!(db.get(&refresh_token_key("old")), None);
+    assert!(db.get(&refresh_token_key("new")).is_some());
+    assert_eq!(remove_expired_tokens(&db), 0);
+}
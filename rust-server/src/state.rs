@@ -0,0 +1,28 @@
+use crate::{jwt::KeyRing, rate_limit::RateLimiter, Config, Database, Mailer};
+use std::sync::Arc;
+
+// Everything a request handler might need, shared between the worker threads.
+pub struct ServerState<D: Database> {
+    pub db: Arc<D>,
+    pub keys: KeyRing,
+    pub config: Config,
+    pub mailer: Box<dyn Mailer>,
+    pub(crate) rate_limiter: RateLimiter,
+}
+impl<D: Database> ServerState<D> {
+    pub fn new(
+        db: Arc<D>,
+        keys: KeyRing,
+        config: Config,
+        mailer: Box<dyn Mailer>,
+    ) -> ServerState<D> {
+        let rate_limiter = RateLimiter::new(config.rate_limits.clone());
+        ServerState {
+            db,
+            keys,
+            config,
+            mailer,
+            rate_limiter,
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use rand::{distributions::Alphanumeric, Rng};
+use ring::digest;
+
+// Random tokens handed out to users, e.g. refresh tokens and reset codes
+pub fn generate_token(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+// Tokens are only ever stored hashed, so a leaked DB doesn't leak them
+pub fn hash_token(token: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, token.as_bytes());
+    hash.as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
@@ -1,14 +1,25 @@
 use crate::{
+    auth::Role,
+    email_verifications, friends,
     http::{HttpError, HttpErrorCode},
-    jwt, users, Database,
+    jwt::KeyRing,
+    login_attempts, password_resets, rrr_game, sessions, tokens, users,
+    validation::Validator,
+    Database, Mailer,
 };
 use argon2::{
     password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
 
+use jsonwebtoken::get_current_timestamp;
+use log::warn;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, OnceLock},
+};
 
 #[derive(Serialize, Deserialize)]
 struct CreateUserRq {
@@ -19,24 +30,134 @@ struct CreateUserRq {
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct UserGameInfo {
-    game_id: String,
-    chunk_id: String,
+    pub game_id: String,
+    pub chunk_id: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct UserStats {
+    games_played: u64,
+    tiles_explored: u64,
+    distance_moved: u64,
 }
 
 #[derive(Serialize, Deserialize)]
 struct UserEntry {
+    // As the user typed it, the ID used everywhere else is lowercase
+    #[serde(default)]
+    username: String,
     email: String,
     hash: String,
     salt: String,
     current_games: HashMap<String, UserGameInfo>,
+    #[serde(default = "default_roles")]
+    roles: Vec<Role>,
+    // Users from before email verification are trusted
+    #[serde(default = "default_verified")]
+    verified: bool,
+    // Unix time, 0 for users from before this was recorded
+    #[serde(default)]
+    created_at: u64,
+    #[serde(default)]
+    stats: UserStats,
 }
 
-#[derive(Serialize, Deserialize)]
-struct TokenBody {
-    access_token: String,
+fn default_roles() -> Vec<Role> {
+    vec![Role::Player]
+}
+
+fn default_verified() -> bool {
+    true
+}
+
+// Usernames are case insensitive, so "James" and "james" are the same user.
+// This is the form used as the user's ID, e.g. in tokens and games.
+pub fn canonical_username(username: &str) -> String {
+    username.to_lowercase()
+}
+
+// Users live in their own namespace, so they can't clash with game data
+const USER_NAME: &str = "user";
+
+fn user_key(username: &str) -> String {
+    USER_NAME.to_string() + ":" + &canonical_username(username)
+}
+
+// Users used to be stored under their bare username. Move any of those into
+// the user namespace. Every other key has a namespace, so anything without a
+// ':' must be an old user.
+pub fn migrate_user_keys(db: Arc<impl Database>) -> usize {
+    let mut migrated = 0;
+    for key in db.keys("") {
+        if key.contains(':') {
+            continue;
+        }
+        let user_info = db.get(&key).unwrap();
+        if serde_json::from_str::<UserEntry>(&user_info).is_err() {
+            warn!("Found key {:?} that isn't a user, skipping", key);
+            continue;
+        }
+
+        let new_key = user_key(&key);
+        if db.get(&new_key).is_some() {
+            warn!("User {:?} already migrated, skipping", key);
+            continue;
+        }
+        db.set(new_key, user_info);
+        db.del(&key);
+        migrated += 1;
+    }
+    migrated
+}
+
+// Makes the accounts named in the config admins. Only ever promotes users that
+// already exist, so someone can't become an admin by signing up with a listed
+// name before the real owner does.
+pub fn promote_admins(admins: &[String], db: Arc<impl Database>) -> usize {
+    let mut promoted = 0;
+    for admin in admins {
+        let newly_admin = update_user_raw(admin, &db, |user_entry| {
+            if user_entry.roles.contains(&Role::Admin) {
+                return false;
+            }
+            user_entry.roles.push(Role::Admin);
+            true
+        });
+        match newly_admin {
+            Ok(true) => promoted += 1,
+            Ok(false) => {}
+            Err(_) => warn!("Admin {:?} doesn't have an account, skipping", admin),
+        }
+    }
+    promoted
+}
+
+// Returns the hash and the salt
+pub(crate) fn hash_password(password: &str) -> (String, String) {
+    // Reference https://docs.rs/argon2/latest/argon2/
+    let salt: SaltString = SaltString::generate(&mut rand_core::OsRng);
+    let argon2 = Argon2::default();
+    let hash = argon2
+        .hash_password(password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+
+    (hash, salt.to_string()) // Js9 - not sure this is right way to store salt
 }
 
-pub fn create_user(body: String, db: Arc<impl Database>) -> Result<String, HttpError> {
+pub(crate) fn verify_password(password: &str, hash: &str) -> bool {
+    let parsed_hash = PasswordHash::new(hash).unwrap();
+    Argon2::default()
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .is_ok()
+}
+
+pub fn create_user(
+    body: String,
+    db: Arc<impl Database>,
+    keys: &KeyRing,
+    mailer: &dyn Mailer,
+) -> Result<String, HttpError> {
     let body: users::CreateUserRq = if let Ok(valid_body) = serde_json::from_str(&body) {
         valid_body
     } else {
@@ -46,7 +167,14 @@ pub fn create_user(body: String, db: Arc<impl Database>) -> Result<String, HttpE
         });
     };
 
-    if db.get(&body.username).is_some() {
+    Validator::new()
+        .username("username", &body.username)
+        .email("email", &body.email)
+        .password("password", &body.password, &body.username)
+        .finish()?;
+
+    let user_id = canonical_username(&body.username);
+    if db.get(&user_key(&user_id)).is_some() {
         // User already exists in the db
         return Err(HttpError {
             code: HttpErrorCode::Error409Conflict,
@@ -54,30 +182,25 @@ pub fn create_user(body: String, db: Arc<impl Database>) -> Result<String, HttpE
         });
     }
 
-    // Reference https://docs.rs/argon2/latest/argon2/
-    let salt: SaltString = SaltString::generate(&mut rand_core::OsRng);
-    let argon2 = Argon2::default();
-    let hash = argon2
-        .hash_password(body.password.as_bytes(), &salt)
-        .unwrap()
-        .to_string();
+    let (hash, salt) = hash_password(&body.password);
 
     let user_entry = UserEntry {
+        username: body.username,
         email: body.email,
         hash,
-        salt: salt.to_string(), // Js9 - not sure this is right way to store salt
+        salt,
         current_games: HashMap::new(),
+        roles: default_roles(),
+        verified: false,
+        created_at: get_current_timestamp(),
+        stats: UserStats::default(),
     };
 
-    db.set(
-        body.username.clone(),
-        serde_json::to_string(&user_entry).unwrap(),
-    );
+    set_user_raw(&user_id, &user_entry, Arc::clone(&db));
+    email_verifications::send_verification(&user_id, &user_entry.email, Arc::clone(&db), mailer);
 
     // Also give new user a token
-    let token_body = TokenBody {
-        access_token: jwt::create_jwt(&body.username, &"test".to_string()),
-    };
+    let token_body = sessions::issue_tokens(&user_id, db, keys)?;
     Ok(serde_json::to_string(&token_body).unwrap())
 }
 
@@ -87,7 +210,12 @@ struct LoginRq {
     password: String,
 }
 
-pub fn login(body: String, db: Arc<impl Database>) -> Result<String, HttpError> {
+pub fn login(
+    body: String,
+    peer: IpAddr,
+    db: Arc<impl Database>,
+    keys: &KeyRing,
+) -> Result<String, HttpError> {
     let body: users::LoginRq = if let Ok(valid_body) = serde_json::from_str(&body) {
         valid_body
     } else {
@@ -97,70 +225,159 @@ pub fn login(body: String, db: Arc<impl Database>) -> Result<String, HttpError>
         });
     };
 
-    // Get user info
-    let user_info: UserEntry = if let Some(user_info) = db.get(&body.username) {
-        serde_json::from_str(&user_info).unwrap()
-    } else {
-        // User doesn't exist
-        // Todo - should this just be a generic error in order to not leak info?
-        return Err(HttpError {
-            code: HttpErrorCode::Error401Unauthorized,
-            message: "User doesn't exist".to_string(),
-        });
+    let now = get_current_timestamp();
+    let user_id = canonical_username(&body.username);
+    login_attempts::check_not_locked(&user_id, &peer, &db, now)?;
+
+    // Unknown users still get a password check, so how long this takes
+    // doesn't give away whether the user exists
+    let password_correct = match db.get(&user_key(&user_id)) {
+        Some(user_info) => {
+            let user_info: UserEntry = serde_json::from_str(&user_info).unwrap();
+            verify_password(&body.password, &user_info.hash)
+        }
+        None => {
+            verify_password(&body.password, dummy_hash());
+            false
+        }
     };
 
-    // Check password
-    let parsed_hash = PasswordHash::new(&user_info.hash).unwrap();
-    if Argon2::default()
-        .verify_password(body.password.as_bytes(), &parsed_hash)
-        .is_ok()
-    {
-        let token_body = TokenBody {
-            access_token: jwt::create_jwt(&body.username, &"test".to_string()),
-        };
+    if password_correct {
+        login_attempts::record_success(&user_id, &db);
+        let token_body = sessions::issue_tokens(&user_id, db, keys)?;
         Ok(serde_json::to_string(&token_body).unwrap())
     } else {
-        // Password incorrect
-        // Todo - should this just be a generic error in order to not leak info?
+        // Same error either way, so this can't be used to find usernames
+        login_attempts::record_failure(&user_id, &peer, &db, now);
         Err(HttpError {
             code: HttpErrorCode::Error401Unauthorized,
-            message: "Password incorrect".to_string(),
+            message: "Username or password incorrect".to_string(),
         })
     }
 }
 
+// Hash to check passwords against for users that don't exist
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash_password(&tokens::generate_token(16)).0)
+}
+
+// Anyone logged in can see this
 #[derive(Serialize, Deserialize)]
 struct PubUserInfo {
     username: String,
+    joined_at: Option<u64>,
+    stats: UserStats,
+    // Only for the user themselves and admins
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    private: Option<PrivateUserInfo>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PrivateUserInfo {
     email: String,
+    verified: bool,
+}
+
+// User doesn't exist
+// Todo - should this just be a generic error in order to not leak info?
+fn user_not_found() -> HttpError {
+    HttpError {
+        code: HttpErrorCode::Error404NotFround,
+        message: "User doesn't exist".to_string(),
+    }
 }
 
 fn get_user_raw(username: &str, db: Arc<impl Database>) -> Result<UserEntry, HttpError> {
-    if let Some(user_info) = db.get(username) {
+    if let Some(user_info) = db.get(&user_key(username)) {
         Ok(serde_json::from_str(&user_info).unwrap())
     } else {
-        // User doesn't exist
-        // Todo - should this just be a generic error in order to not leak info?
-        Err(HttpError {
-            code: HttpErrorCode::Error404NotFround,
-            message: "User doesn't exist".to_string(),
-        })
+        Err(user_not_found())
     }
 }
 
-pub fn get_user(username: String, db: Arc<impl Database>) -> Result<String, HttpError> {
+// Changes the user entry in one go, so e.g. stats from a move can't write back
+// an old password over one that was changed at the same time. Returns what `f`
+// returns.
+fn update_user_raw<T>(
+    username: &str,
+    db: &Arc<impl Database>,
+    mut f: impl FnMut(&mut UserEntry) -> T,
+) -> Result<T, HttpError> {
+    let mut result = None;
+    db.update(&user_key(username), &mut |user_info| {
+        let mut user_info: UserEntry = serde_json::from_str(&user_info?).unwrap();
+        result = Some(f(&mut user_info));
+        Some(serde_json::to_string(&user_info).unwrap())
+    });
+    result.ok_or_else(user_not_found)
+}
+
+fn set_user_raw(username: &str, user_info: &UserEntry, db: Arc<impl Database>) {
+    db.set(
+        user_key(username),
+        serde_json::to_string(user_info).unwrap(),
+    );
+}
+
+pub fn get_user(
+    username: String,
+    include_private: bool,
+    db: Arc<impl Database>,
+) -> Result<String, HttpError> {
     // Get user
     let user_info: UserEntry = get_user_raw(&username, db)?;
 
     // Only display the public info (e.g not the password)
     let pub_user_info = PubUserInfo {
-        username,
-        email: user_info.email,
+        username: if user_info.username.is_empty() {
+            username
+        } else {
+            user_info.username
+        },
+        joined_at: (user_info.created_at > 0).then_some(user_info.created_at),
+        stats: user_info.stats,
+        private: include_private.then_some(PrivateUserInfo {
+            email: user_info.email,
+            verified: user_info.verified,
+        }),
     };
 
     Ok(serde_json::to_string(&pub_user_info).unwrap())
 }
 
+// Returns the email and whether it has been verified
+pub fn get_user_email(username: &str, db: Arc<impl Database>) -> Result<(String, bool), HttpError> {
+    let user_info = get_user_raw(username, db)?;
+    Ok((user_info.email, user_info.verified))
+}
+
+// Returns false if the user's email is no longer the one that was verified
+pub fn set_user_verified(
+    username: &str,
+    email: &str,
+    db: Arc<impl Database>,
+) -> Result<bool, HttpError> {
+    update_user_raw(username, &db, |user_info| {
+        if user_info.email != email {
+            return false;
+        }
+        user_info.verified = true;
+        true
+    })
+}
+
+pub fn check_verified(username: &str, db: Arc<impl Database>) -> Result<(), HttpError> {
+    if get_user_raw(username, db)?.verified {
+        Ok(())
+    } else {
+        Err(HttpError {
+            code: HttpErrorCode::Error403Forbidden,
+            message: "You must verify your email first.".to_string(),
+        })
+    }
+}
+
 pub fn get_user_curr_game_info(
     username: &str,
     db: Arc<impl Database>,
@@ -171,3 +388,358 @@ pub fn get_user_curr_game_info(
 
     Ok(user_info.current_games.get(game).cloned())
 }
+
+pub fn set_user_curr_game_info(
+    username: &str,
+    db: Arc<impl Database>,
+    game: &str,
+    game_info: UserGameInfo,
+) -> Result<(), HttpError> {
+    update_user_raw(username, &db, |user_info| {
+        user_info
+            .current_games
+            .insert(game.to_string(), game_info.clone());
+    })
+}
+
+// Keeps track of which chunk the user is in, as they move between them
+pub fn set_user_curr_chunk(
+    username: &str,
+    game: &str,
+    chunk_id: String,
+    db: Arc<impl Database>,
+) -> Result<(), HttpError> {
+    update_user_raw(username, &db, |user_info| {
+        if let Some(game_info) = user_info.current_games.get_mut(game) {
+            game_info.chunk_id = chunk_id.clone();
+        }
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+struct UpdateUserRq {
+    email: Option<String>,
+}
+
+pub fn update_user(
+    username: String,
+    body: String,
+    db: Arc<impl Database>,
+    mailer: &dyn Mailer,
+) -> Result<String, HttpError> {
+    let body: UpdateUserRq = if let Ok(valid_body) = serde_json::from_str(&body) {
+        valid_body
+    } else {
+        return Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: "Update user request body has invalid format.".to_string(),
+        });
+    };
+
+    if let Some(email) = body.email {
+        Validator::new().email("email", &email).finish()?;
+        let email_changed = update_user_raw(&username, &db, |user_info| {
+            if email == user_info.email {
+                return false;
+            }
+            // The new address has to be verified again
+            user_info.email = email.clone();
+            user_info.verified = false;
+            true
+        })?;
+        if email_changed {
+            email_verifications::send_verification(&username, &email, Arc::clone(&db), mailer);
+        }
+    }
+
+    get_user(username, true, db)
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChangePasswordRq {
+    old_password: String,
+    new_password: String,
+}
+
+pub fn change_password(
+    username: String,
+    body: String,
+    db: Arc<impl Database>,
+) -> Result<String, HttpError> {
+    let body: ChangePasswordRq = if let Ok(valid_body) = serde_json::from_str(&body) {
+        valid_body
+    } else {
+        return Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: "Change password request body has invalid format.".to_string(),
+        });
+    };
+
+    let user_info = get_user_raw(&username, Arc::clone(&db))?;
+
+    // Make sure it's really the user, not just someone with their token
+    if !verify_password(&body.old_password, &user_info.hash) {
+        return Err(HttpError {
+            code: HttpErrorCode::Error401Unauthorized,
+            message: "Password incorrect".to_string(),
+        });
+    }
+
+    set_password(&username, &body.new_password, Arc::clone(&db))?;
+    // Sign out everywhere else, in case someone else had their password
+    sessions::revoke_refresh_tokens(&username, db);
+
+    Ok("".to_string())
+}
+
+// For when the user can't give their old password, e.g. a password reset.
+// The caller has to have checked it's really them.
+pub fn set_password(
+    username: &str,
+    new_password: &str,
+    db: Arc<impl Database>,
+) -> Result<(), HttpError> {
+    // Hashing is slow, so it isn't done while the entry is being updated
+    get_user_raw(username, Arc::clone(&db))?;
+
+    Validator::new()
+        .password("new_password", new_password, username)
+        .finish()?;
+
+    let (hash, salt) = hash_password(new_password);
+    update_user_raw(username, &db, |user_info| {
+        user_info.hash = hash.clone();
+        user_info.salt = salt.clone();
+    })
+}
+
+// Emails aren't unique, so there can be more than one user with it.
+// Returns the user IDs.
+pub fn find_users_by_email(email: &str, db: Arc<impl Database>) -> Vec<String> {
+    let prefix = USER_NAME.to_string() + ":";
+    let mut usernames: Vec<String> = db
+        .keys(&prefix)
+        .iter()
+        .filter_map(|key| {
+            let user_info: UserEntry = serde_json::from_str(&db.get(key)?).ok()?;
+            if user_info.email.eq_ignore_ascii_case(email) {
+                key.strip_prefix(&prefix).map(str::to_string)
+            } else {
+                None
+            }
+        })
+        .collect();
+    usernames.sort();
+    usernames
+}
+
+pub fn record_game_played(username: &str, db: Arc<impl Database>) -> Result<(), HttpError> {
+    update_user_raw(username, &db, |user_info| {
+        user_info.stats.games_played += 1;
+    })
+}
+
+// The user has moved `distance` tiles in a game, ending up on a tile they
+// haven't been on before if `explored_new_tile`. The game keeps track of
+// which tiles.
+pub fn record_move(
+    username: &str,
+    distance: u64,
+    explored_new_tile: bool,
+    db: Arc<impl Database>,
+) -> Result<(), HttpError> {
+    update_user_raw(username, &db, |user_info| {
+        user_info.stats.distance_moved += distance;
+        if explored_new_tile {
+            user_info.stats.tiles_explored += 1;
+        }
+    })
+}
+
+pub fn get_user_roles(username: &str, db: Arc<impl Database>) -> Result<Vec<Role>, HttpError> {
+    Ok(get_user_raw(username, db)?.roles)
+}
+
+#[derive(Serialize, Deserialize)]
+struct SetRolesRq {
+    roles: Vec<Role>,
+}
+
+pub fn set_user_roles(
+    username: String,
+    body: String,
+    db: Arc<impl Database>,
+) -> Result<String, HttpError> {
+    let body: SetRolesRq = if let Ok(valid_body) = serde_json::from_str(&body) {
+        valid_body
+    } else {
+        return Err(HttpError {
+            code: HttpErrorCode::Error400BadRequest,
+            message: "Set roles request body has invalid format.".to_string(),
+        });
+    };
+
+    update_user_raw(&username, &db, |user_info| {
+        user_info.roles = body.roles.clone();
+    })?;
+
+    Ok("".to_string())
+}
+
+pub fn delete_user(username: String, db: Arc<impl Database>) -> Result<String, HttpError> {
+    let user_info = get_user_raw(&username, Arc::clone(&db))?;
+
+    // Take the user out of any games they are in, so they don't haunt them
+    for game_info in user_info.current_games.values() {
+        rrr_game::remove_player(&game_info.game_id, &username, Arc::clone(&db));
+    }
+
+    friends::remove_user(&username, Arc::clone(&db));
+    rrr_game::remove_user_invites(&username, Arc::clone(&db));
+    rrr_game::remove_user_spectating(&username, Arc::clone(&db));
+    // Otherwise they'd still work for whoever signs up with the name next
+    sessions::revoke_refresh_tokens(&username, Arc::clone(&db));
+    sessions::revoke_access_tokens(&username, &db);
+    password_resets::remove_user_resets(&username, &db);
+    email_verifications::remove_user_verifications(&username, &db);
+    db.del(&user_key(&username));
+
+    Ok("".to_string())
+}
+
+// Called when a game is deleted out from under the user
+pub fn remove_user_curr_game(username: &str, game: &str, game_id: &str, db: Arc<impl Database>) {
+    // Fine if the user is gone too
+    let _ = update_user_raw(username, &db, |user_info| {
+        let in_game = user_info
+            .current_games
+            .get(game)
+            .is_some_and(|game_info| game_info.game_id == game_id);
+        if in_game {
+            user_info.current_games.remove(game);
+        }
+    });
+}
+
+#[cfg(test)]
+fn test_user_entry(username: &str) -> UserEntry {
+    UserEntry {
+        username: username.to_string(),
+        email: format!("{}@example.com", username),
+        hash: "hash".to_string(),
+        salt: "salt".to_string(),
+        current_games: HashMap::new(),
+        roles: default_roles(),
+        verified: true,
+        created_at: 0,
+        stats: UserStats::default(),
+    }
+}
+
+#[test]
+fn test_user_namespace() {
+    // A username that looks like a game key still can't overwrite the game
+    let db = Arc::new(crate::LocalDatabase::new());
+    db.set("rrr-game:abc:0-0".to_string(), "{}".to_string());
+
+    set_user_raw(
+        "rrr-game:abc:0-0",
+        &test_user_entry("rrr-game:abc:0-0"),
+        Arc::clone(&db),
+    );
+
+    assert_eq!(db.get("rrr-game:abc:0-0"), Some("{}".to_string()));
+    assert!(db.get("user:rrr-game:abc:0-0").is_some());
+    let user_entry = get_user_raw("rrr-game:abc:0-0", Arc::clone(&db)).unwrap();
+    assert_eq!(user_entry.username, "rrr-game:abc:0-0");
+}
+
+#[test]
+fn test_migrate_populated_db() {
+    // Setup - old users mixed in with everything else the server stores
+    let db = Arc::new(crate::LocalDatabase::new());
+    let old_users = ["james", "alex", "Sam"];
+    for username in old_users {
+        db.set(
+            username.to_string(),
+            serde_json::to_string(&test_user_entry(username)).unwrap(),
+        );
+    }
+    set_user_raw("jo", &test_user_entry("jo"), Arc::clone(&db));
+    let other_keys = [
+        ("rrr-game:abc:0-0", "{}"),
+        ("rrr-game-meta:abc", "{}"),
+        ("rrr-game-player:abc:jo", "{}"),
+        ("friends:jo", "[]"),
+        ("refresh-token:1234", "jo"),
+    ];
+    for (key, value) in other_keys {
+        db.set(key.to_string(), value.to_string());
+    }
+    // Not a user, and a user that was somehow migrated already
+    db.set("notauser".to_string(), "[1, 2, 3]".to_string());
+    let migrated_jo = db.get("user:jo").unwrap();
+    db.set(
+        "jo".to_string(),
+        serde_json::to_string(&test_user_entry("jo-old")).unwrap(),
+    );
+
+    // Migrate
+    assert_eq!(migrate_user_keys(Arc::clone(&db)), old_users.len());
+
+    // Verify every old user moved, keeping what they had
+    for username in old_users {
+        assert_eq!(db.get(username), None);
+        let user_entry = get_user_raw(username, Arc::clone(&db)).unwrap();
+        assert_eq!(user_entry.username, username);
+    }
+    assert!(db.get("user:sam").is_some());
+
+    // Everything else is untouched
+    for (key, value) in other_keys {
+        assert_eq!(db.get(key), Some(value.to_string()));
+    }
+    assert_eq!(db.get("notauser"), Some("[1, 2, 3]".to_string()));
+    assert_eq!(db.get("user:jo"), Some(migrated_jo));
+    assert!(db.get("jo").is_some());
+    assert_eq!(db.keys("user:").len(), old_users.len() + 1);
+
+    // Running it again does nothing
+    assert_eq!(migrate_user_keys(Arc::clone(&db)), 0);
+}
+
+#[test]
+fn test_concurrent_updates_all_kept() {
+    let db = Arc::new(crate::LocalDatabase::new());
+    set_user_raw("james", &test_user_entry("james"), Arc::clone(&db));
+
+    // Moves and a role change at the same time don't undo each other
+    std::thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| {
+                for _ in 0..50 {
+                    record_move("james", 1, true, Arc::clone(&db)).unwrap();
+                }
+            });
+        }
+        scope.spawn(|| {
+            for _ in 0..50 {
+                set_user_roles(
+                    "james".to_string(),
+                    "{\"roles\":[\"Admin\"]}".to_string(),
+                    Arc::clone(&db),
+                )
+                .unwrap();
+            }
+        });
+    });
+
+    let user_entry = get_user_raw("james", Arc::clone(&db)).unwrap();
+    assert_eq!(user_entry.stats.distance_moved, 400);
+    assert_eq!(user_entry.stats.tiles_explored, 400);
+    assert_eq!(user_entry.roles, vec![Role::Admin]);
+
+    // Users that don't exist aren't made
+    assert!(record_move("alex", 1, true, Arc::clone(&db)).is_err());
+    assert_eq!(db.get(&user_key("alex")), None);
+}
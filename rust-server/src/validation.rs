@@ -0,0 +1,243 @@
+use crate::http::{FieldError, HttpError, HttpErrorCode};
+use regex::Regex;
+
+const USERNAME_MIN_LENGTH: usize = 3;
+const USERNAME_MAX_LENGTH: usize = 20;
+const EMAIL_MAX_LENGTH: usize = 254;
+const PASSWORD_MIN_LENGTH: usize = 8;
+// Argon2 is slow on purpose, so don't let people make it hash huge passwords
+const PASSWORD_MAX_LENGTH: usize = 128;
+// Game passwords are shared between friends, so can be short
+const JOIN_PASSWORD_MIN_LENGTH: usize = 4;
+
+// Checked in lowercase, so "Password1" is caught too
+const COMMON_PASSWORDS: [&str; 12] = [
+    "password",
+    "password1",
+    "password123",
+    "12345678",
+    "123456789",
+    "1234567890",
+    "qwerty123",
+    "qwertyuiop",
+    "iloveyou",
+    "letmein1",
+    "11111111",
+    "abc12345",
+];
+
+// Collects up all the problems with a request, so they can be reported in one go
+pub struct Validator {
+    field_errors: Vec<FieldError>,
+}
+impl Validator {
+    pub fn new() -> Validator {
+        Validator {
+            field_errors: vec![],
+        }
+    }
+
+    fn add_error(&mut self, field: &str, message: &str) {
+        self.field_errors.push(FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    // Usernames end up in URLs (/users/{id}) and in DB keys, so are kept to
+    // plain letters and numbers.
+    pub fn username(&mut self, field: &str, username: &str) -> &mut Validator {
+        let re = Regex::new(r"^[a-zA-Z0-9]*$").unwrap();
+        if !(USERNAME_MIN_LENGTH..=USERNAME_MAX_LENGTH).contains(&username.len()) {
+            self.add_error(
+                field,
+                &format!(
+                    "Must be between {} and {} characters long.",
+                    USERNAME_MIN_LENGTH, USERNAME_MAX_LENGTH
+                ),
+            );
+        }
+        if !re.is_match(username) {
+            self.add_error(field, "Must only contain letters and numbers.");
+        }
+        self
+    }
+
+    pub fn email(&mut self, field: &str, email: &str) -> &mut Validator {
+        let re = Regex::new(r"^[^@\s]+@[^@\s.]+(\.[^@\s.]+)+$").unwrap();
+        if email.len() > EMAIL_MAX_LENGTH || !re.is_match(email) {
+            self.add_error(field, "Must be a valid email address.");
+        }
+        self
+    }
+
+    pub fn password(&mut self, field: &str, password: &str, username: &str) -> &mut Validator {
+        let length = password.chars().count();
+        if !(PASSWORD_MIN_LENGTH..=PASSWORD_MAX_LENGTH).contains(&length) {
+            self.add_error(
+                field,
+                &format!(
+                    "Must be between {} and {} characters long.",
+                    PASSWORD_MIN_LENGTH, PASSWORD_MAX_LENGTH
+                ),
+            );
+        }
+        let lowercase_password = password.to_lowercase();
+        if COMMON_PASSWORDS.contains(&lowercase_password.as_str()) {
+            self.add_error(field, "Is too common.");
+        }
+        if !username.is_empty() && lowercase_password.contains(&username.to_lowercase()) {
+            self.add_error(field, "Must not contain the username.");
+        }
+        self
+    }
+
+    pub fn join_password(&mut self, field: &str, password: &str) -> &mut Validator {
+        let length = password.chars().count();
+        if !(JOIN_PASSWORD_MIN_LENGTH..=PASSWORD_MAX_LENGTH).contains(&length) {
+            self.add_error(
+                field,
+                &format!(
+                    "Must be between {} and {} characters long.",
+                    JOIN_PASSWORD_MIN_LENGTH, PASSWORD_MAX_LENGTH
+                ),
+            );
+        }
+        self
+    }
+
+    pub fn finish(&self) -> Result<(), HttpError> {
+        if self.field_errors.is_empty() {
+            Ok(())
+        } else {
+            Err(HttpError {
+                code: HttpErrorCode::Error400InvalidFields(self.field_errors.clone()),
+                message: "Request has invalid fields.".to_string(),
+            })
+        }
+    }
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_validate_username() {
+    assert!(Validator::new()
+        .username("username", "james")
+        .finish()
+        .is_ok());
+    assert!(Validator::new()
+        .username("username", "James123")
+        .finish()
+        .is_ok());
+    assert!(Validator::new()
+        .username("username", "ja")
+        .finish()
+        .is_err());
+    assert!(Validator::new()
+        .username("username", &"a".repeat(21))
+        .finish()
+        .is_err());
+    assert!(Validator::new()
+        .username("username", "rrr-game:abc")
+        .finish()
+        .is_err());
+    assert!(Validator::new()
+        .username("username", "jam es")
+        .finish()
+        .is_err());
+}
+
+#[test]
+fn test_validate_email() {
+    assert!(Validator::new()
+        .email("email", "james@gmail.com")
+        .finish()
+        .is_ok());
+    assert!(Validator::new()
+        .email("email", "a.b@c.co.uk")
+        .finish()
+        .is_ok());
+    assert!(Validator::new().email("email", "james").finish().is_err());
+    assert!(Validator::new()
+        .email("email", "james@gmail")
+        .finish()
+        .is_err());
+    assert!(Validator::new()
+        .email("email", "@gmail.com")
+        .finish()
+        .is_err());
+    assert!(Validator::new()
+        .email("email", "ja mes@gmail.com")
+        .finish()
+        .is_err());
+    assert!(Validator::new()
+        .email("email", "james@gmail.")
+        .finish()
+        .is_err());
+}
+
+#[test]
+fn test_validate_password() {
+    assert!(Validator::new()
+        .password("password", "testpassword", "james")
+        .finish()
+        .is_ok());
+    assert!(Validator::new()
+        .password("password", "short", "james")
+        .finish()
+        .is_err());
+    assert!(Validator::new()
+        .password("password", &"a".repeat(129), "james")
+        .finish()
+        .is_err());
+    assert!(Validator::new()
+        .password("password", "Password123", "james")
+        .finish()
+        .is_err());
+    assert!(Validator::new()
+        .password("password", "james12345", "james")
+        .finish()
+        .is_err());
+}
+
+#[test]
+fn test_validate_join_password() {
+    assert!(Validator::new()
+        .join_password("password", "1234")
+        .finish()
+        .is_ok());
+    assert!(Validator::new()
+        .join_password("password", "123")
+        .finish()
+        .is_err());
+    assert!(Validator::new()
+        .join_password("password", &"a".repeat(129))
+        .finish()
+        .is_err());
+}
+
+#[test]
+fn test_validate_lists_all_errors() {
+    let error = Validator::new()
+        .username("username", "a:")
+        .email("email", "bad")
+        .password("password", "", "a:")
+        .finish()
+        .unwrap_err();
+
+    match error.code {
+        HttpErrorCode::Error400InvalidFields(field_errors) => {
+            let fields: Vec<&str> = field_errors
+                .iter()
+                .map(|field_error| field_error.field.as_str())
+                .collect();
+            assert_eq!(fields, vec!["username", "username", "email", "password"]);
+        }
+        _ => panic!("Expected invalid fields error"),
+    }
+}
@@ -0,0 +1,87 @@
+use rust_book_server_example::{
+    process_request_from, Config, LocalDatabase, RateLimit, ServerState,
+};
+use std::collections::HashMap;
+
+mod util;
+
+fn create_user(username: &str, peer: &str, state: &ServerState<LocalDatabase>) -> String {
+    let request = util::build_request(
+        "POST",
+        "/users",
+        &format!(
+            "{{\"username\":\"{}\", \"email\":\"{}@gmail.com\", \"password\":\"testpassword\"}}",
+            username, username
+        ),
+        "",
+    );
+    process_request_from(request, peer.parse().unwrap(), state)
+}
+
+#[test]
+fn test_rate_limit_by_ip() {
+    // Setup - only 2 sign ups allowed
+    let state = util::test_state_with_config(Config {
+        rate_limits: HashMap::from([(
+            "POST /users".to_string(),
+            RateLimit {
+                capacity: 2.0,
+                refill_per_s: 0.1,
+            },
+        )]),
+        ..Config::default()
+    });
+
+    for username in ["james", "alex"] {
+        let response = util::parse_response(create_user(username, "10.0.0.1", &state));
+        assert_eq!(response.status_code, 200);
+    }
+
+    // Third is refused, and told when to try again
+    let raw_response = create_user("sam", "10.0.0.1", &state);
+    assert!(raw_response.contains("Retry-After: "));
+    let response = util::parse_response(raw_response);
+    assert_eq!(response.status_code, 429);
+
+    // Other IPs aren't affected
+    let response = util::parse_response(create_user("sam", "10.0.0.2", &state));
+    assert_eq!(response.status_code, 200);
+}
+
+#[test]
+fn test_rate_limit_by_user() {
+    // Setup - two users on the same IP
+    let state = util::test_state_with_config(Config {
+        rate_limits: HashMap::from([(
+            "GET /users/{id}".to_string(),
+            RateLimit {
+                capacity: 1.0,
+                refill_per_s: 0.1,
+            },
+        )]),
+        ..Config::default()
+    });
+    let tokens: Vec<String> = ["james", "alex"]
+        .iter()
+        .map(|username| {
+            let response = util::parse_response(create_user(username, "10.0.0.1", &state));
+            response.token.unwrap()
+        })
+        .collect();
+
+    let get_user = |username: &str, token: &str| {
+        let request = util::build_request("GET", &format!("/users/{}", username), "", token);
+        util::parse_response(process_request_from(
+            request,
+            "10.0.0.1".parse().unwrap(),
+            &state,
+        ))
+        .status_code
+    };
+
+    // Each user has their own limit
+    assert_eq!(get_user("james", &tokens[0]), 200);
+    assert_eq!(get_user("alex", &tokens[1]), 200);
+    assert_eq!(get_user("james", &tokens[0]), 429);
+    assert_eq!(get_user("alex", &tokens[1]), 429);
+}
@@ -1,15 +1,20 @@
 use regex::Regex;
-use rust_book_server_example::{process_request, Database, LocalDatabase};
-use std::sync::Arc;
+use rust_book_server_example::{process_request, Config, Database, RateLimit, TickScheduler};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
 
 mod util;
 
-fn get_game_id(body: &String) -> String {
+fn get_game_id(body: &str) -> String {
     let re = Regex::new("game_id\":\"(?<game_id>[a-zA-Z0-9]{7})\"").unwrap();
 
-    assert_eq!(re.captures_iter(&body).count(), 1);
+    assert_eq!(re.captures_iter(body).count(), 1);
 
-    let captures = re.captures_iter(&body);
+    let captures = re.captures_iter(body);
     let capture = captures.last().unwrap();
 
     capture.name("game_id").unwrap().as_str().to_string()
@@ -18,7 +23,7 @@ fn get_game_id(body: &String) -> String {
 #[test]
 fn test_create_rrr_game() {
     // Setup
-    let db = Arc::new(LocalDatabase::new());
+    let state = util::test_state();
     let (user1, _user2) = util::test_users();
     let request = util::build_request(
         "POST",
@@ -29,13 +34,13 @@ fn test_create_rrr_game() {
         ),
         "",
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
     let token = response.token.unwrap();
 
     // Create game
     let request = util::build_request("POST", "/rrr-game", "", &token);
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify created successfully
@@ -50,7 +55,7 @@ fn test_create_rrr_game() {
 #[test]
 fn test_get_gamestate() {
     // Setup
-    let db = Arc::new(LocalDatabase::new());
+    let state = util::test_state();
     let (user1, _user2) = util::test_users();
     let request = util::build_request(
         "POST",
@@ -61,13 +66,13 @@ fn test_get_gamestate() {
         ),
         "",
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
     let token = response.token.unwrap();
 
     // Create game
     let request = util::build_request("POST", "/rrr-game", "", &token);
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
     assert_eq!(response.status_code, 200);
     assert!(response.body.is_some());
@@ -75,7 +80,7 @@ fn test_get_gamestate() {
 
     // Get the gamestate
     let request = util::build_request("GET", &format!("/rrr-game/{}?x=0&y=0", game_id), "", &token);
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify
@@ -90,7 +95,7 @@ fn test_get_gamestate() {
 #[test]
 fn test_make_move() {
     // Setup
-    let db = Arc::new(LocalDatabase::new());
+    let state = util::test_state();
     let (user1, _user2) = util::test_users();
     let request = util::build_request(
         "POST",
@@ -101,7 +106,7 @@ fn test_make_move() {
         ),
         "",
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
     let token = response.token.unwrap();
 
@@ -131,19 +136,20 @@ fn test_make_move() {
                         }
                 }
             }";
-    db.set(
+    state.db.set(
         "rrr-game:1234567:0-0".to_string(),
         gamestate_chunk.to_string(),
     );
+    util::insert_game_meta(&state, "1234567", &["james"]);
 
     // Make move East
     let request = util::build_request(
         "POST",
         "/rrr-game/1234567/actions?x=0&y=0",
-        &format!("{{\"move\":\"East\"}}"),
+        "{\"move\":\"East\"}",
         &token,
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify
@@ -155,10 +161,10 @@ fn test_make_move() {
     let request = util::build_request(
         "POST",
         "/rrr-game/1234567/actions?x=0&y=0",
-        &format!("{{\"move\":\"East\"}}"),
+        "{\"move\":\"East\"}",
         &token,
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify
@@ -170,10 +176,10 @@ fn test_make_move() {
     let request = util::build_request(
         "POST",
         "/rrr-game/1234567/actions?x=0&y=0",
-        &format!("{{\"move\":\"South\"}}"),
+        "{\"move\":\"South\"}",
         &token,
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify
@@ -185,10 +191,10 @@ fn test_make_move() {
     let request = util::build_request(
         "POST",
         "/rrr-game/1234567/actions?x=0&y=0",
-        &format!("{{\"move\":\"West\"}}"),
+        "{\"move\":\"West\"}",
         &token,
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify
@@ -200,10 +206,10 @@ fn test_make_move() {
     let request = util::build_request(
         "POST",
         "/rrr-game/1234567/actions?x=0&y=0",
-        &format!("{{\"move\":\"North\"}}"),
+        "{\"move\":\"North\"}",
         &token,
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify
@@ -215,7 +221,7 @@ fn test_make_move() {
 #[test]
 fn test_make_move_rocks() {
     // Setup
-    let db = Arc::new(LocalDatabase::new());
+    let state = util::test_state();
     let (user1, _user2) = util::test_users();
     let request = util::build_request(
         "POST",
@@ -226,7 +232,7 @@ fn test_make_move_rocks() {
         ),
         "",
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
     let token = response.token.unwrap();
 
@@ -256,34 +262,40 @@ fn test_make_move_rocks() {
                         }
                 }
             }";
-    db.set(
+    state.db.set(
         "rrr-game:1234567:0-0".to_string(),
         gamestate_chunk.to_string(),
     );
+    util::insert_game_meta(&state, "1234567", &["james"]);
 
     // Make move East - should be ignored
     let request = util::build_request(
         "POST",
         "/rrr-game/1234567/actions?x=0&y=0",
-        &format!("{{\"move\":\"East\"}}"),
+        "{\"move\":\"East\"}",
         &token,
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify
     assert!(response.body.is_some());
     assert!(response.body.clone().unwrap().contains("\"x\":0,\"y\":0"));
+    assert!(response
+        .body
+        .clone()
+        .unwrap()
+        .contains("\"outcome\":\"BlockedByTerrain\",\"tile\":\"R\""));
     assert_eq!(response.status_code, 200);
 
     // Make move South - should work
     let request = util::build_request(
         "POST",
         "/rrr-game/1234567/actions?x=0&y=0",
-        &format!("{{\"move\":\"South\"}}"),
+        "{\"move\":\"South\"}",
         &token,
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify
@@ -295,10 +307,10 @@ fn test_make_move_rocks() {
     let request = util::build_request(
         "POST",
         "/rrr-game/1234567/actions?x=0&y=0",
-        &format!("{{\"move\":\"South\"}}"),
+        "{\"move\":\"South\"}",
         &token,
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify
@@ -310,10 +322,10 @@ fn test_make_move_rocks() {
     let request = util::build_request(
         "POST",
         "/rrr-game/1234567/actions?x=0&y=0",
-        &format!("{{\"move\":\"East\"}}"),
+        "{\"move\":\"East\"}",
         &token,
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify
@@ -325,10 +337,10 @@ fn test_make_move_rocks() {
     let request = util::build_request(
         "POST",
         "/rrr-game/1234567/actions?x=0&y=0",
-        &format!("{{\"move\":\"North\"}}"),
+        "{\"move\":\"North\"}",
         &token,
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify
@@ -336,3 +348,1252 @@ fn test_make_move_rocks() {
     assert!(response.body.clone().unwrap().contains("\"x\":1,\"y\":1"));
     assert_eq!(response.status_code, 200);
 }
+
+#[test]
+fn test_delete_game() {
+    // Setup - user2 is an admin
+    let (user1, user2) = util::test_users();
+    let state = util::test_state();
+    let tokens = [
+        util::sign_up(&state, &user1),
+        util::sign_up_admin(&state, &user2),
+    ];
+
+    // Create game
+    let request = util::build_request("POST", "/rrr-game", "", &tokens[0]);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    let game_id = get_game_id(&response.body.unwrap());
+
+    // Normal user can't delete the game
+    let request = util::build_request("DELETE", &format!("/rrr-game/{}", game_id), "", &tokens[0]);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 403);
+
+    // Admin can view the game
+    let request = util::build_request(
+        "GET",
+        &format!("/rrr-game/{}?x=0&y=0", game_id),
+        "",
+        &tokens[1],
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+
+    // Admin can delete the game
+    let request = util::build_request("DELETE", &format!("/rrr-game/{}", game_id), "", &tokens[1]);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+    assert_eq!(state.db.get(&format!("rrr-game:{}:0-0", game_id)), None);
+
+    // Game is gone
+    let request = util::build_request("DELETE", &format!("/rrr-game/{}", game_id), "", &tokens[1]);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 404);
+}
+
+#[test]
+fn test_user_cant_shadow_game() {
+    // Setup
+    let state = util::test_state();
+    let (user1, user2) = util::test_users();
+    let request = util::build_request(
+        "POST",
+        "/users",
+        &format!(
+            "{{\"username\":\"{}\", \"email\":\"{}\", \"password\":\"{}\"}}",
+            user1.username, user1.email, user1.password
+        ),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    let token = response.token.unwrap();
+
+    // Create game
+    let request = util::build_request("POST", "/rrr-game", "", &token);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    let game_id = get_game_id(&response.body.unwrap());
+    let chunk_key = format!("rrr-game:{}:0-0", game_id);
+    let chunk = state.db.get(&chunk_key).unwrap();
+
+    // Try to create users named like the game's chunks
+    for username in [chunk_key.clone(), game_id.clone()] {
+        let request = util::build_request(
+            "POST",
+            "/users",
+            &format!(
+                "{{\"username\":\"{}\", \"email\":\"{}\", \"password\":\"{}\"}}",
+                username, user2.email, user2.password
+            ),
+            "",
+        );
+        process_request(request, &state);
+    }
+
+    // Verify the game is untouched, and no users are stored with the game
+    assert_eq!(state.db.get(&chunk_key).unwrap(), chunk);
+    assert_ne!(
+        state.db.get(&format!("user:{}", game_id.to_lowercase())),
+        None
+    );
+    for key in state.db.keys("rrr-game:") {
+        assert!(key.starts_with(&format!("rrr-game:{}:", game_id)));
+    }
+
+    // And the game still works
+    let request = util::build_request("GET", &format!("/rrr-game/{}?x=0&y=0", game_id), "", &token);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+}
+
+#[test]
+fn test_player_stats() {
+    // Setup - user1 in a game, user2 looking at their profile
+    let state = util::test_state();
+    let (user1, user2) = util::test_users();
+    let mut tokens = vec![];
+    for user in [&user1, &user2] {
+        let request = util::build_request(
+            "POST",
+            "/users",
+            &format!(
+                "{{\"username\":\"{}\", \"email\":\"{}\", \"password\":\"{}\"}}",
+                user.username, user.email, user.password
+            ),
+            "",
+        );
+        let response = process_request(request, &state);
+        let response = util::parse_response(response);
+        tokens.push(response.token.unwrap());
+    }
+    let (token1, token2) = (&tokens[0], &tokens[1]);
+
+    let request = util::build_request("POST", "/rrr-game", "", token1);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    let game_id = get_game_id(&response.body.unwrap());
+
+    // Make the centre chunk all grass, so every move works
+    let chunk_key = format!("rrr-game:{}:0-0", game_id);
+    let gamestate_chunk = state.db.get(&chunk_key).unwrap();
+    let re = Regex::new("\"[RW]\"").unwrap();
+    let gamestate_chunk = re.replace_all(&gamestate_chunk, "\"G\"").to_string();
+    state.db.set(chunk_key, gamestate_chunk);
+
+    // Move there and back, only new tiles count as explored
+    for direction in ["East", "East", "West"] {
+        let request = util::build_request(
+            "POST",
+            &format!("/rrr-game/{}/actions?x=0&y=0", game_id),
+            &format!("{{\"move\":\"{}\"}}", direction),
+            token1,
+        );
+        let response = process_request(request, &state);
+        let response = util::parse_response(response);
+        assert_eq!(response.status_code, 200);
+    }
+    // Which tiles is kept by the game, the user only has the count
+    let user_entry = state.db.get(&format!("user:{}", user1.username)).unwrap();
+    assert!(!user_entry.contains("explored\":["));
+    assert_eq!(
+        state
+            .db
+            .keys(&format!(
+                "rrr-game-explored:{}:{}:",
+                game_id, user1.username
+            ))
+            .len(),
+        1
+    );
+
+    // Other players can see the stats, but not the email
+    let request = util::build_request("GET", &format!("/users/{}", user1.username), "", token2);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+    let body = response.body.unwrap();
+    assert!(
+        body.contains("\"stats\":{\"games_played\":1,\"tiles_explored\":3,\"distance_moved\":3}")
+    );
+    assert!(body.contains("\"joined_at\":"));
+    assert!(!body.contains(&user1.email));
+
+    // The user themselves can see everything
+    let request = util::build_request("GET", &format!("/users/{}", user1.username), "", token1);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    let body = response.body.unwrap();
+    assert!(body.contains("\"games_played\":1"));
+    assert!(body.contains(&user1.email));
+}
+
+#[test]
+fn test_join_and_leave_game() {
+    // Setup - user1 has a game
+    let state = util::test_state();
+    let (user1, user2) = util::test_users();
+    let token1 = util::sign_up(&state, &user1);
+    let token2 = util::sign_up(&state, &user2);
+    let request = util::build_request("POST", "/rrr-game", "", &token1);
+    let response = util::parse_response(process_request(request, &state));
+    let game_id = get_game_id(&response.body.unwrap());
+
+    // Games that don't exist can't be joined
+    let request = util::build_request("POST", "/rrr-game/abcdefg/players", "", &token2);
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 404);
+
+    // Join
+    let request = util::build_request(
+        "POST",
+        &format!("/rrr-game/{}/players", game_id),
+        "",
+        &token2,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 200);
+    assert_eq!(get_game_id(&response.body.unwrap()), game_id);
+
+    // Both players are in the game
+    let request = util::build_request(
+        "GET",
+        &format!("/rrr-game/{}?x=0&y=0", game_id),
+        "",
+        &token2,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    let body = response.body.unwrap();
+    assert!(body.contains("\"james\":{\"x\":0,\"y\":0}"));
+    // Next to james, as no one spawns on anyone else
+    assert!(body.contains("\"alex\":{\"x\":0,\"y\":-1}"));
+
+    // Can't join twice
+    let request = util::build_request(
+        "POST",
+        &format!("/rrr-game/{}/players", game_id),
+        "",
+        &token2,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 400);
+
+    // Leave
+    let request = util::build_request(
+        "DELETE",
+        &format!("/rrr-game/{}/players", game_id),
+        "",
+        &token2,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 200);
+    let chunk = state.db.get(&format!("rrr-game:{}:0-0", game_id)).unwrap();
+    assert!(!chunk.contains("\"alex\""));
+
+    // Can't leave again
+    let request = util::build_request(
+        "DELETE",
+        &format!("/rrr-game/{}/players", game_id),
+        "",
+        &token2,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 404);
+}
+
+#[test]
+fn test_game_invites() {
+    // Setup - user1 has a game
+    let state = util::test_state();
+    let (user1, user2) = util::test_users();
+    let token1 = util::sign_up(&state, &user1);
+    let token2 = util::sign_up(&state, &user2);
+    let request = util::build_request("POST", "/rrr-game", "", &token1);
+    let response = util::parse_response(process_request(request, &state));
+    let game_id = get_game_id(&response.body.unwrap());
+    let invite_alex = || {
+        let request = util::build_request(
+            "POST",
+            &format!("/rrr-game/{}/invites", game_id),
+            &format!("{{\"username\":\"{}\"}}", user2.username),
+            &token1,
+        );
+        util::parse_response(process_request(request, &state))
+    };
+
+    // Can only invite friends
+    assert_eq!(invite_alex().status_code, 403);
+    for (user, token, friend) in [(&user1, &token1, &user2), (&user2, &token2, &user1)] {
+        let request = util::build_request(
+            "POST",
+            &format!("/users/{}/friends", user.username),
+            &format!("{{\"username\":\"{}\"}}", friend.username),
+            token,
+        );
+        let response = util::parse_response(process_request(request, &state));
+        assert_eq!(response.status_code, 200);
+    }
+    assert_eq!(invite_alex().status_code, 200);
+    assert_eq!(invite_alex().status_code, 409);
+
+    // Can only invite people to your own game
+    let request = util::build_request(
+        "POST",
+        "/rrr-game/abcdefg/invites",
+        &format!("{{\"username\":\"{}\"}}", user2.username),
+        &token1,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 403);
+
+    // Invitee can see the invite
+    let request = util::build_request(
+        "GET",
+        &format!("/users/{}/invites", user2.username),
+        "",
+        &token2,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    let body = response.body.unwrap();
+    assert!(body.contains(&format!("\"game_id\":\"{}\"", game_id)));
+    assert!(body.contains("\"from\":\"james\""));
+
+    // Accepting joins the game, and uses up the invite
+    let request = util::build_request(
+        "POST",
+        &format!("/users/{}/invites", user2.username),
+        &format!("{{\"game_id\":\"{}\"}}", game_id),
+        &token2,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 200);
+    let chunk = state.db.get(&format!("rrr-game:{}:0-0", game_id)).unwrap();
+    assert!(chunk.contains("\"alex\""));
+
+    let request = util::build_request(
+        "GET",
+        &format!("/users/{}/invites", user2.username),
+        "",
+        &token2,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.body.unwrap(), "{\"invites\":[]}");
+
+    // Declining an invite that doesn't exist
+    let request = util::build_request(
+        "DELETE",
+        &format!("/users/{}/invites", user2.username),
+        &format!("{{\"game_id\":\"{}\"}}", game_id),
+        &token2,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 404);
+}
+
+#[test]
+fn test_list_games() {
+    // Setup - user1 has a game, user2 is an admin
+    let (user1, user2) = util::test_users();
+    let state = util::test_state();
+    let token1 = util::sign_up(&state, &user1);
+    let token2 = util::sign_up_admin(&state, &user2);
+    let request = util::build_request("POST", "/rrr-game", "", &token1);
+    let response = util::parse_response(process_request(request, &state));
+    let game_id = get_game_id(&response.body.unwrap());
+    let list_games = |params: &str, token: &str| {
+        let request = util::build_request("GET", &format!("/rrr-game{}", params), "", token);
+        let response = util::parse_response(process_request(request, &state));
+        assert_eq!(response.status_code, 200);
+        response.body.unwrap()
+    };
+
+    // Listed with its details
+    let body = list_games("", &token2);
+    assert!(body.contains(&format!("\"game_id\":\"{}\"", game_id)));
+    assert!(body.contains("\"creator\":\"james\""));
+    assert!(body.contains("\"player_count\":1"));
+    assert!(body.contains("\"max_players\":8"));
+    assert!(body.contains("\"status\":\"open\""));
+
+    // Filters
+    assert!(list_games("?mine=true", &token1).contains(&game_id));
+    assert_eq!(list_games("?mine=true", &token2), "{\"games\":[]}");
+    assert_eq!(list_games("?friends=true", &token2), "{\"games\":[]}");
+    assert!(list_games("?has-space=true", &token2).contains(&game_id));
+
+    for (user, token, friend) in [(&user1, &token1, &user2), (&user2, &token2, &user1)] {
+        let request = util::build_request(
+            "POST",
+            &format!("/users/{}/friends", user.username),
+            &format!("{{\"username\":\"{}\"}}", friend.username),
+            token,
+        );
+        util::parse_response(process_request(request, &state));
+    }
+    assert!(list_games("?friends=true", &token2).contains(&game_id));
+
+    // Player count follows joins
+    let request = util::build_request(
+        "POST",
+        &format!("/rrr-game/{}/players", game_id),
+        "",
+        &token2,
+    );
+    util::parse_response(process_request(request, &state));
+    assert!(list_games("", &token2).contains("\"player_count\":2"));
+
+    // Deleted games go
+    let request = util::build_request("DELETE", &format!("/rrr-game/{}", game_id), "", &token2);
+    util::parse_response(process_request(request, &state));
+    assert_eq!(list_games("", &token2), "{\"games\":[]}");
+}
+
+#[test]
+fn test_game_visibility() {
+    // Setup
+    let state = util::test_state();
+    let (user1, user2) = util::test_users();
+    let token1 = util::sign_up(&state, &user1);
+    let token2 = util::sign_up(&state, &user2);
+    let create_game = |body: &str, token: &str| {
+        let request = util::build_request("POST", "/rrr-game", body, token);
+        util::parse_response(process_request(request, &state))
+    };
+    let join_game = |game_id: &str, body: &str, token: &str| {
+        let request = util::build_request(
+            "POST",
+            &format!("/rrr-game/{}/players", game_id),
+            body,
+            token,
+        );
+        util::parse_response(process_request(request, &state)).status_code
+    };
+    let leave_game = |game_id: &str, token: &str| {
+        let request = util::build_request(
+            "DELETE",
+            &format!("/rrr-game/{}/players", game_id),
+            "",
+            token,
+        );
+        util::parse_response(process_request(request, &state)).status_code
+    };
+    let list_games = |token: &str| {
+        let request = util::build_request("GET", "/rrr-game", "", token);
+        util::parse_response(process_request(request, &state))
+            .body
+            .unwrap()
+    };
+
+    // Bad settings
+    let response = create_game("{\"visibility\":\"secret\"}", &token1);
+    assert_eq!(response.status_code, 400);
+    let response = create_game(
+        "{\"visibility\":\"private\", \"password\":\"abc\"}",
+        &token1,
+    );
+    assert_eq!(response.status_code, 400);
+
+    // Unlisted games aren't listed, except for their players, but can be joined
+    let response = create_game("{\"visibility\":\"unlisted\"}", &token1);
+    assert_eq!(response.status_code, 200);
+    let game_id = get_game_id(&response.body.unwrap());
+    assert!(list_games(&token1).contains("\"visibility\":\"unlisted\""));
+    assert_eq!(list_games(&token2), "{\"games\":[]}");
+    assert_eq!(join_game(&game_id, "", &token2), 200);
+    assert_eq!(leave_game(&game_id, &token2), 200);
+    assert_eq!(leave_game(&game_id, &token1), 200);
+
+    // Private games need the password
+    let response = create_game(
+        "{\"visibility\":\"private\", \"password\":\"opensesame\"}",
+        &token1,
+    );
+    assert_eq!(response.status_code, 200);
+    let game_id = get_game_id(&response.body.unwrap());
+    assert_eq!(list_games(&token2), "{\"games\":[]}");
+    assert!(list_games(&token1).contains("\"has_password\":true"));
+    assert_eq!(join_game(&game_id, "", &token2), 403);
+    assert_eq!(
+        join_game(&game_id, "{\"password\":\"wrong\"}", &token2),
+        403
+    );
+    assert_eq!(
+        join_game(&game_id, "{\"password\":\"opensesame\"}", &token2),
+        200
+    );
+    assert_eq!(leave_game(&game_id, &token2), 200);
+    assert_eq!(leave_game(&game_id, &token1), 200);
+
+    // Private games without a password are invite only
+    let response = create_game("{\"visibility\":\"private\"}", &token1);
+    let game_id = get_game_id(&response.body.unwrap());
+    assert_eq!(join_game(&game_id, "", &token2), 403);
+}
+
+#[test]
+fn test_game_meta() {
+    // Setup - user2 is an admin
+    let (user1, user2) = util::test_users();
+    let state = util::test_state();
+    let token1 = util::sign_up(&state, &user1);
+    let token2 = util::sign_up_admin(&state, &user2);
+
+    // Games that don't exist are 404s
+    let request = util::build_request("GET", "/rrr-game/abcdefg?x=0&y=0", "", &token1);
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 404);
+    let request = util::build_request(
+        "POST",
+        "/rrr-game/abcdefg/actions?x=0&y=0",
+        "{\"move\":\"East\"}",
+        &token1,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 404);
+
+    // Create a game, the creator is on the roster
+    let request = util::build_request("POST", "/rrr-game", "", &token1);
+    let response = util::parse_response(process_request(request, &state));
+    let game_id = get_game_id(&response.body.unwrap());
+    let meta = state.db.get(&format!("rrr-game-meta:{}", game_id)).unwrap();
+    assert!(meta.contains("\"creator\":\"james\""));
+    assert!(meta.contains("\"roster\":{\"james\":{\"joined_at\":"));
+
+    // Even if the player goes missing from the gamestate, deleting the game
+    // still frees them up to play another
+    let chunk_key = format!("rrr-game:{}:0-0", game_id);
+    let chunk = state.db.get(&chunk_key).unwrap();
+    state
+        .db
+        .set(chunk_key, chunk.replace("\"james\"", "\"nobody\""));
+    let request = util::build_request("DELETE", &format!("/rrr-game/{}", game_id), "", &token2);
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 200);
+    assert!(state
+        .db
+        .get(&format!("rrr-game-meta:{}", game_id))
+        .is_none());
+
+    let request = util::build_request("POST", "/rrr-game", "", &token1);
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 200);
+}
+
+#[test]
+fn test_game_membership() {
+    // Setup - user1 has a game
+    let state = util::test_state();
+    let (user1, user2) = util::test_users();
+    let token1 = util::sign_up(&state, &user1);
+    let token2 = util::sign_up(&state, &user2);
+    let request = util::build_request("POST", "/rrr-game", "", &token1);
+    let response = util::parse_response(process_request(request, &state));
+    let game_id = get_game_id(&response.body.unwrap());
+    let get_gamestate = |token: &str| {
+        let request =
+            util::build_request("GET", &format!("/rrr-game/{}?x=0&y=0", game_id), "", token);
+        util::parse_response(process_request(request, &state))
+    };
+    let do_action = |token: &str| {
+        let request = util::build_request(
+            "POST",
+            &format!("/rrr-game/{}/actions?x=0&y=0", game_id),
+            "{\"move\":\"East\"}",
+            token,
+        );
+        util::parse_response(process_request(request, &state))
+    };
+
+    // user2 can't see or play user1's game
+    assert_eq!(get_gamestate(&token2).status_code, 403);
+    assert_eq!(do_action(&token2).status_code, 403);
+    assert_eq!(get_gamestate(&token1).status_code, 200);
+
+    // Until they join
+    let request = util::build_request(
+        "POST",
+        &format!("/rrr-game/{}/players", game_id),
+        "",
+        &token2,
+    );
+    util::parse_response(process_request(request, &state));
+    assert_eq!(get_gamestate(&token2).status_code, 200);
+    assert_eq!(do_action(&token2).status_code, 200);
+
+    // And lose access again when they leave
+    let request = util::build_request(
+        "DELETE",
+        &format!("/rrr-game/{}/players", game_id),
+        "",
+        &token2,
+    );
+    util::parse_response(process_request(request, &state));
+    assert_eq!(get_gamestate(&token2).status_code, 403);
+    assert_eq!(do_action(&token2).status_code, 403);
+}
+
+#[test]
+fn test_server_tracks_position() {
+    // Setup - a game with nothing in the way
+    let state = util::test_state();
+    let (user1, _) = util::test_users();
+    let token = util::sign_up(&state, &user1);
+    let request = util::build_request("POST", "/rrr-game", "", &token);
+    let response = util::parse_response(process_request(request, &state));
+    let game_id = get_game_id(&response.body.unwrap());
+    util::flatten_game(&state, &game_id);
+
+    // Coords are optional, and stale ones are ignored. Five moves East takes
+    // the user into the next chunk.
+    for (step, params) in ["", "?x=0&y=0", "?x=100&y=-100", "", ""].iter().enumerate() {
+        let request = util::build_request(
+            "POST",
+            &format!("/rrr-game/{}/actions{}", game_id, params),
+            "{\"move\":\"East\"}",
+            &token,
+        );
+        let response = util::parse_response(process_request(request, &state));
+        assert_eq!(response.status_code, 200);
+        assert!(response
+            .body
+            .unwrap()
+            .contains(&format!("\"user_coord\":{{\"x\":{},\"y\":0}}", step + 1)));
+    }
+    let old_chunk = state.db.get(&format!("rrr-game:{}:0-0", game_id)).unwrap();
+    assert!(!old_chunk.contains("\"james\""));
+    let new_chunk = state.db.get(&format!("rrr-game:{}:1-0", game_id)).unwrap();
+    assert!(new_chunk.contains("\"james\":{\"x\":5,\"y\":0}"));
+
+    // The new chunk's neighbours are made, so the gamestate can be seen
+    for params in ["", "?x=0&y=0"] {
+        let request = util::build_request(
+            "GET",
+            &format!("/rrr-game/{}{}", game_id, params),
+            "",
+            &token,
+        );
+        let response = util::parse_response(process_request(request, &state));
+        assert_eq!(response.status_code, 200);
+        let body = response.body.unwrap();
+        assert!(body.contains("\"james\":{\"x\":5,\"y\":0}"));
+        assert!(body.contains("\"top_left_coord\":{\"x\":-4,\"y\":-13}"));
+    }
+}
+
+#[test]
+fn test_spectators() {
+    // Setup - user1 has a game
+    let state = util::test_state();
+    let (user1, user2) = util::test_users();
+    let token1 = util::sign_up(&state, &user1);
+    let token2 = util::sign_up(&state, &user2);
+    let request = util::build_request("POST", "/rrr-game", "", &token1);
+    let response = util::parse_response(process_request(request, &state));
+    let game_id = get_game_id(&response.body.unwrap());
+    let spectate = |method: &str| {
+        let request = util::build_request(
+            method,
+            &format!("/rrr-game/{}/spectators", game_id),
+            "",
+            &token2,
+        );
+        util::parse_response(process_request(request, &state)).status_code
+    };
+    let get_gamestate = |params: &str| {
+        let request = util::build_request(
+            "GET",
+            &format!("/rrr-game/{}{}", game_id, params),
+            "",
+            &token2,
+        );
+        util::parse_response(process_request(request, &state))
+    };
+
+    assert_eq!(get_gamestate("").status_code, 403);
+    assert_eq!(spectate("POST"), 200);
+    assert_eq!(spectate("POST"), 409);
+
+    // Spectators see the gamestate, but aren't in it
+    let response = get_gamestate("");
+    assert_eq!(response.status_code, 200);
+    let body = response.body.unwrap();
+    assert!(body.contains("\"james\":{\"x\":0,\"y\":0}"));
+    assert!(!body.contains("\"alex\""));
+
+    // And can move the viewport about, as long as there's something there
+    let response = get_gamestate("?x=-4&y=4");
+    assert_eq!(response.status_code, 200);
+    assert!(response
+        .body
+        .unwrap()
+        .contains("\"top_left_coord\":{\"x\":-13,\"y\":-13}"));
+    assert_eq!(get_gamestate("?x=100&y=100").status_code, 400);
+
+    // But can't play, or take up a space
+    let request = util::build_request(
+        "POST",
+        &format!("/rrr-game/{}/actions", game_id),
+        "{\"move\":\"East\"}",
+        &token2,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 403);
+    let request = util::build_request("GET", "/rrr-game", "", &token2);
+    let response = util::parse_response(process_request(request, &state));
+    assert!(response.body.unwrap().contains("\"player_count\":1"));
+
+    // Stop spectating
+    assert_eq!(spectate("DELETE"), 200);
+    assert_eq!(spectate("DELETE"), 404);
+    assert_eq!(get_gamestate("").status_code, 403);
+}
+
+#[test]
+fn test_queued_actions_on_tick() {
+    // Setup - a game with nothing in the way
+    let state = util::test_state();
+    let (user1, _) = util::test_users();
+    let token = util::sign_up(&state, &user1);
+    let request = util::build_request("POST", "/rrr-game", "", &token);
+    let response = util::parse_response(process_request(request, &state));
+    let game_id = get_game_id(&response.body.unwrap());
+    util::flatten_game(&state, &game_id);
+
+    // Queue up moves, nothing happens until the tick
+    for (direction, queued) in [("East", 1), ("East", 2), ("South", 3)] {
+        let request = util::build_request(
+            "POST",
+            &format!("/rrr-game/{}/actions", game_id),
+            &format!("{{\"move\":\"{}\", \"queue\":true}}", direction),
+            &token,
+        );
+        let response = util::parse_response(process_request(request, &state));
+        assert_eq!(response.status_code, 200);
+        // Numbered along with the player's other actions
+        assert_eq!(
+            response.body.unwrap(),
+            format!("{{\"queued\":{},\"action_seqs\":[{}]}}", queued, queued)
+        );
+    }
+    let chunk_key = format!("rrr-game:{}:0-0", game_id);
+    assert!(state
+        .db
+        .get(&chunk_key)
+        .unwrap()
+        .contains("\"james\":{\"x\":0,\"y\":0}"));
+
+    // The tick applies them in order
+    let scheduler = TickScheduler::new(Duration::from_millis(10), Arc::clone(&state.db));
+    let start = Instant::now();
+    while !state
+        .db
+        .get(&chunk_key)
+        .unwrap()
+        .contains("\"james\":{\"x\":2,\"y\":1}")
+    {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(10));
+    }
+    drop(scheduler);
+    assert!(state
+        .db
+        .keys(&format!("rrr-game-queue:{}:", game_id))
+        .is_empty());
+}
+
+#[test]
+fn test_queued_moves_and_targets() {
+    // Setup - a game with nothing in the way
+    let state = util::test_state();
+    let (user1, user2) = util::test_users();
+    let token = util::sign_up(&state, &user1);
+    let token2 = util::sign_up(&state, &user2);
+    let request = util::build_request("POST", "/rrr-game", "", &token);
+    let response = util::parse_response(process_request(request, &state));
+    let game_id = get_game_id(&response.body.unwrap());
+    util::flatten_game(&state, &game_id);
+    let actions_route = format!("/rrr-game/{}/actions", game_id);
+    let get_queue = || {
+        let request = util::build_request("GET", &actions_route, "", &token);
+        let response = util::parse_response(process_request(request, &state));
+        assert_eq!(response.status_code, 200);
+        response.body.unwrap()
+    };
+
+    // Needs exactly one kind of move
+    for body in [
+        "{}",
+        "{\"move\":\"East\", \"moves\":[\"East\"]}",
+        "{\"move\":\"East\", \"target\":{\"x\":1,\"y\":0}}",
+    ] {
+        let request = util::build_request("POST", &actions_route, body, &token);
+        let response = util::parse_response(process_request(request, &state));
+        assert_eq!(response.status_code, 400);
+    }
+
+    // Queue a sequence, look at it and cancel it
+    let request = util::build_request(
+        "POST",
+        &actions_route,
+        "{\"moves\":[\"East\", \"East\"]}",
+        &token,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 200);
+    assert_eq!(
+        response.body.unwrap(),
+        "{\"queued\":2,\"action_seqs\":[1,2]}"
+    );
+    assert_eq!(
+        get_queue(),
+        "{\"moves\":[\"East\",\"East\"],\"action_seqs\":[1,2],\"outcomes\":{}}"
+    );
+
+    let request = util::build_request("DELETE", &actions_route, "", &token);
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 200);
+    assert_eq!(
+        get_queue(),
+        "{\"moves\":[],\"action_seqs\":[],\"outcomes\":{\"1\":\"Cancelled\",\"2\":\"Cancelled\"}}"
+    );
+
+    // Too many at once
+    let moves = vec!["\"East\""; 101].join(",");
+    let request = util::build_request(
+        "POST",
+        &actions_route,
+        &format!("{{\"moves\":[{}]}}", moves),
+        &token,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 400);
+
+    // Only players can see the queue
+    let request = util::build_request("GET", &actions_route, "", &token2);
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 403);
+
+    // Off the edge of the world
+    let request = util::build_request(
+        "POST",
+        &actions_route,
+        "{\"target\":{\"x\":100,\"y\":0}}",
+        &token,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 400);
+
+    // A target replaces what was queued with the way there
+    let request = util::build_request("POST", &actions_route, "{\"moves\":[\"North\"]}", &token);
+    util::parse_response(process_request(request, &state));
+    let request = util::build_request(
+        "POST",
+        &actions_route,
+        "{\"target\":{\"x\":2,\"y\":1}}",
+        &token,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 200);
+    // Moves that were turned down or cancelled still used up their numbers
+    assert_eq!(
+        response.body.unwrap(),
+        "{\"queued\":3,\"action_seqs\":[4,5,6]}"
+    );
+
+    // Walked one step a tick
+    let chunk_key = format!("rrr-game:{}:0-0", game_id);
+    let scheduler = TickScheduler::new(Duration::from_millis(10), Arc::clone(&state.db));
+    let start = Instant::now();
+    while !state
+        .db
+        .get(&chunk_key)
+        .unwrap()
+        .contains("\"james\":{\"x\":2,\"y\":1}")
+    {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(10));
+    }
+    drop(scheduler);
+    assert_eq!(
+        get_queue(),
+        "{\"moves\":[],\"action_seqs\":[],\"outcomes\":{\"1\":\"Cancelled\",\"2\":\"Cancelled\",\
+         \"3\":\"Cancelled\",\"4\":\"Moved\",\"5\":\"Moved\",\"6\":\"Moved\"}}"
+    );
+}
+
+#[test]
+fn test_blocked_queue_is_cancelled() {
+    // Setup - rock two tiles east of the player, at (2, 0)
+    let state = util::test_state();
+    let (user1, _) = util::test_users();
+    let token = util::sign_up(&state, &user1);
+    let request = util::build_request("POST", "/rrr-game", "", &token);
+    let response = util::parse_response(process_request(request, &state));
+    let game_id = get_game_id(&response.body.unwrap());
+    util::flatten_game(&state, &game_id);
+    let chunk_key = format!("rrr-game:{}:0-0", game_id);
+    let mut chunk: serde_json::Value =
+        serde_json::from_str(&state.db.get(&chunk_key).unwrap()).unwrap();
+    chunk["terrain"][4][6] = "R".into();
+    state.db.set(chunk_key.clone(), chunk.to_string());
+    let actions_route = format!("/rrr-game/{}/actions", game_id);
+
+    // A path that walks into it
+    let request = util::build_request(
+        "POST",
+        &actions_route,
+        "{\"moves\":[\"East\", \"East\", \"South\", \"South\"]}",
+        &token,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 200);
+
+    // Stops at the rock, and the rest of the path is dropped rather than
+    // walked from the wrong place
+    let queue_prefix = format!("rrr-game-queue:{}:", game_id);
+    let scheduler = TickScheduler::new(Duration::from_millis(10), Arc::clone(&state.db));
+    let start = Instant::now();
+    while !state.db.keys(&queue_prefix).is_empty() {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(10));
+    }
+    drop(scheduler);
+    assert!(state
+        .db
+        .get(&chunk_key)
+        .unwrap()
+        .contains("\"james\":{\"x\":1,\"y\":0}"));
+
+    // Each step says what happened to it
+    let request = util::build_request("GET", &actions_route, "", &token);
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(
+        response.body.unwrap(),
+        "{\"moves\":[],\"action_seqs\":[],\"outcomes\":{\"1\":\"Moved\",\"2\":\"BlockedByTerrain\",\
+         \"3\":\"Cancelled\",\"4\":\"Cancelled\"}}"
+    );
+}
+
+#[test]
+fn test_player_collision() {
+    let state = util::test_state();
+    let (user1, user2) = util::test_users();
+    let token1 = util::sign_up(&state, &user1);
+    let token2 = util::sign_up(&state, &user2);
+    let do_move = |direction: &str, game_id: &str, token: &str| {
+        let request = util::build_request(
+            "POST",
+            &format!("/rrr-game/{}/actions", game_id),
+            &format!("{{\"move\":\"{}\"}}", direction),
+            token,
+        );
+        let response = util::parse_response(process_request(request, &state));
+        assert_eq!(response.status_code, 200);
+        response.body.unwrap()
+    };
+
+    for pass_through in [false, true] {
+        // Setup - both players in a game with nothing in the way
+        let body = format!("{{\"pass_through\":{}}}", pass_through);
+        let request = util::build_request("POST", "/rrr-game", &body, &token1);
+        let response = util::parse_response(process_request(request, &state));
+        let game_id = get_game_id(&response.body.unwrap());
+        let request = util::build_request(
+            "POST",
+            &format!("/rrr-game/{}/players", game_id),
+            "",
+            &token2,
+        );
+        let response = util::parse_response(process_request(request, &state));
+        assert_eq!(response.status_code, 200);
+        util::flatten_game(&state, &game_id);
+
+        // Across the chunk border, alex at (5, 0) and james at (4, 0). alex
+        // starts at (0, -1), next to james.
+        for _ in 0..4 {
+            do_move("East", &game_id, &token1);
+        }
+        for _ in 0..5 {
+            do_move("East", &game_id, &token2);
+        }
+        assert!(do_move("South", &game_id, &token2).contains("\"user_coord\":{\"x\":5,\"y\":0}"));
+        let body = do_move("East", &game_id, &token1);
+        if pass_through {
+            assert!(body.contains("\"user_coord\":{\"x\":5,\"y\":0}"));
+            assert!(body.contains("\"outcome\":\"Moved\""));
+        } else {
+            assert!(body.contains("\"user_coord\":{\"x\":4,\"y\":0}"));
+            assert!(body.contains("\"outcome\":\"BlockedByPlayer\""));
+        }
+
+        // Leave, so the next game can be made
+        for token in [&token1, &token2] {
+            let request = util::build_request(
+                "DELETE",
+                &format!("/rrr-game/{}/players", game_id),
+                "",
+                token,
+            );
+            let response = util::parse_response(process_request(request, &state));
+            assert_eq!(response.status_code, 200);
+        }
+    }
+}
+
+#[test]
+fn test_ticks_and_moves_together() {
+    // Setup - both players in a game with nothing in the way
+    let state = util::test_state();
+    let (user1, user2) = util::test_users();
+    let token1 = util::sign_up(&state, &user1);
+    let token2 = util::sign_up(&state, &user2);
+    let request = util::build_request("POST", "/rrr-game", "", &token1);
+    let response = util::parse_response(process_request(request, &state));
+    let game_id = get_game_id(&response.body.unwrap());
+    let request = util::build_request(
+        "POST",
+        &format!("/rrr-game/{}/players", game_id),
+        "",
+        &token2,
+    );
+    assert_eq!(
+        util::parse_response(process_request(request, &state)).status_code,
+        200
+    );
+    util::flatten_game(&state, &game_id);
+    let actions_route = format!("/rrr-game/{}/actions", game_id);
+    let do_move = |direction: &str, token: &str| {
+        let body = format!("{{\"move\":\"{}\"}}", direction);
+        let request = util::build_request("POST", &actions_route, &body, token);
+        let response = util::parse_response(process_request(request, &state));
+        assert_eq!(response.status_code, 200);
+    };
+
+    // james at (4, 0) on the chunk border, alex at (5, -1) over it
+    for _ in 0..4 {
+        do_move("East", &token1);
+    }
+    for _ in 0..5 {
+        do_move("East", &token2);
+    }
+
+    // james goes back and forth over the border, on the tick and straight
+    // away, while alex keeps stepping on and off the tile james is going for
+    let moves = vec!["\"East\", \"West\""; 50].join(",");
+    let request = util::build_request(
+        "POST",
+        &actions_route,
+        &format!("{{\"moves\":[{}]}}", moves),
+        &token1,
+    );
+    assert_eq!(
+        util::parse_response(process_request(request, &state)).status_code,
+        200
+    );
+    let queue_prefix = format!("rrr-game-queue:{}:", game_id);
+    let scheduler = TickScheduler::new(Duration::from_millis(1), Arc::clone(&state.db));
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for (token, there, back) in [(&token1, "East", "West"), (&token2, "South", "North")] {
+            let (state, do_move, queue_prefix) = (&state, &do_move, &queue_prefix);
+            scope.spawn(move || {
+                while !state.db.keys(queue_prefix).is_empty() {
+                    assert!(start.elapsed() < Duration::from_secs(10));
+                    do_move(there, token);
+                    do_move(back, token);
+                }
+            });
+        }
+    });
+    drop(scheduler);
+
+    // Each player is in one chunk, where the server thinks they are, and
+    // never on the same tile
+    let mut found = vec![];
+    for key in state.db.keys(&format!("rrr-game:{}:", game_id)) {
+        let chunk: serde_json::Value = serde_json::from_str(&state.db.get(&key).unwrap()).unwrap();
+        for (username, user_coord) in chunk["users"].as_object().unwrap() {
+            found.push((username.clone(), user_coord.clone()));
+        }
+    }
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(found.len(), 2, "{:?}", found);
+    assert_eq!(found[0].0, user2.username);
+    assert_eq!(found[1].0, user1.username);
+    assert_ne!(found[0].1, found[1].1);
+    for (username, user_coord) in &found {
+        let key = format!("rrr-game-player:{}:{}", game_id, username);
+        let entry: serde_json::Value = serde_json::from_str(&state.db.get(&key).unwrap()).unwrap();
+        assert_eq!(&entry["user_coord"], user_coord);
+    }
+}
+
+#[test]
+fn test_joins_dont_overfill() {
+    // Setup - a game, and more people wanting in than there's room for
+    let state = util::test_state_with_config(Config {
+        rate_limits: HashMap::from([(
+            "POST /users".to_string(),
+            RateLimit {
+                capacity: 20.0,
+                refill_per_s: 1.0,
+            },
+        )]),
+        ..Config::default()
+    });
+    let (user1, _) = util::test_users();
+    let token1 = util::sign_up(&state, &user1);
+    let request = util::build_request("POST", "/rrr-game", "", &token1);
+    let response = util::parse_response(process_request(request, &state));
+    let game_id = get_game_id(&response.body.unwrap());
+    let tokens: Vec<String> = (0..11)
+        .map(|i| {
+            util::sign_up(
+                &state,
+                &util::User {
+                    username: format!("player{}", i),
+                    email: format!("player{}@example.com", i),
+                    password: "testpassword".to_string(),
+                },
+            )
+        })
+        .collect();
+
+    // Everyone joins at once
+    let route = format!("/rrr-game/{}/players", game_id);
+    let status_codes: Vec<u32> = thread::scope(|scope| {
+        let joins: Vec<_> = tokens
+            .iter()
+            .map(|token| {
+                let (state, route) = (&state, &route);
+                scope.spawn(move || {
+                    let request = util::build_request("POST", route, "", token);
+                    util::parse_response(process_request(request, state)).status_code
+                })
+            })
+            .collect();
+        joins.into_iter().map(|join| join.join().unwrap()).collect()
+    });
+
+    // Only as many as there's room for get in, each on their own tile
+    assert_eq!(status_codes.iter().filter(|code| **code == 200).count(), 7);
+    assert_eq!(status_codes.iter().filter(|code| **code == 409).count(), 4);
+    let mut found = vec![];
+    for key in state.db.keys(&format!("rrr-game:{}:", game_id)) {
+        let chunk: serde_json::Value = serde_json::from_str(&state.db.get(&key).unwrap()).unwrap();
+        found.extend(chunk["users"].as_object().unwrap().values().cloned());
+    }
+    assert_eq!(found.len(), 8);
+    for (i, user_coord) in found.iter().enumerate() {
+        assert!(!found[i + 1..].contains(user_coord));
+    }
+}
+
+#[test]
+fn test_forest_is_trampled() {
+    // Setup - forest next to the player, at (1, 0)
+    let state = util::test_state();
+    let (user1, _) = util::test_users();
+    let token = util::sign_up(&state, &user1);
+    let request = util::build_request("POST", "/rrr-game", "", &token);
+    let response = util::parse_response(process_request(request, &state));
+    let game_id = get_game_id(&response.body.unwrap());
+    util::flatten_game(&state, &game_id);
+    let chunk_key = format!("rrr-game:{}:0-0", game_id);
+    let get_chunk = || -> serde_json::Value {
+        serde_json::from_str(&state.db.get(&chunk_key).unwrap()).unwrap()
+    };
+    let mut chunk = get_chunk();
+    chunk["terrain"][4][5] = "F".into();
+    state.db.set(chunk_key.clone(), chunk.to_string());
+
+    // Walking onto it leaves grass behind
+    let request = util::build_request(
+        "POST",
+        &format!("/rrr-game/{}/actions", game_id),
+        "{\"move\":\"East\"}",
+        &token,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    let body = response.body.unwrap();
+    assert!(body.contains("\"outcome\":\"Moved\""));
+    assert!(body.contains("\"tile\":\"F\""));
+    assert_eq!(get_chunk()["terrain"][4][5], "G");
+
+    // Until it grows back on a later tick
+    let timers = state.db.keys(&format!("rrr-game-timer:{}:", game_id));
+    assert_eq!(timers.len(), 1);
+    let timer = state.db.get(&timers[0]).unwrap();
+    assert_eq!(
+        timer,
+        "{\"Regrow\":{\"user_coord\":{\"x\":1,\"y\":0},\"tile\":\"F\"}}"
+    );
+}
+
+#[test]
+fn test_action_outcomes() {
+    let state = util::test_state();
+    let (user1, _) = util::test_users();
+    let token = util::sign_up(&state, &user1);
+    let get_action_seq = |body: &str| -> u64 {
+        let re = Regex::new("\"action_seq\":(?<action_seq>[0-9]+)").unwrap();
+        re.captures(body).unwrap()["action_seq"].parse().unwrap()
+    };
+    let do_move = |direction: &str, game_id: &str| {
+        let request = util::build_request(
+            "POST",
+            &format!("/rrr-game/{}/actions", game_id),
+            &format!("{{\"move\":\"{}\"}}", direction),
+            &token,
+        );
+        let response = util::parse_response(process_request(request, &state));
+        assert_eq!(response.status_code, 200);
+        response.body.unwrap()
+    };
+
+    // Setup - just one chunk, all grass
+    let terrain = vec![format!("[{}]", ["\"G\""; 9].join(",")); 9].join(",");
+    state.db.set(
+        "rrr-game:1234567:0-0".to_string(),
+        format!(
+            "{{\"coord\":{{\"x\":0,\"y\":0}},\"terrain\":[{}],\
+            \"users\":{{\"james\":{{\"x\":-4,\"y\":0}}}}}}",
+            terrain
+        ),
+    );
+    util::insert_game_meta(&state, "1234567", &["james"]);
+
+    // Moves onto grass
+    let body = do_move("South", "1234567");
+    assert!(body.contains("\"outcome\":\"Moved\",\"tile\":\"G\""));
+    assert!(body.contains("\"user_coord\":{\"x\":-4,\"y\":1}"));
+    // Each player's actions are numbered from 1 in each game
+    assert_eq!(get_action_seq(&body), 1);
+
+    // Off the edge of the world
+    let body = do_move("West", "1234567");
+    assert!(body.contains("\"outcome\":\"OutOfBounds\",\"tile\":null"));
+    assert!(body.contains("\"user_coord\":{\"x\":-4,\"y\":1}"));
+    assert_eq!(get_action_seq(&body), 2);
+
+    // Games can limit how often players move
+    let request = util::build_request(
+        "POST",
+        "/rrr-game",
+        "{\"min_move_interval_ms\":60000}",
+        &token,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    let game_id = get_game_id(&response.body.unwrap());
+    util::flatten_game(&state, &game_id);
+    assert!(do_move("East", &game_id).contains("\"outcome\":\"Moved\""));
+    let body = do_move("East", &game_id);
+    assert!(body.contains("\"outcome\":\"RateLimited\""));
+    assert!(body.contains("\"user_coord\":{\"x\":1,\"y\":0}"));
+}
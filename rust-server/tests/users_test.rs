@@ -1,4 +1,8 @@
-use rust_book_server_example::{process_request, Database, LocalDatabase};
+use jsonwebtoken::Algorithm;
+use rust_book_server_example::{
+    migrate_user_keys, process_request, process_request_from, promote_admins, Config, Database,
+    KeyRing, LocalDatabase, OutboxMailer, ServerState,
+};
 use std::sync::Arc;
 
 mod util;
@@ -6,7 +10,7 @@ mod util;
 #[test]
 fn test_create_user() {
     // Setup
-    let db = Arc::new(LocalDatabase::new());
+    let state = util::test_state();
     let (user1, user2) = util::test_users();
 
     // Create user
@@ -19,12 +23,12 @@ fn test_create_user() {
         ),
         "",
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify create user
     assert_eq!(response.status_code, 200);
-    assert_ne!(db.get(&user1.username), None);
+    assert_ne!(state.db.get(&format!("user:{}", user1.username)), None);
 
     // Invalid create user request - bad body
     let request = util::build_request(
@@ -36,18 +40,18 @@ fn test_create_user() {
         ),
         "",
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify failed
     assert_eq!(response.status_code, 400);
-    assert_eq!(db.get(&user2.username), None);
+    assert_eq!(state.db.get(&format!("user:{}", user2.username)), None);
 }
 
 #[test]
 fn test_login() {
     // Setup
-    let db = Arc::new(LocalDatabase::new());
+    let state = util::test_state();
     let (user1, _) = util::test_users();
     let request = util::build_request(
         "POST",
@@ -58,7 +62,7 @@ fn test_login() {
         ),
         "",
     );
-    process_request(request, Arc::clone(&db));
+    process_request(request, &state);
 
     // Valid login
     let request = util::build_request(
@@ -70,7 +74,7 @@ fn test_login() {
         ),
         "",
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify succeeded
@@ -82,12 +86,11 @@ fn test_login() {
         "/sessions",
         &format!(
             "{{\"username\":\"{}\", \"password\":\"{}\"}}",
-            user1.username,
-            "wrong_password".to_string()
+            user1.username, "wrong_password"
         ),
         "",
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify login failed
@@ -99,12 +102,11 @@ fn test_login() {
         "/sessions",
         &format!(
             "{{\"username\":\"{}\", \"password_abc\":\"{}\"}}",
-            user1.username,
-            "wrong_password".to_string()
+            user1.username, "wrong_password"
         ),
         "",
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify failed
@@ -114,7 +116,7 @@ fn test_login() {
 #[test]
 fn test_token() {
     // Setup
-    let db = Arc::new(LocalDatabase::new());
+    let state = util::test_state();
     let (user1, user2) = util::test_users();
     let request = util::build_request(
         "POST",
@@ -125,7 +127,7 @@ fn test_token() {
         ),
         "",
     );
-    process_request(request, Arc::clone(&db));
+    process_request(request, &state);
 
     // Get token from valid login
     let request = util::build_request(
@@ -137,13 +139,13 @@ fn test_token() {
         ),
         "",
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
     let token = response.token.unwrap();
 
     // Verfiy token can be used
     let request = util::build_request("GET", &format!("/users/{}", user1.username), "", &token);
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
     assert_eq!(response.status_code, 200);
 
@@ -157,13 +159,13 @@ fn test_token() {
         ),
         "",
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
     let token = response.token.unwrap();
 
     // Verfiy token can be used
     let request = util::build_request("GET", &format!("/users/{}", user2.username), "", &token);
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
     assert_eq!(response.status_code, 200);
 
@@ -174,7 +176,7 @@ fn test_token() {
         "",
         "blah blah bad token",
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify get 401
@@ -182,7 +184,7 @@ fn test_token() {
 
     // TODO: Try to access a protected resoruce without the auth header
     // let request = util::build_request("GET", &format!("/users/{}", user2.username), "", "");
-    // let response = process_request(request, Arc::clone(&db));
+    // let response = process_request(request, &state);
     // let response = util::parse_response(response);
 
     // // Verify get 401
@@ -190,7 +192,7 @@ fn test_token() {
 
     // Try to access protected resource with blank token
     let request = util::build_request("GET", &format!("/users/{}", user2.username), "", "");
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
     // Verify get 401
@@ -206,23 +208,24 @@ fn test_token() {
         ),
         "",
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
     let token = response.token.unwrap();
 
     // Try to use it for user 2
     let request = util::build_request("GET", &format!("/users/{}", user2.username), "", &token);
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
     let response = util::parse_response(response);
 
-    // Verify get 403
-    assert_eq!(response.status_code, 403);
+    // Verify only get the public profile
+    assert_eq!(response.status_code, 200);
+    assert!(!response.body.unwrap().contains(&user2.email));
 }
 
 #[test]
 fn test_users_options() {
     // Given
-    let db = Arc::new(LocalDatabase::new());
+    let state = util::test_state();
     let request = "OPTIONS /users HTTP/1.1\r
 Host: localhost:7878\r
 Connection: keep-alive\r
@@ -240,7 +243,7 @@ Accept-Language: en-GB,en-US;q=0.9,en;q=0.8\r
 ".to_string();
 
     // When
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
 
     // Then
     assert!(response.contains("Access-Control-Allow-Origin"));
@@ -253,8 +256,8 @@ Accept-Language: en-GB,en-US;q=0.9,en;q=0.8\r
 #[test]
 fn test_cors_headers() {
     // Given
-    let db = Arc::new(LocalDatabase::new());
-    let (user1, user2) = util::test_users();
+    let state = util::test_state();
+    let (user1, _user2) = util::test_users();
 
     // When
     let request = util::build_request(
@@ -266,7 +269,7 @@ fn test_cors_headers() {
         ),
         "",
     );
-    let response = process_request(request, Arc::clone(&db));
+    let response = process_request(request, &state);
 
     // Then
     assert!(response.contains("Access-Control-Allow-Origin"));
@@ -274,7 +277,1027 @@ fn test_cors_headers() {
     assert!(response.contains("Access-Control-Max-Age"));
 }
 
-// TODO: tests
-// get user info
-// update user info
-// delete user
+#[test]
+fn test_token_from_other_server() {
+    // Setup - two servers with their own signing keys
+    let state = util::test_state();
+    let other_state = util::test_state();
+    let (user1, _) = util::test_users();
+    let request = util::build_request(
+        "POST",
+        "/users",
+        &format!(
+            "{{\"username\":\"{}\", \"email\":\"{}\", \"password\":\"{}\"}}",
+            user1.username, user1.email, user1.password
+        ),
+        "",
+    );
+    process_request(request.clone(), &state);
+    let response = process_request(request, &other_state);
+    let response = util::parse_response(response);
+    let other_token = response.token.unwrap();
+
+    // Try to use the token from the other server
+    let request = util::build_request(
+        "GET",
+        &format!("/users/{}", user1.username),
+        "",
+        &other_token,
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+
+    // Verify get 401
+    assert_eq!(response.status_code, 401);
+}
+
+#[test]
+fn test_get_public_keys() {
+    // Given
+    let state = ServerState::new(
+        Arc::new(LocalDatabase::new()),
+        KeyRing::generate(Algorithm::EdDSA).unwrap(),
+        Config::default(),
+        Box::new(OutboxMailer::new(&Config::default().outbox_dir)),
+    );
+
+    // When
+    let request = util::build_request("GET", "/keys", "", "");
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+
+    // Then
+    assert_eq!(response.status_code, 200);
+    assert!(response.body.unwrap().contains("\"alg\":\"EdDSA\""));
+}
+
+#[test]
+fn test_refresh_token() {
+    // Setup
+    let state = util::test_state();
+    let (user1, _) = util::test_users();
+    let request = util::build_request(
+        "POST",
+        "/users",
+        &format!(
+            "{{\"username\":\"{}\", \"email\":\"{}\", \"password\":\"{}\"}}",
+            user1.username, user1.email, user1.password
+        ),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    let refresh_token = response.refresh_token.unwrap();
+
+    // Refresh
+    let request = util::build_request(
+        "POST",
+        "/sessions/refresh",
+        &format!("{{\"refresh_token\":\"{}\"}}", refresh_token),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+
+    // Verify get new tokens, and the new access token works
+    assert_eq!(response.status_code, 200);
+    let token = response.token.unwrap();
+    let new_refresh_token = response.refresh_token.unwrap();
+    assert_ne!(new_refresh_token, refresh_token);
+    let request = util::build_request("GET", &format!("/users/{}", user1.username), "", &token);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+
+    // Try to reuse the old refresh token
+    let request = util::build_request(
+        "POST",
+        "/sessions/refresh",
+        &format!("{{\"refresh_token\":\"{}\"}}", refresh_token),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+
+    // Verify get 401
+    assert_eq!(response.status_code, 401);
+}
+
+#[test]
+fn test_logout() {
+    // Setup
+    let state = util::test_state();
+    let (user1, _) = util::test_users();
+    let request = util::build_request(
+        "POST",
+        "/users",
+        &format!(
+            "{{\"username\":\"{}\", \"email\":\"{}\", \"password\":\"{}\"}}",
+            user1.username, user1.email, user1.password
+        ),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    let token = response.token.unwrap();
+    let refresh_token = response.refresh_token.unwrap();
+
+    // Logout
+    let request = util::build_request(
+        "DELETE",
+        "/sessions",
+        &format!("{{\"refresh_token\":\"{}\"}}", refresh_token),
+        &token,
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+
+    // Verify access token is revoked
+    let request = util::build_request("GET", &format!("/users/{}", user1.username), "", &token);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 401);
+
+    // Verify refresh token is revoked
+    let request = util::build_request(
+        "POST",
+        "/sessions/refresh",
+        &format!("{{\"refresh_token\":\"{}\"}}", refresh_token),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 401);
+}
+
+#[test]
+fn test_admin_access() {
+    // Setup - user2 is an admin
+    let (user1, user2) = util::test_users();
+    let state = util::test_state();
+    let token1 = &util::sign_up(&state, &user1);
+    let token2 = &util::sign_up_admin(&state, &user2);
+
+    // Normal user can't make themselves an admin
+    let request = util::build_request(
+        "POST",
+        &format!("/users/{}/roles", user1.username),
+        "{\"roles\":[\"Player\",\"Admin\"]}",
+        token1,
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 403);
+
+    // Admin can view another user
+    let request = util::build_request("GET", &format!("/users/{}", user1.username), "", token2);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+    assert!(response.body.unwrap().contains(&user1.email));
+
+    // Admin can delete another user
+    let request = util::build_request("DELETE", &format!("/users/{}", user1.username), "", token2);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+    assert_eq!(state.db.get(&format!("user:{}", user1.username)), None);
+}
+
+#[test]
+fn test_grant_admin_role() {
+    // Setup - user2 is an admin
+    let (user1, user2) = util::test_users();
+    let state = util::test_state();
+    let request = util::build_request(
+        "POST",
+        "/users",
+        &format!(
+            "{{\"username\":\"{}\", \"email\":\"{}\", \"password\":\"{}\"}}",
+            user1.username, user1.email, user1.password
+        ),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    let refresh_token1 = response.refresh_token.unwrap();
+    let token2 = util::sign_up_admin(&state, &user2);
+
+    // Admin makes user1 an admin
+    let request = util::build_request(
+        "POST",
+        &format!("/users/{}/roles", user1.username),
+        "{\"roles\":[\"Player\",\"Admin\"]}",
+        &token2,
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+
+    // Role is picked up on the next refresh
+    let request = util::build_request(
+        "POST",
+        "/sessions/refresh",
+        &format!("{{\"refresh_token\":\"{}\"}}", refresh_token1),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    let token1 = response.token.unwrap();
+
+    let request = util::build_request("GET", &format!("/users/{}", user2.username), "", &token1);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+}
+
+#[test]
+fn test_admins_are_promoted_not_signed_up() {
+    // Setup - user2 is listed as an admin
+    let (user1, user2) = util::test_users();
+    let state = util::test_state_with_config(Config {
+        admins: vec![user2.username.clone()],
+        ..Config::default()
+    });
+    util::sign_up(&state, &user1);
+
+    // Signing up with a listed name doesn't make someone an admin
+    let token2 = util::sign_up(&state, &user2);
+    let request = util::build_request(
+        "POST",
+        &format!("/users/{}/roles", user1.username),
+        "{\"roles\":[\"Player\"]}",
+        &token2,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 403);
+
+    // Existing accounts are promoted on start up, only once
+    assert_eq!(
+        promote_admins(&state.config.admins, Arc::clone(&state.db)),
+        1
+    );
+    assert_eq!(
+        promote_admins(&state.config.admins, Arc::clone(&state.db)),
+        0
+    );
+    let request = util::build_request(
+        "POST",
+        "/sessions",
+        &format!(
+            "{{\"username\":\"{}\", \"password\":\"{}\"}}",
+            user2.username, user2.password
+        ),
+        "",
+    );
+    let token2 = util::parse_response(process_request(request, &state))
+        .token
+        .unwrap();
+    let request = util::build_request(
+        "POST",
+        &format!("/users/{}/roles", user1.username),
+        "{\"roles\":[\"Player\"]}",
+        &token2,
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 200);
+
+    // Listed names without an account are skipped
+    let state = util::test_state_with_config(Config {
+        admins: vec!["nobody".to_string()],
+        ..Config::default()
+    });
+    assert_eq!(
+        promote_admins(&state.config.admins, Arc::clone(&state.db)),
+        0
+    );
+}
+
+#[test]
+fn test_create_user_validation() {
+    // Setup
+    let state = util::test_state();
+
+    // Create user with every field invalid
+    let request = util::build_request(
+        "POST",
+        "/users",
+        "{\"username\":\"rrr-game:abc\", \"email\":\"not an email\", \"password\":\"pass\"}",
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+
+    // Verify each field is reported
+    assert_eq!(response.status_code, 400);
+    let body = response.body.unwrap();
+    assert!(body.contains("\"field_errors\""));
+    assert!(body.contains("\"field\":\"username\""));
+    assert!(body.contains("\"field\":\"email\""));
+    assert!(body.contains("\"field\":\"password\""));
+    assert_eq!(state.db.get("rrr-game:abc"), None);
+}
+
+#[test]
+fn test_usernames_case_insensitive() {
+    // Setup
+    let state = util::test_state();
+    let (user1, _) = util::test_users();
+    let request = util::build_request(
+        "POST",
+        "/users",
+        &format!(
+            "{{\"username\":\"{}\", \"email\":\"{}\", \"password\":\"{}\"}}",
+            user1.username, user1.email, user1.password
+        ),
+        "",
+    );
+    process_request(request, &state);
+
+    // Create the same user with different case
+    let request = util::build_request(
+        "POST",
+        "/users",
+        &format!(
+            "{{\"username\":\"{}\", \"email\":\"{}\", \"password\":\"{}\"}}",
+            user1.username.to_uppercase(),
+            user1.email,
+            user1.password
+        ),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+
+    // Verify conflict
+    assert_eq!(response.status_code, 409);
+
+    // Login with different case
+    let request = util::build_request(
+        "POST",
+        "/sessions",
+        &format!(
+            "{{\"username\":\"{}\", \"password\":\"{}\"}}",
+            user1.username.to_uppercase(),
+            user1.password
+        ),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+    let token = response.token.unwrap();
+
+    // Token works for the user
+    let request = util::build_request("GET", &format!("/users/{}", user1.username), "", &token);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+}
+
+#[test]
+fn test_migrate_user_keys() {
+    // Setup - user stored the old way, under their bare username
+    let state = util::test_state();
+    let (user1, _) = util::test_users();
+    let request = util::build_request(
+        "POST",
+        "/users",
+        &format!(
+            "{{\"username\":\"{}\", \"email\":\"{}\", \"password\":\"{}\"}}",
+            user1.username, user1.email, user1.password
+        ),
+        "",
+    );
+    process_request(request, &state);
+    let user_key = format!("user:{}", user1.username);
+    let user_entry = state.db.get(&user_key).unwrap();
+    state.db.del(&user_key);
+    state.db.set(user1.username.clone(), user_entry);
+    state
+        .db
+        .set("rrr-game:1234567:0-0".to_string(), "{}".to_string());
+
+    // Migrate
+    assert_eq!(migrate_user_keys(Arc::clone(&state.db)), 1);
+
+    // Verify user has moved and game data is untouched
+    assert_eq!(state.db.get(&user1.username), None);
+    assert_ne!(state.db.get(&user_key), None);
+    assert_eq!(state.db.get("rrr-game:1234567:0-0"), Some("{}".to_string()));
+
+    // User can still log in
+    let request = util::build_request(
+        "POST",
+        "/sessions",
+        &format!(
+            "{{\"username\":\"{}\", \"password\":\"{}\"}}",
+            user1.username, user1.password
+        ),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+
+    // Running it again does nothing
+    assert_eq!(migrate_user_keys(Arc::clone(&state.db)), 0);
+}
+
+#[test]
+fn test_update_user() {
+    // Setup
+    let state = util::test_state();
+    let (user1, _) = util::test_users();
+    let request = util::build_request(
+        "POST",
+        "/users",
+        &format!(
+            "{{\"username\":\"{}\", \"email\":\"{}\", \"password\":\"{}\"}}",
+            user1.username, user1.email, user1.password
+        ),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    let token = response.token.unwrap();
+
+    // Update email
+    let request = util::build_request(
+        "PATCH",
+        &format!("/users/{}", user1.username),
+        "{\"email\":\"new@example.com\"}",
+        &token,
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+
+    // Verify
+    assert_eq!(response.status_code, 200);
+    assert!(response.body.unwrap().contains("new@example.com"));
+
+    // Update to an invalid email
+    let request = util::build_request(
+        "PATCH",
+        &format!("/users/{}", user1.username),
+        "{\"email\":\"not an email\"}",
+        &token,
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+
+    // Verify rejected and email unchanged
+    assert_eq!(response.status_code, 400);
+    let request = util::build_request("GET", &format!("/users/{}", user1.username), "", &token);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert!(response.body.unwrap().contains("new@example.com"));
+}
+
+#[test]
+fn test_change_password() {
+    // Setup
+    let state = util::test_state();
+    let (user1, _) = util::test_users();
+    let request = util::build_request(
+        "POST",
+        "/users",
+        &format!(
+            "{{\"username\":\"{}\", \"email\":\"{}\", \"password\":\"{}\"}}",
+            user1.username, user1.email, user1.password
+        ),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    let token = response.token.unwrap();
+    let refresh_token = response.refresh_token.unwrap();
+
+    // Change password with the wrong old password
+    let request = util::build_request(
+        "POST",
+        &format!("/users/{}/password", user1.username),
+        "{\"old_password\":\"wrong_password\", \"new_password\":\"brandnewpassword\"}",
+        &token,
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 401);
+
+    // Change password
+    let request = util::build_request(
+        "POST",
+        &format!("/users/{}/password", user1.username),
+        &format!(
+            "{{\"old_password\":\"{}\", \"new_password\":\"brandnewpassword\"}}",
+            user1.password
+        ),
+        &token,
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+
+    // Verify old password no longer works, and the new one does
+    for (password, status_code) in [(user1.password.as_str(), 401), ("brandnewpassword", 200)] {
+        let request = util::build_request(
+            "POST",
+            "/sessions",
+            &format!(
+                "{{\"username\":\"{}\", \"password\":\"{}\"}}",
+                user1.username, password
+            ),
+            "",
+        );
+        let response = process_request(request, &state);
+        let response = util::parse_response(response);
+        assert_eq!(response.status_code, status_code);
+    }
+
+    // Sessions from before the change are signed out
+    let request = util::build_request(
+        "POST",
+        "/sessions/refresh",
+        &format!("{{\"refresh_token\":\"{}\"}}", refresh_token),
+        "",
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 401);
+}
+
+#[test]
+fn test_delete_user() {
+    // Setup - user in a game
+    let state = util::test_state();
+    let (user1, _) = util::test_users();
+    let request = util::build_request(
+        "POST",
+        "/users",
+        &format!(
+            "{{\"username\":\"{}\", \"email\":\"{}\", \"password\":\"{}\"}}",
+            user1.username, user1.email, user1.password
+        ),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    let token = response.token.unwrap();
+    let refresh_token = response.refresh_token.unwrap();
+    let request = util::build_request("POST", "/rrr-game", "", &token);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+    let chunk_key = state
+        .db
+        .keys("rrr-game:")
+        .into_iter()
+        .find(|key| key.ends_with(":0-0"))
+        .unwrap();
+    assert!(state.db.get(&chunk_key).unwrap().contains(&user1.username));
+    let verification_token = util::token_from_mail(&util::sent_mail(&state)[0]);
+    let request = util::build_request(
+        "POST",
+        "/password-resets",
+        &format!("{{\"email\":\"{}\"}}", user1.email),
+        "",
+    );
+    process_request(request, &state);
+    let reset_token = util::token_from_mail(util::sent_mail(&state).last().unwrap());
+
+    // Delete user
+    let request = util::build_request("DELETE", &format!("/users/{}", user1.username), "", &token);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+
+    // Verify user is gone, including from the game
+    assert_eq!(response.status_code, 200);
+    assert_eq!(state.db.get(&format!("user:{}", user1.username)), None);
+    assert!(!state.db.get(&chunk_key).unwrap().contains(&user1.username));
+
+    // Can't log in any more
+    let request = util::build_request(
+        "POST",
+        "/sessions",
+        &format!(
+            "{{\"username\":\"{}\", \"password\":\"{}\"}}",
+            user1.username, user1.password
+        ),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 401);
+
+    // Someone else signing up with the name doesn't get the old sessions
+    let new_token = util::sign_up(&state, &user1);
+    let request = util::build_request("GET", &format!("/users/{}", user1.username), "", &new_token);
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 200);
+    let request = util::build_request(
+        "POST",
+        "/sessions/refresh",
+        &format!("{{\"refresh_token\":\"{}\"}}", refresh_token),
+        "",
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 401);
+
+    // Or the old access tokens, or codes mailed to the old user
+    let request = util::build_request("GET", &format!("/users/{}", user1.username), "", &token);
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 401);
+    let request = util::build_request(
+        "POST",
+        "/password-resets/confirm",
+        &format!(
+            "{{\"token\":\"{}\", \"new_password\":\"brandnewpassword\"}}",
+            reset_token
+        ),
+        "",
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 401);
+    let request = util::build_request(
+        "POST",
+        "/email-verifications/confirm",
+        &format!("{{\"token\":\"{}\"}}", verification_token),
+        "",
+    );
+    let response = util::parse_response(process_request(request, &state));
+    assert_eq!(response.status_code, 401);
+}
+
+#[test]
+fn test_password_reset() {
+    // Setup
+    let state = util::test_state();
+    let (user1, user2) = util::test_users();
+    let request = util::build_request(
+        "POST",
+        "/users",
+        &format!(
+            "{{\"username\":\"{}\", \"email\":\"{}\", \"password\":\"{}\"}}",
+            user1.username, user1.email, user1.password
+        ),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    let refresh_token = response.refresh_token.unwrap();
+
+    // Unknown email looks the same, but nothing is sent
+    let request = util::build_request(
+        "POST",
+        "/password-resets",
+        &format!("{{\"email\":\"{}\"}}", user2.email),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+    assert!(util::sent_mail(&state)
+        .iter()
+        .all(|mail| mail.subject != "Reset your password"));
+
+    // Request a reset
+    let request = util::build_request(
+        "POST",
+        "/password-resets",
+        &format!("{{\"email\":\"{}\"}}", user1.email),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+
+    let mail = util::sent_mail(&state);
+    let reset_mail = mail.last().unwrap();
+    assert_eq!(reset_mail.subject, "Reset your password");
+    assert_eq!(reset_mail.to, user1.email);
+    let reset_token = util::token_from_mail(reset_mail);
+
+    // Wrong token
+    let request = util::build_request(
+        "POST",
+        "/password-resets/confirm",
+        "{\"token\":\"notarealtoken\", \"new_password\":\"brandnewpassword\"}",
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 401);
+
+    // New password still has to be valid, and the token isn't used up
+    let request = util::build_request(
+        "POST",
+        "/password-resets/confirm",
+        &format!(
+            "{{\"token\":\"{}\", \"new_password\":\"short\"}}",
+            reset_token
+        ),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 400);
+
+    // Reset password
+    let request = util::build_request(
+        "POST",
+        "/password-resets/confirm",
+        &format!(
+            "{{\"token\":\"{}\", \"new_password\":\"brandnewpassword\"}}",
+            reset_token
+        ),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+
+    // Token is single use
+    let request = util::build_request(
+        "POST",
+        "/password-resets/confirm",
+        &format!(
+            "{{\"token\":\"{}\", \"new_password\":\"anothernewpassword\"}}",
+            reset_token
+        ),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 401);
+
+    // Old sessions are logged out
+    let request = util::build_request(
+        "POST",
+        "/sessions/refresh",
+        &format!("{{\"refresh_token\":\"{}\"}}", refresh_token),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 401);
+
+    // Only the new password works
+    for (password, status_code) in [(user1.password.as_str(), 401), ("brandnewpassword", 200)] {
+        let request = util::build_request(
+            "POST",
+            "/sessions",
+            &format!(
+                "{{\"username\":\"{}\", \"password\":\"{}\"}}",
+                user1.username, password
+            ),
+            "",
+        );
+        let response = process_request(request, &state);
+        let response = util::parse_response(response);
+        assert_eq!(response.status_code, status_code);
+    }
+}
+
+#[test]
+fn test_email_verification() {
+    // Setup
+    let state = util::test_state_with_config(Config {
+        require_verified_email: true,
+        ..Config::default()
+    });
+    let (user1, _) = util::test_users();
+    let request = util::build_request(
+        "POST",
+        "/users",
+        &format!(
+            "{{\"username\":\"{}\", \"email\":\"{}\", \"password\":\"{}\"}}",
+            user1.username, user1.email, user1.password
+        ),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    let token = response.token.unwrap();
+
+    // New users aren't verified, and can't create games yet
+    let request = util::build_request("GET", &format!("/users/{}", user1.username), "", &token);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert!(response.body.unwrap().contains("\"verified\":false"));
+
+    let request = util::build_request("POST", "/rrr-game", "", &token);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 403);
+
+    // Verification mail was sent on sign up
+    let mail = util::sent_mail(&state);
+    assert_eq!(mail.len(), 1);
+    assert_eq!(mail[0].to, user1.email);
+    let verification_token = util::token_from_mail(&mail[0]);
+
+    // Wrong token
+    let request = util::build_request(
+        "POST",
+        "/email-verifications/confirm",
+        "{\"token\":\"notarealtoken\"}",
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 401);
+
+    // Verify
+    let request = util::build_request(
+        "POST",
+        "/email-verifications/confirm",
+        &format!("{{\"token\":\"{}\"}}", verification_token),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+
+    let request = util::build_request("POST", "/rrr-game", "", &token);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+
+    // Token is single use, and there's nothing to resend
+    let request = util::build_request(
+        "POST",
+        "/email-verifications/confirm",
+        &format!("{{\"token\":\"{}\"}}", verification_token),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 401);
+
+    let request = util::build_request(
+        "POST",
+        &format!("/users/{}/verification", user1.username),
+        "",
+        &token,
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 409);
+
+    // Changing email means verifying again
+    let request = util::build_request(
+        "PATCH",
+        &format!("/users/{}", user1.username),
+        "{\"email\":\"james@hotmail.com\"}",
+        &token,
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert!(response.body.unwrap().contains("\"verified\":false"));
+    let mail = util::sent_mail(&state);
+    assert_eq!(mail.len(), 2);
+    assert_eq!(mail[1].to, "james@hotmail.com");
+
+    // Resend, and verify with the new mail
+    let request = util::build_request(
+        "POST",
+        &format!("/users/{}/verification", user1.username),
+        "",
+        &token,
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+    let mail = util::sent_mail(&state);
+    assert_eq!(mail.len(), 3);
+
+    let request = util::build_request(
+        "POST",
+        "/email-verifications/confirm",
+        &format!("{{\"token\":\"{}\"}}", util::token_from_mail(&mail[2])),
+        "",
+    );
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert_eq!(response.status_code, 200);
+
+    let request = util::build_request("GET", &format!("/users/{}", user1.username), "", &token);
+    let response = process_request(request, &state);
+    let response = util::parse_response(response);
+    assert!(response.body.unwrap().contains("\"verified\":true"));
+}
+
+#[test]
+fn test_login_lockout() {
+    // Setup
+    let state = util::test_state();
+    let (user1, _) = util::test_users();
+    let request = util::build_request(
+        "POST",
+        "/users",
+        &format!(
+            "{{\"username\":\"{}\", \"email\":\"{}\", \"password\":\"{}\"}}",
+            user1.username, user1.email, user1.password
+        ),
+        "",
+    );
+    process_request(request, &state);
+    let login = |username: &str, password: &str, peer: &str| {
+        let request = util::build_request(
+            "POST",
+            "/sessions",
+            &format!(
+                "{{\"username\":\"{}\", \"password\":\"{}\"}}",
+                username, password
+            ),
+            "",
+        );
+        let response = process_request_from(request, peer.parse().unwrap(), &state);
+        util::parse_response(response)
+    };
+
+    // Unknown user and wrong password look the same
+    let unknown_user = login("nobody", "wrong_password", "10.0.0.1");
+    let wrong_password = login(&user1.username, "wrong_password", "10.0.0.1");
+    assert_eq!(unknown_user.status_code, 401);
+    assert_eq!(wrong_password.status_code, 401);
+    assert_eq!(unknown_user.body, wrong_password.body);
+
+    // Locked out after too many failures, even with the right password and
+    // from a different IP
+    for _ in 0..4 {
+        let response = login(&user1.username, "wrong_password", "10.0.0.1");
+        assert_eq!(response.status_code, 401);
+    }
+    let response = login(&user1.username, &user1.password, "10.0.0.2");
+    assert_eq!(response.status_code, 429);
+    assert!(response
+        .body
+        .unwrap()
+        .contains("Too many failed login attempts"));
+
+    // Other users can still log in from the same IP
+    let response = login("nobody", "wrong_password", "10.0.0.1");
+    assert_eq!(response.status_code, 401);
+}
+
+#[test]
+fn test_friends() {
+    // Setup
+    let state = util::test_state();
+    let (user1, user2) = util::test_users();
+    let token1 = util::sign_up(&state, &user1);
+    let token2 = util::sign_up(&state, &user2);
+    let friends_request = |method: &str, user: &util::User, body: &str, token: &str| {
+        let request = util::build_request(
+            method,
+            &format!("/users/{}/friends", user.username),
+            body,
+            token,
+        );
+        util::parse_response(process_request(request, &state))
+    };
+    let alex = format!("{{\"username\":\"{}\"}}", user2.username);
+    let james = format!("{{\"username\":\"{}\"}}", user1.username);
+
+    // Can't befriend yourself, or users that don't exist
+    let response = friends_request("POST", &user1, &james, &token1);
+    assert_eq!(response.status_code, 400);
+    let response = friends_request("POST", &user1, "{\"username\":\"nobody\"}", &token1);
+    assert_eq!(response.status_code, 404);
+
+    // Send a request
+    let response = friends_request("POST", &user1, &alex, &token1);
+    assert_eq!(response.status_code, 200);
+    let response = friends_request("POST", &user1, &alex, &token1);
+    assert_eq!(response.status_code, 409);
+    let response = friends_request("GET", &user2, "", &token2);
+    assert_eq!(
+        response.body.unwrap(),
+        "{\"friends\":[],\"incoming\":[\"james\"],\"outgoing\":[]}"
+    );
+
+    // Can't see someone else's friends
+    let response = friends_request("GET", &user2, "", &token1);
+    assert_eq!(response.status_code, 403);
+
+    // Accept it
+    let response = friends_request("POST", &user2, &james, &token2);
+    assert_eq!(response.status_code, 200);
+    let response = friends_request("GET", &user1, "", &token1);
+    assert_eq!(
+        response.body.unwrap(),
+        "{\"friends\":[\"alex\"],\"incoming\":[],\"outgoing\":[]}"
+    );
+
+    // Unfriend
+    let response = friends_request("DELETE", &user2, &james, &token2);
+    assert_eq!(response.status_code, 200);
+    let response = friends_request("GET", &user1, "", &token1);
+    assert_eq!(
+        response.body.unwrap(),
+        "{\"friends\":[],\"incoming\":[],\"outgoing\":[]}"
+    );
+    let response = friends_request("DELETE", &user2, &james, &token2);
+    assert_eq!(response.status_code, 404);
+}
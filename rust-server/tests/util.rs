@@ -1,5 +1,48 @@
+// Shared by all the integration tests, not every test uses every helper
+#![allow(dead_code)]
+
+use jsonwebtoken::Algorithm;
+use rand::{distributions::Alphanumeric, Rng};
 use regex::Regex;
-use std::str;
+use rust_book_server_example::{
+    promote_admins, read_outbox, Config, Database, KeyRing, LocalDatabase, Mail, OutboxMailer,
+    ServerState,
+};
+use std::{path::Path, str, sync::Arc};
+
+pub fn test_state() -> ServerState<LocalDatabase> {
+    test_state_with_config(Config::default())
+}
+
+// Each test gets its own outbox, so tests running at the same time don't see
+// each other's mail. They're left under target/ to look at after a run.
+pub fn test_state_with_config(mut config: Config) -> ServerState<LocalDatabase> {
+    let outbox_id: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(12)
+        .map(char::from)
+        .collect();
+    config.outbox_dir =
+        Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("outbox-{}", outbox_id));
+
+    let mailer = Box::new(OutboxMailer::new(&config.outbox_dir));
+    ServerState::new(
+        Arc::new(LocalDatabase::new()),
+        KeyRing::generate(Algorithm::HS512).unwrap(),
+        config,
+        mailer,
+    )
+}
+
+pub fn sent_mail(state: &ServerState<LocalDatabase>) -> Vec<Mail> {
+    read_outbox(&state.config.outbox_dir)
+}
+
+// Tokens are on a line of their own in the mail
+pub fn token_from_mail(mail: &Mail) -> String {
+    let re = Regex::new(r"\n\n(?<token>[a-zA-Z0-9]+)\n\n").unwrap();
+    re.captures(&mail.body).unwrap()["token"].to_string()
+}
 
 pub fn build_request(method: &str, url: &str, body: &str, token: &str) -> String {
     let body_length = body.len();
@@ -24,6 +67,7 @@ pub struct Response {
     pub status_code: u32,
     pub body: Option<String>,
     pub token: Option<String>,
+    pub refresh_token: Option<String>,
 }
 
 pub fn parse_response(response: String) -> Response {
@@ -45,30 +89,31 @@ pub fn parse_response(response: String) -> Response {
         .unwrap();
 
     // Body
-    let body = if let Some(body) = capture.name("body") {
-        Some(body.as_str().to_string())
-    } else {
-        None
-    };
-
-    // Token
-    let token = if let Some(ref body_present) = body {
-        // { }
-        let re = Regex::new(r#""access_token":"(?<token>.*)""#).unwrap();
-
-        let captures = re.captures_iter(&body_present);
-
-        captures
-            .last()
-            .map(|value| value.name("token").unwrap().as_str().to_string())
-    } else {
-        None
+    let body = capture.name("body").map(|body| body.as_str().to_string());
+
+    // Tokens
+    let get_token = |name: &str| {
+        if let Some(ref body_present) = body {
+            // { }
+            let re = Regex::new(&format!(r#""{name}":"(?<token>[^"]*)""#)).unwrap();
+
+            let captures = re.captures_iter(body_present);
+
+            captures
+                .last()
+                .map(|value| value.name("token").unwrap().as_str().to_string())
+        } else {
+            None
+        }
     };
+    let token = get_token("access_token");
+    let refresh_token = get_token("refresh_token");
 
     Response {
         status_code,
         body,
         token,
+        refresh_token,
     }
 }
 
@@ -93,3 +138,68 @@ pub fn test_users() -> (User, User) {
 
     (user1, user2)
 }
+
+// Creates the user, returning their access token
+pub fn sign_up(state: &ServerState<LocalDatabase>, user: &User) -> String {
+    let request = build_request(
+        "POST",
+        "/users",
+        &format!(
+            "{{\"username\":\"{}\", \"email\":\"{}\", \"password\":\"{}\"}}",
+            user.username, user.email, user.password
+        ),
+        "",
+    );
+    let response = parse_response(rust_book_server_example::process_request(request, state));
+    assert_eq!(response.status_code, 200);
+    response.token.unwrap()
+}
+
+// Creates the user and promotes them to admin the way the server does on
+// start up, returning an access token that has the admin role
+pub fn sign_up_admin(state: &ServerState<LocalDatabase>, user: &User) -> String {
+    sign_up(state, user);
+    promote_admins(std::slice::from_ref(&user.username), Arc::clone(&state.db));
+
+    let request = build_request(
+        "POST",
+        "/sessions",
+        &format!(
+            "{{\"username\":\"{}\", \"password\":\"{}\"}}",
+            user.username, user.password
+        ),
+        "",
+    );
+    let response = parse_response(rust_book_server_example::process_request(request, state));
+    assert_eq!(response.status_code, 200);
+    response.token.unwrap()
+}
+
+// For tests that insert gamestate chunks by hand, as a game also needs its meta
+pub fn insert_game_meta(state: &ServerState<LocalDatabase>, game_id: &str, players: &[&str]) {
+    let roster = players
+        .iter()
+        .map(|player| format!("\"{}\":{{\"joined_at\":0}}", player))
+        .collect::<Vec<String>>()
+        .join(",");
+    state.db.set(
+        format!("rrr-game-meta:{}", game_id),
+        format!(
+            "{{\"game_id\":\"{}\",\"creator\":\"{}\",\"created_at\":0,\
+            \"settings\":{{\"visibility\":\"public\",\"password_hash\":null,\"max_players\":8}},\
+            \"roster\":{{{}}}}}",
+            game_id, players[0], roster
+        ),
+    );
+}
+
+// Makes every tile in the game grass, so nothing gets in the way
+pub fn flatten_game(state: &ServerState<LocalDatabase>, game_id: &str) {
+    let tile = Regex::new("\"[A-Z]\"").unwrap();
+    for key in state.db.keys(&format!("rrr-game:{}:", game_id)) {
+        let chunk = state.db.get(&key).unwrap();
+        state
+            .db
+            .set(key, tile.replace_all(&chunk, "\"G\"").to_string());
+    }
+}